@@ -25,13 +25,18 @@ impl Bitmap {
         return true;
     }
 
-    pub fn remove(&mut self, key: u8) {
+    /// 返回移除前是否存在
+    pub fn remove(&mut self, key: u8) -> bool {
         if key <= 127 {
-            let b = !(1 << key);
-            self.1 &= b;
+            let b = 1 << key;
+            let had = self.1 & b == b;
+            self.1 &= !b;
+            had
         } else {
-            let b = !(1 << (key - 128));
-            self.0 &= b;
+            let b = 1 << (key - 128);
+            let had = self.0 & b == b;
+            self.0 &= !b;
+            had
         }
     }
 }
@@ -59,8 +64,9 @@ fn test() {
     assert_eq!(bm.push(168), false);
     assert_eq!(bm.push(255), false);
 
-    bm.remove(10);
-    bm.remove(168);
+    assert_eq!(bm.remove(10), true);
+    assert_eq!(bm.remove(168), true);
+    assert_eq!(bm.remove(168), false);
 
     assert_eq!(bm.push(10), true);
     assert_eq!(bm.push(168), true);
@@ -1,28 +1,53 @@
 use fltk::button::Button;
+use fltk::button::CheckButton;
 use fltk::enums::Color;
 use fltk::frame::Frame;
 use fltk::input::Input;
 use fltk::input::SecretInput;
+use fltk::browser::HoldBrowser;
+use fltk::dialog;
+use fltk::enums::Shortcut;
+use fltk::menu::Choice;
+use fltk::menu::MenuBar;
+use fltk::menu::MenuFlag;
 use fltk::prelude::InputExt;
 use fltk::window::Window;
-use std::collections::hash_map::DefaultHasher;
-use std::hash::Hasher;
-use std::io::Read;
-use std::io::Write;
-use std::net::TcpStream;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::sync::Mutex;
 use std::sync::RwLock;
+use std::time::Duration;
+use std::time::Instant;
 
 use fltk::app;
+use fltk::draw;
 use fltk::enums;
 use fltk::enums::Event;
 use fltk::image;
 use fltk::prelude::GroupExt;
 use fltk::prelude::ImageExt;
+use fltk::prelude::BrowserExt;
+use fltk::prelude::MenuExt;
 use fltk::prelude::WidgetBase;
 use fltk::prelude::WidgetExt;
+use fltk::prelude::WindowExt;
 
 use crate::bitmap;
+use crate::files::Files;
+use crate::keymap;
+use dscom::auth::Level;
+use dscom::clipboard::Clipboard;
+use dscom::keymap as hid;
+use dscom::proto;
+use dscom::proto::Codec;
+use dscom::proto::Message;
+use dsclient::headless;
+use dsclient::headless::Connection;
+use dsclient::headless::Decoder;
+use dscom::stream::Writer;
 
 pub fn app_run() {
     let app = app::App::default();
@@ -30,9 +55,9 @@ pub fn app_run() {
     // 开始绘制wind窗口
     let mut wind = Window::new(
         (sw / 2.0) as i32 - 170,
-        (sh / 2.0) as i32 - 70,
+        (sh / 2.0) as i32 - 85,
         340,
-        140,
+        170,
         "Diffscreen",
     );
     wind.set_color(Color::from_rgb(255, 255, 255));
@@ -40,6 +65,17 @@ pub fn app_run() {
     host_ipt.set_value("127.0.0.1:38971");
     let mut pwd_ipt = SecretInput::new(80, 50, 200, 25, "PASS:");
     pwd_ipt.set_value("diffscreen");
+    let tls_chk = CheckButton::new(80, 80, 80, 25, "TLS");
+    // 剪贴板同步方向, 下标对应CLIP_DIRS
+    let mut clip_choice = Choice::new(80, 110, 110, 25, "CLIP:");
+    clip_choice.add_choice("Both|To server|To client|Off");
+    clip_choice.set_value(0);
+    // 视频编码, 服务端不支持VP9时退回VP8
+    let mut codec_choice = Choice::new(80, 140, 110, 25, "CODEC:");
+    codec_choice.add_choice("VP9|VP8");
+    codec_choice.set_value(0);
+    // 色度不降采样, 文字更清晰, 仅VP9且带宽足够时服务端才启用
+    let i444_chk = CheckButton::new(200, 140, 80, 25, "4:4:4");
     let mut login_btn = Button::new(200, 80, 80, 40, "Login");
     // wind窗口结束绘制
     wind.end();
@@ -47,158 +83,384 @@ pub fn app_run() {
 
     login_btn.set_callback(move |_| {
         wind.hide();
-        draw(host_ipt.value(), pwd_ipt.value());
+        let clip = CLIP_DIRS[clip_choice.value().max(0) as usize];
+        let vp9 = if codec_choice.value() == 0 { proto::CAP_VP9 } else { 0 };
+        let i444 = if i444_chk.is_checked() { proto::CAP_I444 } else { 0 };
+        draw(host_ipt.value(), pwd_ipt.value(), tls_chk.is_checked(), clip | vp9 | i444);
     });
     app.run().unwrap();
 }
 
-enum Msg {
+pub enum Msg {
     Draw,
+    /// 文件传输进度, 显示在窗口标题
+    Progress(String),
+    /// 服务端可下载的文件
+    Files(Vec<(String, u64)>),
+    /// 光标形状或可见性变化
+    Cursor,
+    /// 光标移动
+    CursorMove,
 }
 
-// 解包
-#[inline]
-fn depack(buffer: &[u8]) -> usize {
-    ((buffer[0] as usize) << 16) | ((buffer[1] as usize) << 8) | (buffer[2] as usize)
+/**
+ * 解码后的RGB画面, 切换显示器或服务端分辨率变化后大小会变
+ */
+struct Screen {
+    rgb: Vec<u8>,
+    width: i32,
+    height: i32,
 }
 
-fn draw(host: String, pwd: String) {
-    let mut conn = TcpStream::connect(host).unwrap();
-    // 认证
-    let mut hasher = DefaultHasher::new();
-    hasher.write(pwd.as_bytes());
-    let pk = hasher.finish();
-    conn.write_all(&[
-        (pk >> (7 * 8)) as u8,
-        (pk >> (6 * 8)) as u8,
-        (pk >> (5 * 8)) as u8,
-        (pk >> (4 * 8)) as u8,
-        (pk >> (3 * 8)) as u8,
-        (pk >> (2 * 8)) as u8,
-        (pk >> (1 * 8)) as u8,
-        pk as u8,
-    ])
-    .unwrap();
-    let mut suc = [0u8];
-    conn.read_exact(&mut suc).unwrap();
-    if suc[0] != 1 {
-        if suc[0] == 2 {
-            panic!("Password error !");
-        } else {
-            panic!("Some error !");
+impl Screen {
+    fn new(width: i32, height: i32) -> Screen {
+        Screen {
+            rgb: vec![0u8; (width * height * 3) as _],
+            width,
+            height,
         }
     }
+}
+
+/**
+ * 服务端的光标, 坐标相对画面左上角, 协商CAP_CURSOR后由光标消息更新
+ */
+struct RemoteCursor {
+    // 宽, 高, 热点x, 热点y, RGBA
+    shape: Option<(i32, i32, i32, i32, Vec<u8>)>,
+    x: i32,
+    y: i32,
+    visible: bool,
+}
+
+impl RemoteCursor {
+    fn image(&self) -> Option<(image::RgbImage, i32, i32)> {
+        match &self.shape {
+            Some((w, h, hot_x, hot_y, rgba)) if self.visible && *w > 0 && *h > 0 => {
+                let image = image::RgbImage::new(rgba, *w, *h, enums::ColorDepth::Rgba8).ok()?;
+                Some((image, *hot_x, *hot_y))
+            }
+            _ => None,
+        }
+    }
+}
+
+/**
+ * 用服务端的光标形状作为本地光标, 指针随本地鼠标移动, 不必等待服务端
+ */
+fn native_cursor(wind: &mut Window, cursor: &RemoteCursor) {
+    match cursor.image() {
+        Some((image, hot_x, hot_y)) => wind.set_cursor_image(image, hot_x, hot_y),
+        None => wind.set_cursor(enums::Cursor::None),
+    }
+}
+
+/**
+ * 在画面上按服务端坐标绘制光标, 位置和大小随画面缩放
+ */
+fn overlay_cursor(frame: &Frame, cursor: &RemoteCursor, iw: i32, ih: i32) {
+    let (mut image, hot_x, hot_y) = match cursor.image() {
+        Some(c) => c,
+        None => return,
+    };
+    let (fw, fh) = (frame.width(), frame.height());
+    let x = frame.x() + (cursor.x - hot_x) * fw / iw;
+    let y = frame.y() + (cursor.y - hot_y) * fh / ih;
+    let (w, h) = ((image.width() * fw / iw).max(1), (image.height() * fh / ih).max(1));
+    image.scale(w, h, false, true);
+    draw::push_clip(frame.x(), frame.y(), fw, fh);
+    image.draw(x, y, w, h);
+    draw::pop_clip();
+}
+
+/// 登录窗口剪贴板选项对应的能力位
+const CLIP_DIRS: [u32; 4] = [
+    proto::CAP_CLIP_UP | proto::CAP_CLIP_DOWN,
+    proto::CAP_CLIP_UP,
+    proto::CAP_CLIP_DOWN,
+    0,
+];
+/// 剪贴板轮询间隔, 秒
+const CLIP_POLL: f64 = 0.5;
+/// 接收情况回报间隔
+const FEEDBACK: Duration = Duration::from_millis(500);
+/// 等待关键帧时重复请求的间隔
+const KEYFRAME_RETRY: Duration = Duration::from_secs(1);
+
+/**
+ * 服务端证书指纹记录文件
+ */
+fn known_hosts() -> PathBuf {
+    match std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE")) {
+        Some(home) => PathBuf::from(home).join(".diffscreen_known_hosts"),
+        None => PathBuf::from("diffscreen_known_hosts"),
+    }
+}
+
+/**
+ * 下载文件保存目录
+ */
+fn downloads() -> PathBuf {
+    match std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE")) {
+        Some(home) => PathBuf::from(home).join("Downloads"),
+        None => PathBuf::from("Downloads"),
+    }
+}
+
+/**
+ * 选择要下载的服务端文件
+ */
+fn pick_file(files: &[(String, u64)]) -> Option<String> {
+    if files.is_empty() {
+        dialog::message_default("No files on server");
+        return None;
+    }
+    let mut win = Window::default().with_size(320, 300).with_label("Receive file");
+    let mut browser = HoldBrowser::new(10, 10, 300, 240, None);
+    for (name, size) in files {
+        browser.add(&format!("{} ({} bytes)", name, size));
+    }
+    let mut ok_btn = Button::new(220, 260, 90, 30, "Receive");
+    win.end();
+    win.make_modal(true);
+    win.show();
+    let picked = Arc::new(Mutex::new(None));
+    let picked_cb = picked.clone();
+    let mut win_cb = win.clone();
+    ok_btn.set_callback(move |_| {
+        *picked_cb.lock().unwrap() = Some(browser.value());
+        win_cb.hide();
+    });
+    while win.shown() {
+        app::wait();
+    }
+    // HoldBrowser行号从1开始, 0为未选择
+    let line = picked.lock().unwrap().take()?;
+    files.get((line as usize).checked_sub(1)?).map(|(name, _)| name.clone())
+}
+
+/**
+ * 按住不放时的重复按下只发送一次, 媒体键不重复直接发送
+ */
+fn first_press(bmap: &mut bitmap::Bitmap, key: u32) -> bool {
+    hid::page(key) != hid::PAGE_KEYBOARD || bmap.push(hid::usage(key) as u8)
+}
+
+/**
+ * options为登录窗口选择的剪贴板方向和编码相关能力位
+ */
+fn draw(host: String, pwd: String, tls: bool, options: u32) {
+    // 启用TLS时按known_hosts校验服务端证书
+    let known_hosts = if tls { Some(known_hosts()) } else { None };
+    let optional = proto::CAP_CLIP_UP | proto::CAP_CLIP_DOWN | proto::CAP_VP9 | proto::CAP_I444;
+    let offer = (proto::CAPS & !optional) | options;
+    let Connection {
+        stream: mut conn,
+        caps,
+        level,
+        monitors,
+    } = match headless::connect(&host, &pwd, known_hosts.as_deref(), offer) {
+        Ok(c) => c,
+        Err(e) => panic!("{}", e),
+    };
+    let codec = Codec::from_caps(caps);
+    println!("Codec {}", codec.name());
+
+    let (tx, rx) = app::channel::<Msg>();
+    // 发送指令socket, 各线程共用
+    let txc = Writer::new(conn.try_clone().unwrap()).unwrap();
+    let files = if caps & proto::CAP_FILE != 0 && level >= Level::Full {
+        Some(Files::new(downloads(), txc.clone(), tx))
+    } else {
+        None
+    };
 
     // 开始绘制wind2窗口
     let (sw, sh) = app::screen_size();
+    let mut title = String::from("Diffscreen");
+    if level < Level::Full {
+        // 提示输入受限
+        title = format!("Diffscreen ({})", level.name());
+    }
     let mut wind_screen = Window::default()
         .with_size((sw / 2.0) as i32, (sh / 2.0) as i32)
-        .with_label("Diffscreen");
-    let mut frame = Frame::default().size_of(&wind_screen);
-    wind_screen.make_resizable(true);
+        .with_label(&title);
+    // 有文件传输或多个显示器时顶部显示菜单
+    let top = if files.is_some() || monitors.len() > 1 { 25 } else { 0 };
+    let mut menu = MenuBar::new(0, 0, wind_screen.width(), top, None);
+    if top == 0 {
+        menu.hide();
+    }
+    if let Some(files) = &files {
+        let push = files.clone();
+        menu.add("&File/Send file...", Shortcut::None, MenuFlag::Normal, move |_| {
+            let mut chooser = dialog::NativeFileChooser::new(dialog::NativeFileChooserType::BrowseFile);
+            chooser.show();
+            let path = chooser.filename();
+            if !path.as_os_str().is_empty() {
+                push.push(path);
+            }
+        });
+        let list = files.clone();
+        menu.add("&File/Receive file...", Shortcut::None, MenuFlag::Normal, move |_| {
+            list.list();
+        });
+    }
+    if monitors.len() > 1 {
+        // 默认查看主显示器
+        let primary = monitors.iter().position(|m| m.primary).unwrap_or(0);
+        let mut items: Vec<(String, u8)> = monitors
+            .iter()
+            .enumerate()
+            .map(|(i, m)| (format!("&Screen/Monitor {} ({}x{})", i + 1, m.width, m.height), i as u8))
+            .collect();
+        items.push((String::from("&Screen/All monitors"), proto::ALL_MONITORS));
+        for (i, (label, monitor)) in items.into_iter().enumerate() {
+            let selc = txc.clone();
+            let idx = menu.add(&label, Shortcut::None, MenuFlag::Radio, move |_| {
+                let _ = selc.send(&Message::SelectMonitor(monitor));
+            });
+            if i == primary {
+                if let Some(mut item) = menu.at(idx) {
+                    item.set();
+                }
+            }
+        }
+    }
+    let mut frame = Frame::new(0, top, wind_screen.width(), wind_screen.height() - top, None);
+    wind_screen.resizable(&frame);
     wind_screen.end();
     wind_screen.show();
 
-    // 发送指令socket
-    let mut txc = conn.try_clone().unwrap();
-    // 接收meta信息
-    let mut meta = [0u8; 4];
-    if let Err(_) = conn.read_exact(&mut meta) {
-        return;
+    // 剪贴板
+    let clip = if caps & (proto::CAP_CLIP_UP | proto::CAP_CLIP_DOWN) != 0 {
+        match Clipboard::new() {
+            Ok(c) => Some(Arc::new(Mutex::new(c))),
+            Err(e) => {
+                println!("Clipboard unavailable {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let clip_down = clip.clone().filter(|_| caps & proto::CAP_CLIP_DOWN != 0);
+    if let Some(clip) = clip.filter(|_| caps & proto::CAP_CLIP_UP != 0 && level >= Level::Full) {
+        let clipc = txc.clone();
+        app::add_timeout3(CLIP_POLL, move |handle| {
+            let changed = clip.lock().unwrap().poll();
+            if let Some((kind, data)) = changed {
+                if clipc.send(&Message::Clipboard { kind, data }).is_err() {
+                    return;
+                }
+            }
+            app::repeat_timeout3(CLIP_POLL, handle);
+        });
     }
-    let iw = (((meta[0] as u16) << 8) | meta[1] as u16) as i32;
-    let ih = (((meta[2] as u16) << 8) | meta[3] as u16) as i32;
+    // 接收meta信息, 颜色空间决定YUV转RGB的系数
+    let (iw, ih, color) = match Message::read_from(&mut conn) {
+        Ok(Message::Meta { width, height, color }) => (width as i32, height as i32, color),
+        _ => return,
+    };
+    println!("Color space {}", color.name());
 
-    let work_buf = Arc::new(RwLock::new(vec![0u8; (iw * ih * 3) as _]));
+    let work_buf = Arc::new(RwLock::new(Screen::new(iw, ih)));
     let draw_work_buf = work_buf.clone();
+    let event_work_buf = work_buf.clone();
     let mut hooked = false;
+    // 指针在画面内, 可操作鼠标时改用本地光标, 不再绘制服务端光标
+    let inside = Arc::new(AtomicBool::new(false));
+    let draw_inside = inside.clone();
+    let inside_main = inside.clone();
+    let cursor = Arc::new(Mutex::new(RemoteCursor {
+        shape: None,
+        x: 0,
+        y: 0,
+        visible: true,
+    }));
+    let draw_cursor = cursor.clone();
+    let cursor_rx = cursor.clone();
+    let keyboard = level >= Level::Full;
+    let mouse = level >= Level::Mouse;
+    let text_input = caps & proto::CAP_TEXT != 0;
     let mut bmap = bitmap::Bitmap::new();
-    let mut cmd_buf = [0u8; 5];
+    // 已解码未显示的帧数
+    let pending = Arc::new(AtomicU32::new(0));
+    let pending_rx = pending.clone();
+    let feedback = caps & proto::CAP_FEEDBACK != 0;
+    let keyframes = caps & proto::CAP_KEYFRAME != 0;
+    let txr = txc.clone();
     frame.handle(move |f, ev| {
-        let (w, h) = (iw, ih);
+        let (w, h) = match event_work_buf.read() {
+            Ok(s) => (s.width, s.height),
+            Err(_) => return false,
+        };
         match ev {
             Event::Enter => {
                 // 进入窗口
                 hooked = true;
+                inside.store(true, Ordering::Relaxed);
+                f.redraw();
             }
             Event::Leave => {
                 // 离开窗口
                 hooked = false;
+                inside.store(false, Ordering::Relaxed);
+                f.redraw();
             }
-            Event::KeyDown if hooked => {
-                // 按键按下
-                let key = app::event_key().bits() as u8;
-                cmd_buf[0] = dscom::KEY_DOWN;
-                cmd_buf[1] = key;
-                if bmap.push(key) {
-                    txc.write_all(&cmd_buf[..2]).unwrap();
+            Event::KeyDown | Event::Shortcut if hooked && keyboard => {
+                // 按键按下, 可打印的文本按文本发送, 快捷键和控制键按原始按键发送
+                let text = app::event_text();
+                let modifier =
+                    app::is_event_ctrl() || app::is_event_alt() || app::is_event_command();
+                if text_input && keymap::is_text(&text, modifier) {
+                    txc.send(&Message::Text(text)).unwrap();
+                } else if let Some(key) = keymap::fltk_to_hid(app::event_key()) {
+                    if first_press(&mut bmap, key) {
+                        txc.send(&Message::KeyDown(key)).unwrap();
+                    }
                 }
             }
-            Event::Shortcut if hooked => {
-                // 按键按下
-                let key = app::event_key().bits() as u8;
-                cmd_buf[0] = dscom::KEY_DOWN;
-                cmd_buf[1] = key;
-                if bmap.push(key) {
-                    txc.write_all(&cmd_buf[..2]).unwrap();
+            Event::KeyUp if hooked && keyboard => {
+                // 按键放开, 按文本发送过的按键服务端并未按下
+                if let Some(key) = keymap::fltk_to_hid(app::event_key()) {
+                    if hid::page(key) != hid::PAGE_KEYBOARD || bmap.remove(hid::usage(key) as u8) {
+                        txc.send(&Message::KeyUp(key)).unwrap();
+                    }
                 }
             }
-            Event::KeyUp if hooked => {
-                // 按键放开
-                let key = app::event_key().bits() as u8;
-                bmap.remove(key);
-                cmd_buf[0] = dscom::KEY_UP;
-                cmd_buf[1] = key;
-                txc.write_all(&cmd_buf[..2]).unwrap();
-            }
-            Event::Move if hooked => {
+            Event::Move if hooked && mouse => {
                 // 鼠标移动
-                let relx = (w * app::event_x() / f.width()) as u16;
-                let rely = (h * app::event_y() / f.height()) as u16;
-                // MOVE xu xd yu yd
-                cmd_buf[0] = dscom::MOVE;
-                cmd_buf[1] = (relx >> 8) as u8;
-                cmd_buf[2] = relx as u8;
-                cmd_buf[3] = (rely >> 8) as u8;
-                cmd_buf[4] = rely as u8;
-                txc.write_all(&cmd_buf).unwrap();
+                let relx = (w * (app::event_x() - f.x()) / f.width()) as u16;
+                let rely = (h * (app::event_y() - f.y()) / f.height()) as u16;
+                txc.send(&Message::Move { x: relx, y: rely }).unwrap();
             }
-            Event::Push if hooked => {
+            Event::Push if hooked && mouse => {
                 // 鼠标按下
-                cmd_buf[0] = dscom::MOUSE_KEY_DOWN;
-                cmd_buf[1] = app::event_key().bits() as u8;
-                txc.write_all(&cmd_buf[..2]).unwrap();
+                txc.send(&Message::MouseKeyDown(app::event_key().bits() as u8))
+                    .unwrap();
             }
-            Event::Released if hooked => {
+            Event::Released if hooked && mouse => {
                 // 鼠标释放
-                cmd_buf[0] = dscom::MOUSE_KEY_UP;
-                cmd_buf[1] = app::event_key().bits() as u8;
-                txc.write_all(&cmd_buf[..2]).unwrap();
+                txc.send(&Message::MouseKeyUp(app::event_key().bits() as u8))
+                    .unwrap();
             }
-            Event::Drag if hooked => {
+            Event::Drag if hooked && mouse => {
                 // 鼠标按下移动
-                let relx = (w * app::event_x() / f.width()) as u16;
-                let rely = (h * app::event_y() / f.height()) as u16;
-                // MOVE xu xd yu yd
-                cmd_buf[0] = dscom::MOVE;
-                cmd_buf[1] = (relx >> 8) as u8;
-                cmd_buf[2] = relx as u8;
-                cmd_buf[3] = (rely >> 8) as u8;
-                cmd_buf[4] = rely as u8;
-                txc.write_all(&cmd_buf).unwrap();
+                let relx = (w * (app::event_x() - f.x()) / f.width()) as u16;
+                let rely = (h * (app::event_y() - f.y()) / f.height()) as u16;
+                txc.send(&Message::Move { x: relx, y: rely }).unwrap();
             }
-            Event::MouseWheel if hooked => {
+            Event::MouseWheel if hooked && mouse => {
                 // app::MouseWheel::Down;
                 match app::event_dy() {
                     app::MouseWheel::Down => {
                         // 滚轮下滚
-                        cmd_buf[0] = dscom::MOUSE_WHEEL_DOWN;
-                        txc.write_all(&cmd_buf[..1]).unwrap();
+                        txc.send(&Message::MouseWheelDown).unwrap();
                     }
                     app::MouseWheel::Up => {
                         // 滚轮上滚
-                        cmd_buf[0] = dscom::MOUSE_WHEEL_UP;
-                        txc.write_all(&cmd_buf[..1]).unwrap();
+                        txc.send(&Message::MouseWheelUp).unwrap();
                     }
                     _ => {}
                 }
@@ -212,64 +474,168 @@ fn draw(host: String, pwd: String) {
         true
     });
     frame.draw(move |frame|{
-        if let Ok(p) = draw_work_buf.read() {
+        if let Ok(s) = draw_work_buf.read() {
+            let (p, iw, ih) = (&s.rgb, s.width, s.height);
             unsafe {
                 if let Ok(mut image) =
-                    image::RgbImage::from_data2(&p, iw as _, ih as _, enums::ColorDepth::Rgb8 as i32, 0)
+                    image::RgbImage::from_data2(p, iw as _, ih as _, enums::ColorDepth::Rgb8 as i32, 0)
                 {
                     image.scale(frame.width(), frame.height(), false, true);
                     image.draw(frame.x(), frame.y(), frame.width(), frame.height());             
                 }
             }
+            if !(mouse && draw_inside.load(Ordering::Relaxed)) {
+                if let Ok(c) = draw_cursor.lock() {
+                    overlay_cursor(frame, &c, iw, ih);
+                }
+            }
         }
     });
 
-    let (tx, rx) = app::channel::<Msg>();
-
+    let files_rx = files.clone();
     std::thread::spawn(move || {
-        let mut buf = Vec::<u8>::new();
-        let fps = 30;
-
-        let ecfg = vpx_codec::decoder::Config {
-            width: iw as _,
-            height: ih as _,
-            timebase: [1, (fps as i32) * 1000], // 120fps
-            bitrate: 8192,
-            codec: vpx_codec::decoder::VideoCodecId::VP8,
-        };
+        let decoder = |iw: i32, ih: i32| Decoder::new(codec, iw as usize, ih as usize).unwrap();
 
-        let mut dec = vpx_codec::decoder::Decoder::new(ecfg).unwrap();
+        let mut dec = decoder(iw, ih);
+        let mut color = color;
+        // 累计收到的视频字节数
+        let mut received = 0u64;
+        let mut next_feedback = Instant::now() + FEEDBACK;
+        // 解码出错后丢弃非关键帧, 直到收到关键帧
+        let mut resync = keyframes;
+        let mut next_request = Instant::now() + KEYFRAME_RETRY;
 
         loop {
-            let mut header = [0u8; 3];
-            if let Err(_) = conn.read_exact(&mut header) {
-                return;
+            let (key, buf) = match Message::read_from(&mut conn) {
+                Ok(Message::Video { key, data }) => (key, data),
+                Ok(Message::Meta { width, height, color: cs }) => {
+                    // 切换显示器或服务端分辨率变化, 重建解码器和画面
+                    let (iw, ih) = (width as i32, height as i32);
+                    dec = decoder(iw, ih);
+                    color = cs;
+                    resync = keyframes;
+                    if let Ok(mut s) = work_buf.write() {
+                        *s = Screen::new(iw, ih);
+                    }
+                    continue;
+                }
+                Ok(Message::CursorShape {
+                    width,
+                    height,
+                    hot_x,
+                    hot_y,
+                    rgba,
+                }) => {
+                    if let Ok(mut c) = cursor_rx.lock() {
+                        c.shape = Some((width as i32, height as i32, hot_x as i32, hot_y as i32, rgba));
+                    }
+                    tx.send(Msg::Cursor);
+                    continue;
+                }
+                Ok(Message::CursorPos { x, y, visible }) => {
+                    let changed = match cursor_rx.lock() {
+                        Ok(mut c) => {
+                            let changed = c.visible != visible;
+                            (c.x, c.y, c.visible) = (x, y, visible);
+                            changed
+                        }
+                        Err(_) => false,
+                    };
+                    tx.send(if changed { Msg::Cursor } else { Msg::CursorMove });
+                    continue;
+                }
+                Ok(Message::Clipboard { kind, data }) => {
+                    if let Some(clip) = &clip_down {
+                        clip.lock().unwrap().set(kind, &data);
+                    }
+                    continue;
+                }
+                Ok(
+                    msg @ (Message::FileResume { .. }
+                    | Message::FilePut { .. }
+                    | Message::FileData { .. }
+                    | Message::FileEnd { .. }
+                    | Message::FileStatus { .. }
+                    | Message::FileList(_)),
+                ) => {
+                    if let Some(files) = &files_rx {
+                        files.handle(msg);
+                    }
+                    continue;
+                }
+                Ok(_) => continue,
+                Err(e) => {
+                    println!("error {}", e);
+                    return;
+                }
+            };
+
+            received += buf.len() as u64;
+            if feedback && Instant::now() >= next_feedback {
+                next_feedback = Instant::now() + FEEDBACK;
+                let pending = pending_rx.load(Ordering::Relaxed).min(u16::MAX as u32) as u16;
+                let _ = txr.send(&Message::Feedback { received, pending });
             }
-            let recv_len = depack(&header);
-            
-            buf.resize(recv_len, 0u8);
-            if let Err(e) = conn.read_exact(&mut buf) {
-                println!("error {}", e);
-                return;
+
+            if resync && !key {
+                // 之前的请求可能因限流被忽略
+                if Instant::now() >= next_request {
+                    next_request = Instant::now() + KEYFRAME_RETRY;
+                    let _ = txr.send(&Message::KeyframeRequest);
+                }
+                continue;
             }
+            resync = false;
 
-            if let Ok(pkgs) = dec.decode(&buf) {
-                for ele in pkgs {
-                    let (y, u, v) = ele.data();
-                    if let Ok(mut p) = work_buf.write() {
-                        dscom::convert::i420_to_rgb(ele.width(), ele.height(), y, u, v, &mut p, iw as _, ih as _);
+            let pkgs = match dec.decode(&buf) {
+                Ok(pkgs) => pkgs,
+                Err(e) => {
+                    println!("decode error {}", e);
+                    if keyframes {
+                        resync = true;
+                        next_request = Instant::now() + KEYFRAME_RETRY;
+                        let _ = txr.send(&Message::KeyframeRequest);
                     }
-                    tx.send(Msg::Draw);
+                    continue;
+                }
+            };
+            for ele in pkgs {
+                if let Ok(mut s) = work_buf.write() {
+                    let (iw, ih) = (s.width as usize, s.height as usize);
+                    headless::to_rgb(&ele, &mut s.rgb, iw, ih, color);
                 }
+                pending_rx.fetch_add(1, Ordering::Relaxed);
+                tx.send(Msg::Draw);
             }
         }
     });
     while app::wait() {
         match rx.recv() {
             Some(Msg::Draw) => {
+                pending.fetch_sub(1, Ordering::Relaxed);
+                frame.redraw();
+            }
+            Some(Msg::Progress(text)) => {
+                wind_screen.set_label(&format!("{} - {}", title, text));
+            }
+            Some(Msg::Files(list)) => {
+                if let (Some(files), Some(name)) = (&files, pick_file(&list)) {
+                    files.pull(&name);
+                }
+            }
+            Some(Msg::Cursor) => {
+                if mouse {
+                    if let Ok(c) = cursor.lock() {
+                        native_cursor(&mut wind_screen, &c);
+                    }
+                }
+                frame.redraw();
+            }
+            // 使用本地光标时位置由本地鼠标决定, 不必重绘
+            Some(Msg::CursorMove) if !(mouse && inside_main.load(Ordering::Relaxed)) => {
                 frame.redraw();
             }
-            _ => {}
+            Some(Msg::CursorMove) | None => {}
         }
     }
 }
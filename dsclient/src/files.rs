@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use dscom::proto;
+use dscom::proto::Message;
+use dscom::stream::Writer;
+use dscom::transfer;
+use dscom::transfer::Incoming;
+use fltk::app;
+
+use crate::client::Msg;
+
+static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+
+/**
+ * 客户端文件传输, 菜单操作在UI线程, 服务端消息在接收线程处理
+ * 进度通过Msg::Progress显示在窗口标题
+ */
+#[derive(Clone)]
+pub struct Files {
+    // 下载目录
+    dir: PathBuf,
+    writer: Writer,
+    tx: app::Sender<Msg>,
+    // 等待服务端告知续传位置的上传
+    pushes: Arc<Mutex<HashMap<u32, PathBuf>>>,
+    incoming: Arc<Mutex<HashMap<u32, Incoming>>>,
+}
+
+impl Files {
+    pub fn new(dir: PathBuf, writer: Writer, tx: app::Sender<Msg>) -> Files {
+        Files {
+            dir,
+            writer,
+            tx,
+            pushes: Arc::new(Mutex::new(HashMap::new())),
+            incoming: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /**
+     * 上传文件到服务端目录
+     */
+    pub fn push(&self, path: PathBuf) {
+        let name = match path.file_name().and_then(|n| n.to_str()) {
+            Some(name) => name.to_string(),
+            None => return,
+        };
+        let size = match std::fs::metadata(&path) {
+            Ok(meta) => meta.len(),
+            Err(e) => {
+                self.progress(format!("{}: {}", name, e));
+                return;
+            }
+        };
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        self.pushes.lock().unwrap().insert(id, path);
+        self.progress(format!("Sending {}", name));
+        let _ = self.writer.send(&Message::FilePut { id, size, name });
+    }
+
+    /**
+     * 请求服务端文件列表, 结果以Msg::Files返回
+     */
+    pub fn list(&self) {
+        let _ = self.writer.send(&Message::FileList(Vec::new()));
+    }
+
+    /**
+     * 下载服务端文件, 本地已有.part时续传
+     */
+    pub fn pull(&self, name: &str) {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let offset = transfer::received(&self.dir, name);
+        self.progress(format!("Receiving {}", name));
+        let _ = self.writer.send(&Message::FileGet {
+            id,
+            offset,
+            name: name.to_string(),
+        });
+    }
+
+    /**
+     * 处理服务端的文件消息
+     */
+    pub fn handle(&self, msg: Message) {
+        match msg {
+            Message::FileResume { id, offset } => {
+                if let Some(path) = self.pushes.lock().unwrap().remove(&id) {
+                    let files = self.clone();
+                    std::thread::spawn(move || files.send(id, path, offset));
+                }
+            }
+            Message::FilePut { id, size, name } => match Incoming::open(&self.dir, &name, size) {
+                Ok(inc) => {
+                    self.incoming.lock().unwrap().insert(id, inc);
+                }
+                Err(e) => self.progress(format!("{}: {}", name, e)),
+            },
+            Message::FileData { id, offset, data } => {
+                let mut incoming = self.incoming.lock().unwrap();
+                if let Some(inc) = incoming.get_mut(&id) {
+                    let before = percent(inc.received, inc.size);
+                    match inc.write(offset, &data) {
+                        Ok(_) => {
+                            let now = percent(inc.received, inc.size);
+                            if now != before {
+                                self.progress(format!("Receiving {} {}%", inc.name, now));
+                            }
+                        }
+                        Err(e) => {
+                            self.progress(format!("{}: {}", inc.name, e));
+                            incoming.remove(&id);
+                        }
+                    }
+                }
+            }
+            Message::FileEnd { id, digest } => {
+                let inc = self.incoming.lock().unwrap().remove(&id);
+                if let Some(inc) = inc {
+                    let name = inc.name.clone();
+                    match inc.finish(&digest) {
+                        Ok(path) => self.progress(format!("Saved {}", path.display())),
+                        Err(e) => self.progress(format!("{}: {}", name, e)),
+                    }
+                }
+            }
+            Message::FileStatus { id, code, msg } => {
+                self.pushes.lock().unwrap().remove(&id);
+                self.incoming.lock().unwrap().remove(&id);
+                if code == proto::FILE_OK {
+                    self.progress(format!("Sent {}", msg));
+                } else {
+                    self.progress(msg);
+                }
+            }
+            Message::FileList(files) => {
+                self.tx.send(Msg::Files(files));
+            }
+            _ => {}
+        }
+    }
+
+    fn send(&self, id: u32, path: PathBuf, offset: u64) {
+        let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let mut last = None;
+        let r = transfer::send(&self.writer, id, &path, offset, |n, size| {
+            let now = percent(n, size);
+            if last != Some(now) {
+                last = Some(now);
+                self.progress(format!("Sending {} {}%", name, now));
+            }
+        });
+        if let Err(e) = r {
+            self.progress(format!("{}: {}", name, e));
+        }
+    }
+
+    fn progress(&self, text: String) {
+        self.tx.send(Msg::Progress(text));
+    }
+}
+
+fn percent(n: u64, size: u64) -> u64 {
+    (n * 100).checked_div(size).unwrap_or(100)
+}
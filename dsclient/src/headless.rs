@@ -0,0 +1,213 @@
+use std::io;
+use std::net::TcpStream;
+use std::path::Path;
+
+use dscom::auth;
+use dscom::auth::Level;
+use dscom::convert;
+use dscom::convert::ColorSpace;
+use dscom::convert::Planes;
+use dscom::proto;
+use dscom::proto::Codec;
+use dscom::proto::Message;
+use dscom::proto::Monitor;
+use dscom::stream::Stream;
+use dscom::tls;
+use dscom::tls::TlsStream;
+use vpx_codec::decoder::Image;
+use vpx_codec::decoder::Packets;
+
+/*
+无界面的客户端
+握手, 认证和视频解码不依赖FLTK, 图形界面和自动化测试共用
+*/
+
+/**
+ * 认证通过的连接
+ */
+pub struct Connection {
+    pub stream: Stream,
+    // 协商后的能力
+    pub caps: u32,
+    pub level: Level,
+    // 协商了CAP_MONITOR时为服务端的显示器
+    pub monitors: Vec<Monitor>,
+}
+
+/**
+ * 连接host并认证, known_hosts为Some时启用TLS并按其校验服务端证书
+ * offer为客户端提供的能力
+ */
+pub fn connect(host: &str, pwd: &str, known_hosts: Option<&Path>, offer: u32) -> io::Result<Connection> {
+    let tcp = TcpStream::connect(host)?;
+    let mut stream = match known_hosts {
+        Some(path) => Stream::Tls(TlsStream::connect(tcp, tls::client_config(host, path)?)?),
+        None => Stream::Tcp(tcp),
+    };
+    // 握手
+    let caps = proto::client_hello(&mut stream, offer)?;
+    // 认证
+    let error = |msg: &str| io::Error::new(io::ErrorKind::PermissionDenied, msg);
+    let nonce = match Message::read_from(&mut stream)? {
+        Message::Challenge(nonce) => nonce,
+        Message::AuthResult {
+            code: proto::AUTH_LIMITED,
+            ..
+        } => return Err(error("Too many failures, try later !")),
+        _ => return Err(error("Some error !")),
+    };
+    Message::Auth(auth::respond(pwd, &nonce)).write_to(&mut stream)?;
+    let level = match Message::read_from(&mut stream)? {
+        Message::AuthResult {
+            code: proto::AUTH_OK,
+            level,
+        } => Level::from_u8(level).unwrap_or(Level::View),
+        Message::AuthResult {
+            code: proto::AUTH_FAIL,
+            ..
+        } => return Err(error("Password error !")),
+        _ => return Err(error("Some error !")),
+    };
+    // 显示器列表
+    let monitors = if caps & proto::CAP_MONITOR != 0 {
+        match Message::read_from(&mut stream)? {
+            Message::Monitors(monitors) => monitors,
+            _ => return Err(error("Some error !")),
+        }
+    } else {
+        Vec::new()
+    };
+    Ok(Connection {
+        stream,
+        caps,
+        level,
+        monitors,
+    })
+}
+
+/**
+ * 视频解码器, 宽高来自META, 变化时重建
+ */
+pub struct Decoder {
+    dec: vpx_codec::decoder::Decoder,
+}
+
+impl Decoder {
+    pub fn new(codec: Codec, width: usize, height: usize) -> io::Result<Decoder> {
+        let fps = 30;
+        let config = vpx_codec::decoder::Config {
+            width: width as _,
+            height: height as _,
+            timebase: [1, fps * 1000],
+            bitrate: 8192,
+            codec: match codec {
+                Codec::VP8 => vpx_codec::decoder::VideoCodecId::VP8,
+                Codec::VP9 => vpx_codec::decoder::VideoCodecId::VP9,
+            },
+        };
+        let dec = vpx_codec::decoder::Decoder::new(config).map_err(io::Error::other)?;
+        Ok(Decoder { dec })
+    }
+
+    /**
+     * 解码一帧, 返回解出的画面
+     */
+    pub fn decode(&mut self, data: &[u8]) -> io::Result<Packets<'_>> {
+        self.dec.decode(data).map_err(io::Error::other)
+    }
+}
+
+/**
+ * 解出的画面按颜色空间color转为RGB, 写入width*height的rgb
+ * 服务端随码率在I420和I444间切换, 按每帧的格式转换
+ */
+pub fn to_rgb(img: &Image, rgb: &mut [u8], width: usize, height: usize, color: ColorSpace) {
+    let (y, u, v) = img.data();
+    let stride = img.stride();
+    // 解码器的行宽按对齐分配, 大于画面宽度
+    let src = Planes {
+        y,
+        u,
+        v,
+        stride: [stride[0] as usize, stride[1] as usize, stride[2] as usize],
+    };
+    let (w, h) = (img.width().min(width), img.height().min(height));
+    if img.is_i444() {
+        convert::i444_to_rgb_par(w, h, &src, rgb, width * 3, color);
+    } else {
+        convert::i420_to_rgb_par(w, h, &src, rgb, width * 3, color);
+    }
+}
+
+/**
+ * 无界面的查看端, 收到的画面解码为RGB, 用于自动化测试
+ */
+pub struct Viewer {
+    pub conn: Connection,
+    dec: Option<Decoder>,
+    color: ColorSpace,
+    pub width: usize,
+    pub height: usize,
+    pub rgb: Vec<u8>,
+    // 最近一帧是否为关键帧
+    pub key: bool,
+}
+
+impl Viewer {
+    pub fn new(conn: Connection) -> Viewer {
+        Viewer {
+            conn,
+            dec: None,
+            color: ColorSpace::default(),
+            width: 0,
+            height: 0,
+            rgb: Vec::new(),
+            key: false,
+        }
+    }
+
+    /**
+     * 读取消息直到解出一帧画面, 其他消息忽略
+     */
+    pub fn next_frame(&mut self) -> io::Result<()> {
+        let codec = Codec::from_caps(self.conn.caps);
+        loop {
+            match Message::read_from(&mut self.conn.stream)? {
+                Message::Meta { width, height, color } => {
+                    (self.width, self.height, self.color) = (width as usize, height as usize, color);
+                    self.rgb = vec![0u8; self.width * self.height * 3];
+                    self.dec = Some(Decoder::new(codec, self.width, self.height)?);
+                }
+                Message::Video { key, data } => {
+                    // META之前不会收到视频
+                    let dec = match &mut self.dec {
+                        Some(dec) => dec,
+                        None => continue,
+                    };
+                    let mut decoded = false;
+                    for img in dec.decode(&data)? {
+                        to_rgb(&img, &mut self.rgb, self.width, self.height, self.color);
+                        decoded = true;
+                    }
+                    if decoded {
+                        self.key = key;
+                        return Ok(());
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /**
+     * 最近一帧(x, y)处的RGB
+     */
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 3] {
+        let i = (y * self.width + x) * 3;
+        [self.rgb[i], self.rgb[i + 1], self.rgb[i + 2]]
+    }
+
+    pub fn send(&mut self, msg: &Message) -> io::Result<()> {
+        msg.write_to(&mut self.conn.stream)
+    }
+}
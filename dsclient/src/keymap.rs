@@ -0,0 +1,182 @@
+use dscom::keymap::*;
+use fltk::enums::Key;
+
+// fltk-rs未导出的多媒体键, 取值见 FL/Enumerations.H
+const VOLUME_DOWN: Key = Key::from_i32(0xef11);
+const VOLUME_MUTE: Key = Key::from_i32(0xef12);
+const VOLUME_UP: Key = Key::from_i32(0xef13);
+const MEDIA_PLAY_KEY: Key = Key::from_i32(0xef14);
+const MEDIA_STOP_KEY: Key = Key::from_i32(0xef15);
+const MEDIA_PREV_KEY: Key = Key::from_i32(0xef16);
+const MEDIA_NEXT_KEY: Key = Key::from_i32(0xef17);
+
+const fn ch(c: char) -> Key {
+    Key::from_char(c)
+}
+
+/// 小键盘按键为 KP + 对应ASCII
+const fn kp(c: char) -> Key {
+    Key::from_i32(Key::KP.bits() + c as i32)
+}
+
+/**
+ * FLTK按键到HID usage的转换表
+ * FLTK报告的是按键未按Shift时的符号, 按美式键盘位置对应
+ */
+static KEYS: &[(Key, u32)] = &[
+    (ch('a'), KEY_A),
+    (ch('b'), KEY_B),
+    (ch('c'), KEY_C),
+    (ch('d'), KEY_D),
+    (ch('e'), KEY_E),
+    (ch('f'), KEY_F),
+    (ch('g'), KEY_G),
+    (ch('h'), KEY_H),
+    (ch('i'), KEY_I),
+    (ch('j'), KEY_J),
+    (ch('k'), KEY_K),
+    (ch('l'), KEY_L),
+    (ch('m'), KEY_M),
+    (ch('n'), KEY_N),
+    (ch('o'), KEY_O),
+    (ch('p'), KEY_P),
+    (ch('q'), KEY_Q),
+    (ch('r'), KEY_R),
+    (ch('s'), KEY_S),
+    (ch('t'), KEY_T),
+    (ch('u'), KEY_U),
+    (ch('v'), KEY_V),
+    (ch('w'), KEY_W),
+    (ch('x'), KEY_X),
+    (ch('y'), KEY_Y),
+    (ch('z'), KEY_Z),
+    (ch('1'), KEY_1),
+    (ch('2'), KEY_2),
+    (ch('3'), KEY_3),
+    (ch('4'), KEY_4),
+    (ch('5'), KEY_5),
+    (ch('6'), KEY_6),
+    (ch('7'), KEY_7),
+    (ch('8'), KEY_8),
+    (ch('9'), KEY_9),
+    (ch('0'), KEY_0),
+    (Key::Enter, KEY_ENTER),
+    (Key::Escape, KEY_ESCAPE),
+    (Key::BackSpace, KEY_BACKSPACE),
+    (Key::Tab, KEY_TAB),
+    (ch(' '), KEY_SPACE),
+    (ch('-'), KEY_MINUS),
+    (ch('='), KEY_EQUAL),
+    (ch('['), KEY_LEFT_BRACE),
+    (ch(']'), KEY_RIGHT_BRACE),
+    (ch('\\'), KEY_BACKSLASH),
+    (ch(';'), KEY_SEMICOLON),
+    (ch('\''), KEY_APOSTROPHE),
+    (ch('`'), KEY_GRAVE),
+    (ch(','), KEY_COMMA),
+    (ch('.'), KEY_DOT),
+    (ch('/'), KEY_SLASH),
+    (Key::CapsLock, KEY_CAPS_LOCK),
+    (Key::F1, KEY_F1),
+    (Key::F2, KEY_F2),
+    (Key::F3, KEY_F3),
+    (Key::F4, KEY_F4),
+    (Key::F5, KEY_F5),
+    (Key::F6, KEY_F6),
+    (Key::F7, KEY_F7),
+    (Key::F8, KEY_F8),
+    (Key::F9, KEY_F9),
+    (Key::F10, KEY_F10),
+    (Key::F11, KEY_F11),
+    (Key::F12, KEY_F12),
+    (Key::Print, KEY_PRINT_SCREEN),
+    (Key::ScrollLock, KEY_SCROLL_LOCK),
+    (Key::Pause, KEY_PAUSE),
+    (Key::Insert, KEY_INSERT),
+    (Key::Home, KEY_HOME),
+    (Key::PageUp, KEY_PAGE_UP),
+    (Key::Delete, KEY_DELETE),
+    (Key::End, KEY_END),
+    (Key::PageDown, KEY_PAGE_DOWN),
+    (Key::Right, KEY_RIGHT),
+    (Key::Left, KEY_LEFT),
+    (Key::Down, KEY_DOWN),
+    (Key::Up, KEY_UP),
+    (Key::NumLock, KEY_NUM_LOCK),
+    (kp('/'), KEY_KP_DIVIDE),
+    (kp('*'), KEY_KP_MULTIPLY),
+    (kp('-'), KEY_KP_SUBTRACT),
+    (kp('+'), KEY_KP_ADD),
+    (Key::KPEnter, KEY_KP_ENTER),
+    (kp('1'), KEY_KP_1),
+    (kp('2'), KEY_KP_2),
+    (kp('3'), KEY_KP_3),
+    (kp('4'), KEY_KP_4),
+    (kp('5'), KEY_KP_5),
+    (kp('6'), KEY_KP_6),
+    (kp('7'), KEY_KP_7),
+    (kp('8'), KEY_KP_8),
+    (kp('9'), KEY_KP_9),
+    (kp('0'), KEY_KP_0),
+    (kp('.'), KEY_KP_DECIMAL),
+    (kp('='), KEY_KP_EQUAL),
+    (Key::IsoKey, KEY_102ND),
+    (Key::Menu, KEY_MENU),
+    (Key::Help, KEY_HELP),
+    (VOLUME_MUTE, KEY_MUTE),
+    (VOLUME_UP, KEY_VOLUME_UP),
+    (VOLUME_DOWN, KEY_VOLUME_DOWN),
+    (Key::ControlL, KEY_LEFT_CTRL),
+    (Key::ShiftL, KEY_LEFT_SHIFT),
+    (Key::AltL, KEY_LEFT_ALT),
+    (Key::MetaL, KEY_LEFT_META),
+    (Key::ControlR, KEY_RIGHT_CTRL),
+    (Key::ShiftR, KEY_RIGHT_SHIFT),
+    (Key::AltR, KEY_RIGHT_ALT),
+    (Key::MetaR, KEY_RIGHT_META),
+    (MEDIA_NEXT_KEY, MEDIA_NEXT),
+    (MEDIA_PREV_KEY, MEDIA_PREV),
+    (MEDIA_STOP_KEY, MEDIA_STOP),
+    (MEDIA_PLAY_KEY, MEDIA_PLAY_PAUSE),
+];
+
+/**
+ * FLTK按键转为协议中的按键标识, 不认识的按键返回None
+ */
+pub fn fltk_to_hid(key: Key) -> Option<u32> {
+    KEYS.iter().find(|(k, _)| *k == key).map(|(_, h)| *h)
+}
+
+/**
+ * 按键事件是否按文本发送
+ * 输入法提交和非美式布局的字符按原始按键发送会在服务端变成别的字符,
+ * 带Ctrl/Alt/Command的快捷键以及回车退格等控制字符仍按原始按键发送
+ */
+pub fn is_text(text: &str, modifier: bool) -> bool {
+    !modifier && !text.is_empty() && !text.chars().any(char::is_control)
+}
+
+#[test]
+fn test_keymap() {
+    for &h in ALL {
+        let keys: Vec<Key> = KEYS.iter().filter(|(_, x)| *x == h).map(|(k, _)| *k).collect();
+        assert_eq!(keys.len(), 1, "usage {:#x}", h);
+        assert_eq!(fltk_to_hid(keys[0]), Some(h));
+    }
+    assert_eq!(KEYS.len(), ALL.len());
+    assert_eq!(fltk_to_hid(Key::from_char('q')), Some(KEY_Q));
+    assert_eq!(fltk_to_hid(Key::from_i32(0xffb7)), Some(KEY_KP_7));
+    assert_eq!(fltk_to_hid(Key::None), None);
+}
+
+#[test]
+fn test_is_text() {
+    assert!(is_text("a", false));
+    assert!(is_text("ü", false));
+    assert!(is_text("中文", false));
+    assert!(!is_text("c", true));
+    assert!(!is_text("", false));
+    assert!(!is_text("\r", false));
+    assert!(!is_text("\x08", false));
+    assert!(!is_text("\x1b", false));
+}
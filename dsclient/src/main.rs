@@ -1,6 +1,8 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 mod bitmap;
 mod client;
+mod files;
+mod keymap;
 
 fn main() {
     client::app_run();
@@ -0,0 +1,86 @@
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::Criterion;
+use criterion::Throughput;
+
+use dscom::convert;
+use dscom::convert::ColorSpace;
+use dscom::convert::Matrix;
+use dscom::convert::Planes;
+
+// 4K画面
+const W: usize = 3840;
+const H: usize = 2160;
+// 有限范围, 解码时多一步减去Y的偏移
+const CS: ColorSpace = ColorSpace {
+    matrix: Matrix::BT709,
+    full_range: false,
+};
+
+/**
+ * 渐变加噪点, 避免各像素相同
+ */
+fn bgra() -> Vec<u8> {
+    let mut x = 1u32;
+    let mut src = Vec::with_capacity(W * H * 4);
+    for i in 0..H {
+        for j in 0..W {
+            x = x.wrapping_mul(1103515245).wrapping_add(12345);
+            src.extend_from_slice(&[(j * 255 / W) as u8, (i * 255 / H) as u8, (x >> 24) as u8, 255]);
+        }
+    }
+    src
+}
+
+fn encode(c: &mut Criterion) {
+    let src = bgra();
+    let mut dest = Vec::new();
+    let mut g = c.benchmark_group("bgra_to_i420");
+    g.sample_size(20);
+    g.throughput(Throughput::Elements((W * H) as u64));
+    g.bench_function("scalar", |b| b.iter(|| convert::scalar::bgra_to_i420(W, H, &src, W * 4, &mut dest, CS)));
+    g.bench_function("simd", |b| b.iter(|| convert::bgra_to_i420(W, H, &src, W * 4, &mut dest, CS)));
+    g.bench_function("simd_par", |b| b.iter(|| convert::bgra_to_i420_par(W, H, &src, W * 4, &mut dest, CS)));
+    g.finish();
+
+    let mut g = c.benchmark_group("bgra_to_i444");
+    g.sample_size(20);
+    g.throughput(Throughput::Elements((W * H) as u64));
+    g.bench_function("scalar", |b| b.iter(|| convert::scalar::bgra_to_i444(W, H, &src, W * 4, &mut dest, CS)));
+    g.bench_function("simd", |b| b.iter(|| convert::bgra_to_i444(W, H, &src, W * 4, &mut dest, CS)));
+    g.bench_function("simd_par", |b| b.iter(|| convert::bgra_to_i444_par(W, H, &src, W * 4, &mut dest, CS)));
+    g.finish();
+}
+
+fn decode(c: &mut Criterion) {
+    let src = bgra();
+    let mut rgb = vec![0u8; W * H * 3];
+
+    let mut yuv = Vec::new();
+    convert::bgra_to_i420(W, H, &src, W * 4, &mut yuv, CS);
+    let (y, uv) = yuv.split_at(W * H);
+    let (u, v) = uv.split_at(W * H / 4);
+    let src420 = Planes { y, u, v, stride: [W, W / 2, W / 2] };
+    let mut g = c.benchmark_group("i420_to_rgb");
+    g.sample_size(20);
+    g.throughput(Throughput::Elements((W * H) as u64));
+    g.bench_function("scalar", |b| b.iter(|| convert::scalar::i420_to_rgb(W, H, &src420, &mut rgb, W * 3, CS)));
+    g.bench_function("simd", |b| b.iter(|| convert::i420_to_rgb(W, H, &src420, &mut rgb, W * 3, CS)));
+    g.bench_function("simd_par", |b| b.iter(|| convert::i420_to_rgb_par(W, H, &src420, &mut rgb, W * 3, CS)));
+    g.finish();
+
+    convert::bgra_to_i444(W, H, &src, W * 4, &mut yuv, CS);
+    let (y, uv) = yuv.split_at(W * H);
+    let (u, v) = uv.split_at(W * H);
+    let src444 = Planes { y, u, v, stride: [W; 3] };
+    let mut g = c.benchmark_group("i444_to_rgb");
+    g.sample_size(20);
+    g.throughput(Throughput::Elements((W * H) as u64));
+    g.bench_function("scalar", |b| b.iter(|| convert::scalar::i444_to_rgb(W, H, &src444, &mut rgb, W * 3, CS)));
+    g.bench_function("simd", |b| b.iter(|| convert::i444_to_rgb(W, H, &src444, &mut rgb, W * 3, CS)));
+    g.bench_function("simd_par", |b| b.iter(|| convert::i444_to_rgb_par(W, H, &src444, &mut rgb, W * 3, CS)));
+    g.finish();
+}
+
+criterion_group!(benches, encode, decode);
+criterion_main!(benches);
@@ -0,0 +1,128 @@
+use hmac::Hmac;
+use hmac::Mac;
+use sha2::Sha256;
+
+/*
+认证流程
+client              server
+  |  <-- CHALLENGE --  |  随机nonce, 每个连接一个
+  |  ----- AUTH -----> |  HMAC-SHA256(password, DOMAIN + nonce)
+  |  <- AUTH_RESULT -- |
+*/
+
+pub const NONCE_LEN: usize = 32;
+pub const MAC_LEN: usize = 32;
+
+/**
+ * 会话权限, 认证时由所用密码决定
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// 只能观看
+    View = 1,
+    /// 可操作鼠标
+    Mouse = 2,
+    /// 鼠标键盘完全控制
+    Full = 3,
+}
+
+impl Level {
+    pub fn from_u8(v: u8) -> Option<Level> {
+        match v {
+            1 => Some(Level::View),
+            2 => Some(Level::Mouse),
+            3 => Some(Level::Full),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Level::View => "view only",
+            Level::Mouse => "mouse only",
+            Level::Full => "full control",
+        }
+    }
+}
+
+/// 区分其他用途的HMAC
+const DOMAIN: &[u8] = b"diffscreen-auth-v1";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/**
+ * 服务端签发的一次性挑战, 校验后即被消费, 旧的应答无法重放
+ */
+pub struct Challenge {
+    nonce: [u8; NONCE_LEN],
+}
+
+impl Challenge {
+    pub fn new() -> Challenge {
+        let mut nonce = [0u8; NONCE_LEN];
+        getrandom::getrandom(&mut nonce).expect("random source unavailable");
+        Challenge { nonce }
+    }
+
+    pub fn nonce(&self) -> [u8; NONCE_LEN] {
+        self.nonce
+    }
+
+    /**
+     * 常量时间比较客户端应答
+     */
+    pub fn verify(self, pwd: &str, mac: &[u8]) -> bool {
+        self.verify_any(&[pwd], mac).is_some()
+    }
+
+    /**
+     * 依次尝试多个密码, 返回匹配的下标
+     */
+    pub fn verify_any(self, pwds: &[&str], mac: &[u8]) -> Option<usize> {
+        pwds.iter()
+            .position(|pwd| keyed(pwd, &self.nonce).verify_slice(mac).is_ok())
+    }
+}
+
+impl Default for Challenge {
+    fn default() -> Self {
+        Challenge::new()
+    }
+}
+
+/**
+ * 客户端对挑战的应答
+ */
+pub fn respond(pwd: &str, nonce: &[u8; NONCE_LEN]) -> [u8; MAC_LEN] {
+    keyed(pwd, nonce).finalize().into_bytes().into()
+}
+
+fn keyed(pwd: &str, nonce: &[u8; NONCE_LEN]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(pwd.as_bytes()).expect("hmac accepts any key length");
+    mac.update(DOMAIN);
+    mac.update(nonce);
+    mac
+}
+
+#[test]
+fn test_challenge() {
+    let c = Challenge::new();
+    let mac = respond("diffscreen", &c.nonce());
+    assert!(c.verify("diffscreen", &mac));
+
+    let c = Challenge::new();
+    let mac = respond("wrong", &c.nonce());
+    assert!(!c.verify("diffscreen", &mac));
+
+    // 旧应答不能用于新挑战
+    let old = Challenge::new();
+    let mac = respond("diffscreen", &old.nonce());
+    let c = Challenge::new();
+    assert_ne!(old.nonce(), c.nonce());
+    assert!(!c.verify("diffscreen", &mac));
+    assert!(!Challenge::new().verify("diffscreen", &mac[..16]));
+
+    let c = Challenge::new();
+    let mac = respond("viewer", &c.nonce());
+    assert_eq!(c.verify_any(&["admin", "viewer"], &mac), Some(1));
+}
@@ -0,0 +1,167 @@
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+
+use crate::proto::MAX_PAYLOAD;
+
+/*
+剪贴板同步
+两端各自轮询本机剪贴板, 有变化时发送 CLIPBOARD 消息
+收到对端内容写入本机后记录摘要, 下次轮询读到同样内容不再回传
+*/
+
+// 剪贴板内容类型
+pub const CLIP_TEXT: u8 = 1;
+pub const CLIP_PNG: u8 = 2;
+
+/// 同步内容上限, 超出的既不发送也不接受
+pub const MAX_CLIP: usize = 8 << 20;
+
+/**
+ * 本机剪贴板
+ */
+pub struct Clipboard {
+    board: arboard::Clipboard,
+    // 最近一次发送或写入内容的摘要
+    last: u64,
+}
+
+impl Clipboard {
+    pub fn new() -> Result<Clipboard, arboard::Error> {
+        Ok(Clipboard {
+            board: arboard::Clipboard::new()?,
+            last: 0,
+        })
+    }
+
+    /**
+     * 本机剪贴板有新内容时返回 (类型, 数据), 图片编码为PNG
+     */
+    pub fn poll(&mut self) -> Option<(u8, Vec<u8>)> {
+        if let Ok(text) = self.board.get_text() {
+            if !self.changed(CLIP_TEXT, text.as_bytes()) || text.is_empty() {
+                return None;
+            }
+            if text.len() > MAX_CLIP {
+                println!("Skip clipboard text of {} bytes", text.len());
+                return None;
+            }
+            return Some((CLIP_TEXT, text.into_bytes()));
+        }
+        let img = self.board.get_image().ok()?;
+        if !self.changed(CLIP_PNG, &img.bytes) {
+            return None;
+        }
+        let png = encode_png(img.width, img.height, &img.bytes)?;
+        if png.len() > MAX_CLIP {
+            println!("Skip clipboard image of {} bytes", png.len());
+            return None;
+        }
+        Some((CLIP_PNG, png))
+    }
+
+    /**
+     * 写入对端发来的内容
+     */
+    pub fn set(&mut self, kind: u8, data: &[u8]) -> bool {
+        if data.len() > MAX_CLIP {
+            return false;
+        }
+        match kind {
+            CLIP_TEXT => {
+                let text = String::from_utf8_lossy(data).into_owned();
+                self.last = digest(CLIP_TEXT, text.as_bytes());
+                self.board.set_text(text).is_ok()
+            }
+            CLIP_PNG => match decode_png(data) {
+                Some((width, height, rgba)) => {
+                    self.last = digest(CLIP_PNG, &rgba);
+                    let img = arboard::ImageData {
+                        width,
+                        height,
+                        bytes: Cow::Owned(rgba),
+                    };
+                    self.board.set_image(img).is_ok()
+                }
+                None => false,
+            },
+            _ => false,
+        }
+    }
+
+    fn changed(&mut self, kind: u8, data: &[u8]) -> bool {
+        let d = digest(kind, data);
+        if d == self.last {
+            return false;
+        }
+        self.last = d;
+        true
+    }
+}
+
+fn digest(kind: u8, data: &[u8]) -> u64 {
+    let mut h = DefaultHasher::new();
+    h.write_u8(kind);
+    h.write(data);
+    h.finish()
+}
+
+/**
+ * RGBA编码为PNG
+ */
+pub fn encode_png(width: usize, height: usize, rgba: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut enc = png::Encoder::new(&mut out, width as u32, height as u32);
+    enc.set_color(png::ColorType::Rgba);
+    enc.set_depth(png::BitDepth::Eight);
+    let mut w = enc.write_header().ok()?;
+    w.write_image_data(rgba).ok()?;
+    w.finish().ok()?;
+    Some(out)
+}
+
+/**
+ * PNG解码为RGBA, 限制解码后大小防止压缩炸弹
+ */
+pub fn decode_png(data: &[u8]) -> Option<(usize, usize, Vec<u8>)> {
+    let mut dec = png::Decoder::new_with_limits(data, png::Limits { bytes: MAX_PAYLOAD });
+    dec.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = dec.read_info().ok()?;
+    let mut buf = vec![0u8; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).ok()?;
+    buf.truncate(info.buffer_size());
+    let rgba = match info.color_type {
+        png::ColorType::Rgba => buf,
+        png::ColorType::Rgb => buf.chunks(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+        png::ColorType::GrayscaleAlpha => buf.chunks(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+        png::ColorType::Grayscale => buf.iter().flat_map(|&g| [g, g, g, 255]).collect(),
+        png::ColorType::Indexed => return None,
+    };
+    Some((info.width as usize, info.height as usize, rgba))
+}
+
+#[test]
+fn test_png() {
+    let (w, h) = (7, 5);
+    let rgba: Vec<u8> = (0..w * h * 4).map(|i| (i * 13) as u8).collect();
+    let png = encode_png(w, h, &rgba).unwrap();
+    assert_eq!(decode_png(&png), Some((w, h, rgba)));
+    assert_eq!(decode_png(&png[..png.len() / 2]), None);
+    assert_eq!(decode_png(b"not a png"), None);
+
+    // 非RGBA的PNG转为RGBA
+    let mut gray = Vec::new();
+    let mut enc = png::Encoder::new(&mut gray, 2, 1);
+    enc.set_color(png::ColorType::Grayscale);
+    let mut wr = enc.write_header().unwrap();
+    wr.write_image_data(&[10, 200]).unwrap();
+    wr.finish().unwrap();
+    assert_eq!(decode_png(&gray), Some((2, 1, vec![10, 10, 10, 255, 200, 200, 200, 255])));
+}
+
+#[test]
+fn test_digest() {
+    assert_eq!(digest(CLIP_TEXT, b"abc"), digest(CLIP_TEXT, b"abc"));
+    assert_ne!(digest(CLIP_TEXT, b"abc"), digest(CLIP_PNG, b"abc"));
+    assert_ne!(digest(CLIP_TEXT, b"abc"), digest(CLIP_TEXT, b"abd"));
+}
@@ -1,77 +1,603 @@
-pub fn bgra_to_i420(width: usize, height: usize, src: &[u8], dest: &mut Vec<u8>) {
-    let stride = src.len() / height;
+/*
+颜色空间转换
+按CPU特性选择SSE2/AVX2/NEON实现, 运行时检测, 结果与scalar中的参考实现逐字节一致
+_par版本把画面按行分段在多个线程中转换, 用于4K等大画面
+*/
 
-    dest.clear();
+pub mod scalar;
+#[cfg(target_arch = "aarch64")]
+mod neon;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod x86;
+
+/// RGB转YUV系数的定点位数
+const FIX: i32 = 14;
 
-    for y in 0..height {
-        for x in 0..width {
-            let o = y * stride + 4 * x;
+/**
+ * 颜色矩阵, 标清画面习惯用BT.601, 高清用BT.709
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Matrix {
+    #[default]
+    BT601,
+    BT709,
+}
 
-            let b = src[o] as i32;
-            let g = src[o + 1] as i32;
-            let r = src[o + 2] as i32;
+/**
+ * 颜色空间, 编码和解码两端必须一致, 否则颜色会偏
+ * 有限范围Y为16-235, U V为16-240, 全范围均为0-255
+ * 默认值为BT.601有限范围, 与未协商CAP_COLOR的旧版本相同
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ColorSpace {
+    pub matrix: Matrix,
+    pub full_range: bool,
+}
+
+impl ColorSpace {
+    pub const ALL: [ColorSpace; 4] = [
+        ColorSpace { matrix: Matrix::BT601, full_range: false },
+        ColorSpace { matrix: Matrix::BT601, full_range: true },
+        ColorSpace { matrix: Matrix::BT709, full_range: false },
+        ColorSpace { matrix: Matrix::BT709, full_range: true },
+    ];
+
+    /**
+     * 在META中的编码, bit0为BT.709, bit1为全范围
+     */
+    pub fn to_byte(self) -> u8 {
+        (self.matrix == Matrix::BT709) as u8 | (self.full_range as u8) << 1
+    }
 
-            let y = (66 * r + 129 * g + 25 * b + 128) / 256 + 16;
-            dest.push(clamp(y));
+    pub fn from_byte(b: u8) -> ColorSpace {
+        ColorSpace {
+            matrix: if b & 1 != 0 { Matrix::BT709 } else { Matrix::BT601 },
+            full_range: b & 2 != 0,
         }
     }
 
-    for y in (0..height).step_by(2) {
-        for x in (0..width).step_by(2) {
-            let o = y * stride + 4 * x;
+    pub fn name(self) -> &'static str {
+        match (self.matrix, self.full_range) {
+            (Matrix::BT601, false) => "BT.601",
+            (Matrix::BT601, true) => "BT.601 full",
+            (Matrix::BT709, false) => "BT.709",
+            (Matrix::BT709, true) => "BT.709 full",
+        }
+    }
+}
 
-            let b = src[o] as i32;
-            let g = src[o + 1] as i32;
-            let r = src[o + 2] as i32;
+/**
+ * 由颜色空间算出的定点系数, 编码和解码使用同一组Kr Kb, 互为逆变换
+ */
+#[derive(Clone, Copy, Debug)]
+struct Coef {
+    // RGB转YUV, 按B G R顺序, FIX位小数
+    y: [i16; 3],
+    u: [i16; 3],
+    v: [i16; 3],
+    // Y的偏移, 有限范围为16
+    y_off: i32,
+    // YUV转RGB, 12位小数, Y减去偏移后乘cy
+    cy: i16,
+    rv: i16,
+    gu: i16,
+    gv: i16,
+    bu: i16,
+}
 
-            let u = (-38 * r - 74 * g + 112 * b + 128) / 256 + 128;
-            dest.push(clamp(u));
+impl Coef {
+    fn new(cs: ColorSpace) -> Coef {
+        let (kr, kb) = match cs.matrix {
+            Matrix::BT601 => (0.299, 0.114),
+            Matrix::BT709 => (0.2126, 0.0722),
+        };
+        let kg = 1.0 - kr - kb;
+        // 有限范围把0-255压缩到219级亮度和224级色度
+        let (ys, cs, y_off) = if cs.full_range { (1.0, 1.0, 0) } else { (219.0 / 255.0, 224.0 / 255.0, 16) };
+        let fix = |x: f64, bits: i32| (x * (1 << bits) as f64).round() as i16;
+        // 系数取整后调整G, 使白色的Y和灰色的U V没有误差
+        let (yr, yb) = (fix(ys * kr, FIX), fix(ys * kb, FIX));
+        let yg = fix(ys, FIX) - yr - yb;
+        let (ur, ub) = (fix(-cs * kr / (2.0 * (1.0 - kb)), FIX), fix(cs / 2.0, FIX));
+        let (vr, vb) = (fix(cs / 2.0, FIX), fix(-cs * kb / (2.0 * (1.0 - kr)), FIX));
+        Coef {
+            y: [yb, yg, yr],
+            u: [ub, -ub - ur, ur],
+            v: [vb, -vb - vr, vr],
+            y_off,
+            cy: fix(1.0 / ys, 12),
+            rv: fix(2.0 * (1.0 - kr) / cs, 12),
+            gu: fix(2.0 * kb * (1.0 - kb) / (kg * cs), 12),
+            gv: fix(2.0 * kr * (1.0 - kr) / (kg * cs), 12),
+            bu: fix(2.0 * (1.0 - kb) / cs, 12),
         }
     }
+}
 
-    for y in (0..height).step_by(2) {
-        for x in (0..width).step_by(2) {
-            let o = y * stride + 4 * x;
+/// 每段至少转换的行数, 太小时线程开销超过转换本身
+const MIN_BAND: usize = 64;
 
-            let b = src[o] as i32;
-            let g = src[o + 1] as i32;
-            let r = src[o + 2] as i32;
+// 一行YUV转RGB, 参数为系数, Y U V, 每step个像素共用一个色度, 输出
+type RgbRow = fn(&Coef, &[u8], &[u8], &[u8], usize, &mut [u8]);
 
-            let v = (112 * r - 94 * g - 18 * b + 128) / 256 + 128;
-            dest.push(clamp(v));
+/**
+ * 单行转换函数, 各指令集实现一组
+ */
+#[derive(Clone, Copy)]
+struct Kernels {
+    // 一行BGRA转Y
+    y: fn(&Coef, &[u8], &mut [u8]),
+    // 一行BGRA转U和V, 色度隔step个像素取样
+    uv: fn(&Coef, &[u8], usize, &mut [u8], &mut [u8]),
+    rgb: RgbRow,
+}
+
+const SCALAR: Kernels = Kernels {
+    y: scalar::y_row,
+    uv: scalar::uv_row,
+    rgb: scalar::rgb_row,
+};
+
+/**
+ * 当前CPU支持的最快实现
+ */
+fn kernels() -> Kernels {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if is_x86_feature_detected!("avx2") {
+            return x86::AVX2;
+        }
+        if is_x86_feature_detected!("sse2") {
+            return x86::SSE2;
         }
     }
+    #[cfg(target_arch = "aarch64")]
+    {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            return neon::NEON;
+        }
+    }
+    SCALAR
+}
+
+fn threads() -> usize {
+    std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
 }
 
 fn clamp(x: i32) -> u8 {
-    x.min(255).max(0) as u8
+    x.clamp(0, 255) as u8
+}
+
+/**
+ * 一帧YUV的三个平面, stride为各平面每行的字节数, 解码器按对齐分配时大于画面宽度
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct Planes<'a> {
+    pub y: &'a [u8],
+    pub u: &'a [u8],
+    pub v: &'a [u8],
+    pub stride: [usize; 3],
+}
+
+/**
+ * stride为src每行的字节数, 不小于width * 4, 输出的各平面紧密排列
+ */
+pub fn bgra_to_i420(width: usize, height: usize, src: &[u8], stride: usize, dest: &mut Vec<u8>, cs: ColorSpace) {
+    bgra_to_yuv(kernels(), &Coef::new(cs), 1, 2, (width, height), (src, stride), dest);
+}
+
+/**
+ * 多线程版本
+ */
+pub fn bgra_to_i420_par(width: usize, height: usize, src: &[u8], stride: usize, dest: &mut Vec<u8>, cs: ColorSpace) {
+    bgra_to_yuv(kernels(), &Coef::new(cs), threads(), 2, (width, height), (src, stride), dest);
+}
+
+/**
+ * 不降采样色度, 每个像素都有自己的U和V, 彩色文字和细线不会模糊
+ */
+pub fn bgra_to_i444(width: usize, height: usize, src: &[u8], stride: usize, dest: &mut Vec<u8>, cs: ColorSpace) {
+    bgra_to_yuv(kernels(), &Coef::new(cs), 1, 1, (width, height), (src, stride), dest);
+}
+
+/**
+ * 多线程版本
+ */
+pub fn bgra_to_i444_par(width: usize, height: usize, src: &[u8], stride: usize, dest: &mut Vec<u8>, cs: ColorSpace) {
+    bgra_to_yuv(kernels(), &Coef::new(cs), threads(), 1, (width, height), (src, stride), dest);
+}
+
+/**
+ * 转换左上角width*height的部分, dest每行stride字节, 不小于width * 3
+ */
+pub fn i420_to_rgb(width: usize, height: usize, src: &Planes, dest: &mut [u8], stride: usize, cs: ColorSpace) {
+    yuv_to_rgb(kernels(), &Coef::new(cs), 1, 2, (width, height), src, (dest, stride));
+}
+
+/**
+ * 多线程版本
+ */
+pub fn i420_to_rgb_par(width: usize, height: usize, src: &Planes, dest: &mut [u8], stride: usize, cs: ColorSpace) {
+    yuv_to_rgb(kernels(), &Coef::new(cs), threads(), 2, (width, height), src, (dest, stride));
+}
+
+/**
+ * I444转RGB, 参数同i420_to_rgb
+ */
+pub fn i444_to_rgb(width: usize, height: usize, src: &Planes, dest: &mut [u8], stride: usize, cs: ColorSpace) {
+    yuv_to_rgb(kernels(), &Coef::new(cs), 1, 1, (width, height), src, (dest, stride));
+}
+
+/**
+ * 多线程版本
+ */
+pub fn i444_to_rgb_par(width: usize, height: usize, src: &Planes, dest: &mut [u8], stride: usize, cs: ColorSpace) {
+    yuv_to_rgb(kernels(), &Coef::new(cs), threads(), 1, (width, height), src, (dest, stride));
+}
+
+/**
+ * 每行stride字节共height行的缓冲区至少需要的长度, 最后一行不要求填充
+ */
+fn plane_len(stride: usize, row: usize, height: usize) -> usize {
+    if height == 0 {
+        0
+    } else {
+        stride * (height - 1) + row
+    }
+}
+
+/**
+ * 每段的行数, 为sub的整数倍, 使色度行不跨段
+ */
+fn band_rows(height: usize, sub: usize, threads: usize) -> usize {
+    let units = height.div_ceil(sub);
+    let n = threads.min(units.div_ceil(MIN_BAND / sub)).max(1);
+    units.div_ceil(n).max(1) * sub
+}
+
+/**
+ * 第一段在当前线程执行, 其余各开一个线程
+ */
+fn parallel<T: Send, F: Fn(T) + Sync>(mut jobs: impl Iterator<Item = T>, f: F) {
+    std::thread::scope(|s| {
+        let first = jobs.next();
+        for job in jobs {
+            let f = &f;
+            s.spawn(move || f(job));
+        }
+        if let Some(job) = first {
+            f(job);
+        }
+    });
+}
+
+/**
+ * BGRA转YUV平面, sub为色度降采样倍数, 2为I420, 1为I444
+ */
+fn bgra_to_yuv(k: Kernels, c: &Coef, threads: usize, sub: usize, (width, height): (usize, usize), (src, stride): (&[u8], usize), dest: &mut Vec<u8>) {
+    let cw = width.div_ceil(sub);
+    let ch = height.div_ceil(sub);
+    dest.clear();
+    dest.resize(width * height + 2 * cw * ch, 0);
+    if width == 0 || height == 0 {
+        return;
+    }
+    assert!(stride >= width * 4, "stride {} < width {} * 4", stride, width);
+    assert!(src.len() >= plane_len(stride, width * 4, height), "bgra {} bytes too short", src.len());
+    let (dy, duv) = dest.split_at_mut(width * height);
+    let (du, dv) = duv.split_at_mut(cw * ch);
+
+    let rows = band_rows(height, sub, threads);
+    let bands = src
+        .chunks(rows * stride)
+        .zip(dy.chunks_mut(rows * width))
+        .zip(du.chunks_mut(rows / sub * cw))
+        .zip(dv.chunks_mut(rows / sub * cw));
+    parallel(bands, |(((src, dy), du), dv)| {
+        for r in 0..dy.len() / width {
+            let row = &src[r * stride..r * stride + width * 4];
+            (k.y)(c, row, &mut dy[r * width..(r + 1) * width]);
+            if r % sub == 0 {
+                let o = r / sub * cw;
+                (k.uv)(c, row, sub, &mut du[o..o + cw], &mut dv[o..o + cw]);
+            }
+        }
+    });
+}
+
+/**
+ * YUV平面转RGB, sub为色度降采样倍数
+ */
+fn yuv_to_rgb(k: Kernels, c: &Coef, threads: usize, sub: usize, (width, height): (usize, usize), src: &Planes, (dest, stride): (&mut [u8], usize)) {
+    if width == 0 || height == 0 {
+        return;
+    }
+    let (cw, ch) = (width.div_ceil(sub), height.div_ceil(sub));
+    let [ys, us, vs] = src.stride;
+    assert!(ys >= width && us >= cw && vs >= cw, "plane stride {:?} < width {}", src.stride, width);
+    assert!(src.y.len() >= plane_len(ys, width, height), "y plane {} bytes too short", src.y.len());
+    assert!(src.u.len() >= plane_len(us, cw, ch), "u plane {} bytes too short", src.u.len());
+    assert!(src.v.len() >= plane_len(vs, cw, ch), "v plane {} bytes too short", src.v.len());
+    assert!(stride >= width * 3, "stride {} < width {} * 3", stride, width);
+    let len = plane_len(stride, width * 3, height);
+    assert!(dest.len() >= len, "rgb {} bytes too short", dest.len());
+
+    let rows = band_rows(height, sub, threads);
+    let bands = dest[..len].chunks_mut(rows * stride).enumerate();
+    parallel(bands, |(b, dest)| {
+        for r in 0..dest.len().div_ceil(stride) {
+            let i = b * rows + r;
+            let (y, o) = (i * ys, i / sub);
+            let (u, v) = (&src.u[o * us..o * us + cw], &src.v[o * vs..o * vs + cw]);
+            (k.rgb)(c, &src.y[y..y + width], u, v, sub, &mut dest[r * stride..r * stride + width * 3]);
+        }
+    });
+}
+
+#[cfg(test)]
+fn noise(len: usize, seed: u32) -> Vec<u8> {
+    let mut x = seed;
+    (0..len)
+        .map(|_| {
+            x = x.wrapping_mul(1103515245).wrapping_add(12345);
+            (x >> 16) as u8
+        })
+        .collect()
+}
+
+/**
+ * 当前CPU上可用的全部实现, 测试时逐一与参考实现比较
+ */
+#[cfg(test)]
+fn available() -> Vec<(&'static str, Kernels)> {
+    #[allow(unused_mut)]
+    let mut all = vec![("scalar", SCALAR)];
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if is_x86_feature_detected!("sse2") {
+            all.push(("sse2", x86::SSE2));
+        }
+        if is_x86_feature_detected!("avx2") {
+            all.push(("avx2", x86::AVX2));
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            all.push(("neon", neon::NEON));
+        }
+    }
+    all
+}
+
+/**
+ * 紧密排列的YUV各平面
+ */
+#[cfg(test)]
+fn packed(yuv: &[u8], width: usize, height: usize, sub: usize) -> Planes<'_> {
+    let cw = width.div_ceil(sub);
+    let (y, uv) = yuv.split_at(width * height);
+    let (u, v) = uv.split_at(cw * height.div_ceil(sub));
+    Planes { y, u, v, stride: [width, cw, cw] }
+}
+
+#[test]
+fn test_simd_encode() {
+    // 奇数宽高, 行尾对齐填充, 以及不足一组SIMD宽度的尾部
+    for (width, height, pad) in [(1, 1, 0), (2, 2, 8), (33, 7, 4), (64, 16, 0), (130, 9, 12), (257, 130, 0)] {
+        let stride = width * 4 + pad;
+        // 最后一行没有填充
+        let src = noise(plane_len(stride, width * 4, height), (width * height) as u32);
+        for cs in ColorSpace::ALL {
+            let c = Coef::new(cs);
+            let mut i420 = Vec::new();
+            let mut i444 = Vec::new();
+            scalar::bgra_to_i420(width, height, &src, stride, &mut i420, cs);
+            scalar::bgra_to_i444(width, height, &src, stride, &mut i444, cs);
+            for (name, k) in available() {
+                for threads in [1, 3] {
+                    let mut dest = Vec::new();
+                    bgra_to_yuv(k, &c, threads, 2, (width, height), (&src, stride), &mut dest);
+                    assert!(dest == i420, "{} {} i420 {}x{} threads {}", name, cs.name(), width, height, threads);
+                    bgra_to_yuv(k, &c, threads, 1, (width, height), (&src, stride), &mut dest);
+                    assert!(dest == i444, "{} {} i444 {}x{} threads {}", name, cs.name(), width, height, threads);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_simd_decode() {
+    // 各平面和输出的行宽都可大于画面宽度
+    for (width, height, pad, rgb_pad) in [(1usize, 1usize, [0, 0, 0], 0), (1, 2, [1, 0, 3], 2), (33, 7, [15, 7, 3], 0), (64, 16, [0, 0, 0], 0), (257, 129, [31, 16, 48], 9)] {
+        let rgb_stride = width * 3 + rgb_pad;
+        for (sub, seed) in [(2, 1), (1, 10)] {
+            let (cw, ch) = (width.div_ceil(sub), height.div_ceil(sub));
+            let stride = [width + pad[0], cw + pad[1], cw + pad[2]];
+            let y = noise(plane_len(stride[0], width, height), seed);
+            let u = noise(plane_len(stride[1], cw, ch), seed + 1);
+            let v = noise(plane_len(stride[2], cw, ch), seed + 2);
+            let src = Planes { y: &y, u: &u, v: &v, stride };
+            for cs in ColorSpace::ALL {
+                let c = Coef::new(cs);
+                // 行尾的填充保持不变
+                let mut expect = vec![7; rgb_stride * height];
+                if sub == 2 {
+                    scalar::i420_to_rgb(width, height, &src, &mut expect, rgb_stride, cs);
+                } else {
+                    scalar::i444_to_rgb(width, height, &src, &mut expect, rgb_stride, cs);
+                }
+                for (name, k) in available() {
+                    for threads in [1, 3] {
+                        let mut dest = vec![7; rgb_stride * height];
+                        yuv_to_rgb(k, &c, threads, sub, (width, height), &src, (&mut dest, rgb_stride));
+                        assert!(dest == expect, "{} {} sub {} {}x{} threads {}", name, cs.name(), sub, width, height, threads);
+                    }
+                }
+            }
+        }
+    }
 }
 
-#[allow(dead_code)]
-pub fn i420_to_rgb(width: usize, height: usize, sy: &[u8], su: &[u8], sv: &[u8], dest: &mut [u8], crop_width: usize, crop_height: usize) {
-    // 确保裁剪尺寸不超过原始尺寸
-    let crop_width = crop_width.min(width);
-    let crop_height = crop_height.min(height);
-    let uvw = width >> 1;
-    for i in 0..crop_height {
-        let sw = i * width;
-        let swc = i * crop_width;
-        let t = (i >> 1) * uvw;
-        for j in 0..crop_width {
-            let rgbstart = sw + j;
-            let mut rgbstartc = swc + j;
-            let uvi = t + (j >> 1);
+/**
+ * 带填充的画面与紧密排列的画面转换结果相同, 填充内容不影响输出
+ */
+#[test]
+fn test_stride() {
+    let (width, height) = (45, 11);
+    let cs = ColorSpace::default();
+    let bgra = noise(width * height * 4, 5);
+    // 每行填充28字节, 内容为噪点
+    let stride = width * 4 + 28;
+    let mut padded = noise(stride * height, 6);
+    for r in 0..height {
+        padded[r * stride..r * stride + width * 4].copy_from_slice(&bgra[r * width * 4..(r + 1) * width * 4]);
+    }
+    for sub in [2, 1] {
+        let (mut a, mut b) = (Vec::new(), Vec::new());
+        if sub == 2 {
+            bgra_to_i420_par(width, height, &bgra, width * 4, &mut a, cs);
+            bgra_to_i420_par(width, height, &padded, stride, &mut b, cs);
+        } else {
+            bgra_to_i444_par(width, height, &bgra, width * 4, &mut a, cs);
+            bgra_to_i444_par(width, height, &padded, stride, &mut b, cs);
+        }
+        assert!(a == b, "sub {}", sub);
 
-            let y = sy[rgbstart] as i32;
-            let u = su[uvi] as i32 - 128;
-            let v = sv[uvi] as i32 - 128;
+        // 解码器按64字节对齐行宽, 平面之间也有间隔
+        let src = packed(&a, width, height, sub);
+        let (cw, ch) = (width.div_ceil(sub), height.div_ceil(sub));
+        let pad = |p: &[u8], w: usize, h: usize, seed: u32| {
+            let mut buf = noise(64 * h + 100, seed);
+            for r in 0..h {
+                buf[r * 64..r * 64 + w].copy_from_slice(&p[r * w..(r + 1) * w]);
+            }
+            buf
+        };
+        let (y, u, v) = (pad(src.y, width, height, 7), pad(src.u, cw, ch, 8), pad(src.v, cw, ch, 9));
+        let aligned = Planes { y: &y, u: &u, v: &v, stride: [64; 3] };
 
-            rgbstartc *= 3;
-            dest[rgbstartc] = clamp(y + (v * 359 >> 8));
-            dest[rgbstartc + 1] = clamp(y - (u * 88 >> 8) - (v * 182 >> 8));
-            dest[rgbstartc + 2] = clamp(y + (u * 453 >> 8));
+        let mut rgb = vec![0; width * height * 3];
+        let rgb_stride = width * 3 + 5;
+        let mut rgb_padded = vec![0xaa; rgb_stride * height];
+        if sub == 2 {
+            i420_to_rgb_par(width, height, &src, &mut rgb, width * 3, cs);
+            i420_to_rgb_par(width, height, &aligned, &mut rgb_padded, rgb_stride, cs);
+        } else {
+            i444_to_rgb_par(width, height, &src, &mut rgb, width * 3, cs);
+            i444_to_rgb_par(width, height, &aligned, &mut rgb_padded, rgb_stride, cs);
+        }
+        for r in 0..height {
+            let row = &rgb_padded[r * rgb_stride..(r + 1) * rgb_stride];
+            assert!(row[..width * 3] == rgb[r * width * 3..(r + 1) * width * 3], "sub {} row {}", sub, r);
+            assert!(row[width * 3..].iter().all(|b| *b == 0xaa), "sub {} row {} padding", sub, r);
+        }
+    }
+}
+
+#[test]
+fn test_i444() {
+    // 2x2, 每个像素颜色不同, 源画面每行有4字节对齐填充
+    let pixels = [[200, 30, 30], [30, 200, 30], [30, 30, 200], [128, 128, 128]];
+    let mut bgra = vec![0u8; 2 * (2 * 4 + 4)];
+    for (i, [r, g, b]) in pixels.iter().enumerate() {
+        let o = (i / 2) * 12 + (i % 2) * 4;
+        bgra[o..o + 4].copy_from_slice(&[*b, *g, *r, 255]);
+    }
+    let mut yuv = Vec::new();
+    bgra_to_i444(2, 2, &bgra, 12, &mut yuv, ColorSpace::default());
+    assert_eq!(yuv.len(), 12);
 
+    let mut rgb = vec![0u8; 2 * 2 * 3];
+    i444_to_rgb(2, 2, &packed(&yuv, 2, 2, 1), &mut rgb, 6, ColorSpace::default());
+    // 色度不降采样, 相邻像素的颜色互不影响, 误差只来自定点系数
+    for (i, p) in pixels.iter().enumerate() {
+        for c in 0..3 {
+            let d = rgb[i * 3 + c] as i32 - p[c] as i32;
+            assert!(d.abs() <= 16, "pixel {} channel {}: {} vs {}", i, c, rgb[i * 3 + c], p[c]);
         }
     }
 
-}
\ No newline at end of file
+    // 同样的画面经I420后四个像素共用一组色度, 绿色像素变成了红色
+    bgra_to_i420(2, 2, &bgra, 12, &mut yuv, ColorSpace::default());
+    i420_to_rgb(2, 2, &packed(&yuv, 2, 2, 2), &mut rgb, 6, ColorSpace::default());
+    assert!(rgb[3] > 200 && rgb[4] < 100);
+}
+
+/**
+ * 每个颜色空间RGB经YUV转回RGB的最大误差
+ * 有限范围的亮度只有219级, 色度224级, 误差比全范围大
+ */
+#[test]
+fn test_round_trip() {
+    // 各通道0到255每隔15取一个值, 包括纯黑和纯白
+    let levels: Vec<u8> = (0..=255).step_by(15).collect();
+    let mut bgra = Vec::new();
+    for &r in &levels {
+        for &g in &levels {
+            for &b in &levels {
+                bgra.extend_from_slice(&[b, g, r, 255]);
+            }
+        }
+    }
+    let width = levels.len();
+    let height = bgra.len() / 4 / width;
+    for (cs, bound) in ColorSpace::ALL.into_iter().zip([2, 1, 2, 1]) {
+        let mut yuv = Vec::new();
+        bgra_to_i444(width, height, &bgra, width * 4, &mut yuv, cs);
+        let mut rgb = vec![0; width * height * 3];
+        i444_to_rgb(width, height, &packed(&yuv, width, height, 1), &mut rgb, width * 3, cs);
+        let mut max = 0;
+        for (p, q) in bgra.chunks_exact(4).zip(rgb.chunks_exact(3)) {
+            for (a, b) in [p[2], p[1], p[0]].iter().zip(q) {
+                max = max.max((*a as i32 - *b as i32).abs());
+            }
+        }
+        assert!(max <= bound, "{} max error {}", cs.name(), max);
+
+        // I420每2x2共用色度, 每个颜色放大为2x2的色块后误差相同
+        let wide: Vec<u8> = bgra.chunks_exact(4).flat_map(|p| [p, p].concat()).collect();
+        let big: Vec<u8> = wide.chunks_exact(width * 8).flat_map(|r| [r, r].concat()).collect();
+        bgra_to_i420(width * 2, height * 2, &big, width * 8, &mut yuv, cs);
+        let mut rgb = vec![0; width * height * 12];
+        i420_to_rgb(width * 2, height * 2, &packed(&yuv, width * 2, height * 2, 2), &mut rgb, width * 6, cs);
+        for (p, q) in big.chunks_exact(4).zip(rgb.chunks_exact(3)) {
+            for (a, b) in [p[2], p[1], p[0]].iter().zip(q) {
+                assert!((*a as i32 - *b as i32).abs() <= bound, "{} i420 {:?} {:?}", cs.name(), p, q);
+            }
+        }
+
+        // 黑白灰不能带颜色, 全范围时无损
+        for gray in [0u8, 1, 16, 128, 235, 254, 255] {
+            let px = [gray, gray, gray, 255];
+            bgra_to_i444(1, 1, &px, 4, &mut yuv, cs);
+            assert_eq!((yuv[1], yuv[2]), (128, 128), "{} gray {}", cs.name(), gray);
+            let mut rgb = [0; 3];
+            i444_to_rgb(1, 1, &packed(&yuv, 1, 1, 1), &mut rgb, 3, cs);
+            assert!(rgb[0] == rgb[1] && rgb[1] == rgb[2], "{} gray {}: {:?}", cs.name(), gray, rgb);
+            if cs.full_range {
+                assert_eq!(rgb[0], gray, "{}", cs.name());
+            } else {
+                assert!((rgb[0] as i32 - gray as i32).abs() <= 1, "{} gray {}: {}", cs.name(), gray, rgb[0]);
+            }
+        }
+    }
+
+    // 用BT.601解BT.709的画面, 饱和色明显偏色, 所以颜色空间必须随流传给客户端
+    let red = [0, 0, 255, 255];
+    let mut yuv = Vec::new();
+    bgra_to_i444(1, 1, &red, 4, &mut yuv, ColorSpace::ALL[3]);
+    let mut rgb = [0; 3];
+    i444_to_rgb(1, 1, &packed(&yuv, 1, 1, 1), &mut rgb, 3, ColorSpace::ALL[1]);
+    let err = (255 - rgb[0] as i32) + rgb[1] as i32 + rgb[2] as i32;
+    assert!(err > 16, "{:?}", rgb);
+}
+
+#[test]
+fn test_color_byte() {
+    for cs in ColorSpace::ALL {
+        assert_eq!(ColorSpace::from_byte(cs.to_byte()), cs);
+    }
+    assert_eq!(ColorSpace::from_byte(0), ColorSpace::default());
+}
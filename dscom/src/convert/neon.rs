@@ -0,0 +1,126 @@
+/*
+NEON实现, 每次处理8个像素, 定点运算与scalar完全相同
+vld4/vst3直接完成BGRA的拆分和RGB的交错
+*/
+
+use std::arch::aarch64::*;
+
+use super::scalar;
+use super::{Coef, Kernels, FIX};
+
+// 包装为普通函数指针, 只在检测到NEON后选用
+pub(super) const NEON: Kernels = Kernels {
+    y: |c, src, dst| unsafe { y_neon(c, src, dst) },
+    uv: |c, src, step, du, dv| unsafe { uv_neon(c, src, step, du, dv) },
+    rgb: |c, y, u, v, step, dst| unsafe { rgb_neon(c, y, u, v, step, dst) },
+};
+
+#[inline]
+#[target_feature(enable = "neon")]
+unsafe fn widen(x: uint8x8_t) -> int16x8_t {
+    vreinterpretq_s16_u16(vmovl_u8(x))
+}
+
+/**
+ * 8个像素的B G R与系数的点积, 加上scalar::bias, 返回前后4个像素
+ */
+#[inline]
+#[target_feature(enable = "neon")]
+unsafe fn dot_neon(b: uint8x8_t, g: uint8x8_t, r: uint8x8_t, c: [i16; 3], bias: i32) -> [int32x4_t; 2] {
+    let (b, g, r) = (widen(b), widen(g), widen(r));
+    let half = |b: int16x4_t, g: int16x4_t, r: int16x4_t| {
+        let t = vmlal_n_s16(vmlal_n_s16(vmull_n_s16(b, c[0]), g, c[1]), r, c[2]);
+        vaddq_s32(t, vdupq_n_s32(bias))
+    };
+    [
+        half(vget_low_s16(b), vget_low_s16(g), vget_low_s16(r)),
+        half(vget_high_s16(b), vget_high_s16(g), vget_high_s16(r)),
+    ]
+}
+
+/**
+ * 8个i32饱和压缩为8个u8
+ */
+#[inline]
+#[target_feature(enable = "neon")]
+unsafe fn pack_neon(t: [int32x4_t; 2]) -> uint8x8_t {
+    vqmovun_s16(vcombine_s16(vqmovn_s32(t[0]), vqmovn_s32(t[1])))
+}
+
+#[inline]
+#[target_feature(enable = "neon")]
+unsafe fn fin_neon(t: [int32x4_t; 2]) -> uint8x8_t {
+    pack_neon([vshrq_n_s32::<FIX>(t[0]), vshrq_n_s32::<FIX>(t[1])])
+}
+
+#[target_feature(enable = "neon")]
+unsafe fn y_neon(c: &Coef, src: &[u8], dst: &mut [u8]) {
+    let bias = scalar::bias(c.y_off);
+    let mut i = 0;
+    while i + 8 <= dst.len() && (i + 8) * 4 <= src.len() {
+        let px = vld4_u8(src.as_ptr().add(i * 4));
+        vst1_u8(dst.as_mut_ptr().add(i), fin_neon(dot_neon(px.0, px.1, px.2, c.y, bias)));
+        i += 8;
+    }
+    scalar::y_row(c, &src[i * 4..], &mut dst[i..]);
+}
+
+#[target_feature(enable = "neon")]
+unsafe fn uv_neon(c: &Coef, src: &[u8], step: usize, du: &mut [u8], dv: &mut [u8]) {
+    let bias = scalar::bias(128);
+    let mut i = 0;
+    while i + 8 <= du.len() && (i + 8) * step * 4 <= src.len() {
+        let p = src.as_ptr().add(i * step * 4);
+        let (b, g, r) = if step == 1 {
+            let px = vld4_u8(p);
+            (px.0, px.1, px.2)
+        } else {
+            // 读16个像素取偶数位
+            let px = vld4q_u8(p);
+            let even = |x: uint8x16_t| vget_low_u8(vuzp1q_u8(x, x));
+            (even(px.0), even(px.1), even(px.2))
+        };
+        vst1_u8(du.as_mut_ptr().add(i), fin_neon(dot_neon(b, g, r, c.u, bias)));
+        vst1_u8(dv.as_mut_ptr().add(i), fin_neon(dot_neon(b, g, r, c.v, bias)));
+        i += 8;
+    }
+    scalar::uv_row(c, &src[i * step * 4..], step, &mut du[i..], &mut dv[i..]);
+}
+
+/**
+ * 读8个像素的色度, 减去128, step为2时每个重复两次
+ */
+#[inline]
+#[target_feature(enable = "neon")]
+unsafe fn chroma_neon(p: *const u8, step: usize) -> int16x8_t {
+    let x = vld1_u8(p);
+    let x = if step == 1 { x } else { vzip1_u8(x, x) };
+    vsubq_s16(widen(x), vdupq_n_s16(128))
+}
+
+#[target_feature(enable = "neon")]
+unsafe fn rgb_neon(c: &Coef, y: &[u8], u: &[u8], v: &[u8], step: usize, dst: &mut [u8]) {
+    let n = dst.len() / 3;
+    let mut i = 0;
+    // 色度总是读8字节, step为2时只用前4个
+    while i + 8 <= n && i + 8 <= y.len() && i / step + 8 <= u.len().min(v.len()) {
+        let y16 = vsubq_s16(widen(vld1_u8(y.as_ptr().add(i))), vdupq_n_s16(c.y_off as i16));
+        let u16 = chroma_neon(u.as_ptr().add(i / step), step);
+        let v16 = chroma_neon(v.as_ptr().add(i / step), step);
+        // 各项右移8位后保留4位小数, 四舍五入的0.5先加到Y上
+        let half = |y: int16x4_t, u: int16x4_t, v: int16x4_t| {
+            let mul = |x: int16x4_t, k: i16| vshrq_n_s32::<8>(vmull_n_s16(x, k));
+            let y = vaddq_s32(mul(y, c.cy), vdupq_n_s32(8));
+            let r = vaddq_s32(y, mul(v, c.rv));
+            let g = vsubq_s32(vsubq_s32(y, mul(u, c.gu)), mul(v, c.gv));
+            let b = vaddq_s32(y, mul(u, c.bu));
+            [vshrq_n_s32::<4>(r), vshrq_n_s32::<4>(g), vshrq_n_s32::<4>(b)]
+        };
+        let lo = half(vget_low_s16(y16), vget_low_s16(u16), vget_low_s16(v16));
+        let hi = half(vget_high_s16(y16), vget_high_s16(u16), vget_high_s16(v16));
+        let rgb = uint8x8x3_t(pack_neon([lo[0], hi[0]]), pack_neon([lo[1], hi[1]]), pack_neon([lo[2], hi[2]]));
+        vst3_u8(dst.as_mut_ptr().add(i * 3), rgb);
+        i += 8;
+    }
+    scalar::rgb_row(c, &y[i..], &u[i / step..], &v[i / step..], step, &mut dst[i * 3..]);
+}
@@ -0,0 +1,153 @@
+/*
+标量参考实现, 结果作为SIMD实现的基准, 修改定点运算时两边一起改
+*/
+
+use super::{clamp, Coef, ColorSpace, Planes, FIX};
+
+pub fn bgra_to_i420(width: usize, height: usize, src: &[u8], stride: usize, dest: &mut Vec<u8>, cs: ColorSpace) {
+    let c = Coef::new(cs);
+
+    dest.clear();
+
+    for y in 0..height {
+        for x in 0..width {
+            let o = y * stride + 4 * x;
+            dest.push(yuv(&c, &src[o..o + 3])[0]);
+        }
+    }
+
+    for k in 1..3 {
+        for y in (0..height).step_by(2) {
+            for x in (0..width).step_by(2) {
+                let o = y * stride + 4 * x;
+                dest.push(yuv(&c, &src[o..o + 3])[k]);
+            }
+        }
+    }
+}
+
+/**
+ * 不降采样色度, 每个像素都有自己的U和V, 彩色文字和细线不会模糊
+ */
+pub fn bgra_to_i444(width: usize, height: usize, src: &[u8], stride: usize, dest: &mut Vec<u8>, cs: ColorSpace) {
+    let c = Coef::new(cs);
+    let n = width * height;
+
+    dest.clear();
+    dest.resize(n * 3, 0);
+    let (dy, duv) = dest.split_at_mut(n);
+    let (du, dv) = duv.split_at_mut(n);
+
+    for y in 0..height {
+        for x in 0..width {
+            let o = y * stride + 4 * x;
+            let i = y * width + x;
+            [dy[i], du[i], dv[i]] = yuv(&c, &src[o..o + 3]);
+        }
+    }
+}
+
+pub fn i420_to_rgb(width: usize, height: usize, src: &Planes, dest: &mut [u8], stride: usize, cs: ColorSpace) {
+    let c = Coef::new(cs);
+    let [ys, us, vs] = src.stride;
+    for i in 0..height {
+        for j in 0..width {
+            let d = i * stride + j * 3;
+            let (u, v) = (src.u[(i >> 1) * us + (j >> 1)], src.v[(i >> 1) * vs + (j >> 1)]);
+            dest[d..d + 3].copy_from_slice(&rgb(&c, src.y[i * ys + j], u, v));
+        }
+    }
+}
+
+/**
+ * I444转RGB, 参数同i420_to_rgb
+ */
+pub fn i444_to_rgb(width: usize, height: usize, src: &Planes, dest: &mut [u8], stride: usize, cs: ColorSpace) {
+    let c = Coef::new(cs);
+    let [ys, us, vs] = src.stride;
+    for i in 0..height {
+        for j in 0..width {
+            let d = i * stride + j * 3;
+            dest[d..d + 3].copy_from_slice(&rgb(&c, src.y[i * ys + j], src.u[i * us + j], src.v[i * vs + j]));
+        }
+    }
+}
+
+/**
+ * 一行BGRA转Y
+ */
+pub(super) fn y_row(c: &Coef, src: &[u8], dst: &mut [u8]) {
+    for (x, d) in dst.iter_mut().enumerate() {
+        let o = 4 * x;
+        *d = fin(dot(&src[o..o + 3], c.y), c.y_off);
+    }
+}
+
+/**
+ * 一行BGRA转U和V, 第k个色度取第k*step个像素
+ */
+pub(super) fn uv_row(c: &Coef, src: &[u8], step: usize, du: &mut [u8], dv: &mut [u8]) {
+    for (x, (u, v)) in du.iter_mut().zip(dv.iter_mut()).enumerate() {
+        let o = 4 * x * step;
+        *u = fin(dot(&src[o..o + 3], c.u), 128);
+        *v = fin(dot(&src[o..o + 3], c.v), 128);
+    }
+}
+
+/**
+ * 一个BGR像素转Y U V
+ */
+#[inline]
+fn yuv(c: &Coef, bgr: &[u8]) -> [u8; 3] {
+    [fin(dot(bgr, c.y), c.y_off), fin(dot(bgr, c.u), 128), fin(dot(bgr, c.v), 128)]
+}
+
+/**
+ * BGR与系数的点积
+ */
+#[inline]
+fn dot(bgr: &[u8], c: [i16; 3]) -> i32 {
+    bgr[0] as i32 * c[0] as i32 + bgr[1] as i32 * c[1] as i32 + bgr[2] as i32 * c[2] as i32
+}
+
+/**
+ * 点积四舍五入后加上偏移
+ */
+#[inline]
+fn fin(t: i32, off: i32) -> u8 {
+    clamp((t + bias(off)) >> FIX)
+}
+
+/**
+ * 四舍五入的0.5与偏移合并为一次加法
+ */
+#[inline]
+pub(super) fn bias(off: i32) -> i32 {
+    (1 << (FIX - 1)) + (off << FIX)
+}
+
+/**
+ * 一个像素的Y U V转R G B
+ * 各项乘积右移8位后保留4位小数, 求和后再四舍五入, SIMD在16位中做同样的运算
+ */
+#[inline]
+fn rgb(c: &Coef, y: u8, u: u8, v: u8) -> [u8; 3] {
+    let mul = |x: i32, k: i16| (x * k as i32) >> 8;
+    let yy = mul(y as i32 - c.y_off, c.cy);
+    let uu = u as i32 - 128;
+    let vv = v as i32 - 128;
+    [
+        clamp((yy + mul(vv, c.rv) + 8) >> 4),
+        clamp((yy - mul(uu, c.gu) - mul(vv, c.gv) + 8) >> 4),
+        clamp((yy + mul(uu, c.bu) + 8) >> 4),
+    ]
+}
+
+/**
+ * 一行YUV转RGB, 第j个像素的色度为u[j / step]
+ */
+pub(super) fn rgb_row(c: &Coef, y: &[u8], u: &[u8], v: &[u8], step: usize, dst: &mut [u8]) {
+    for (j, d) in dst.chunks_exact_mut(3).enumerate() {
+        d.copy_from_slice(&rgb(c, y[j], u[j / step], v[j / step]));
+    }
+}
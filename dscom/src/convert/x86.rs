@@ -0,0 +1,339 @@
+/*
+SSE2和AVX2实现
+定点运算与scalar完全相同, 系数由调用方按颜色空间给出
+SSE2每次处理16个像素, AVX2每次32个, 行尾不足的部分交给scalar
+*/
+
+#[cfg(target_arch = "x86")]
+use std::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+use super::scalar;
+use super::{Coef, Kernels, FIX};
+
+// 包装为普通函数指针, 只在检测到对应指令集后选用
+macro_rules! kernels {
+    ($y:ident, $uv:ident, $rgb:ident) => {
+        Kernels {
+            y: |c, src, dst| unsafe { $y(c, src, dst) },
+            uv: |c, src, step, du, dv| unsafe { $uv(c, src, step, du, dv) },
+            rgb: |c, y, u, v, step, dst| unsafe { $rgb(c, y, u, v, step, dst) },
+        }
+    };
+}
+
+pub(super) const SSE2: Kernels = kernels!(y_sse2, uv_sse2, rgb_sse2);
+pub(super) const AVX2: Kernels = kernels!(y_avx2, uv_avx2, rgb_avx2);
+
+// shuffle_ps取两个源中偶数位和奇数位的32位元素
+const EVEN: i32 = 0b10_00_10_00;
+const ODD: i32 = 0b11_01_11_01;
+
+// SSE2 start
+
+/**
+ * 系数按像素重复两次, 与解包后的B G R A对齐
+ */
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn coef_sse2(c: [i16; 3]) -> __m128i {
+    _mm_setr_epi16(c[0], c[1], c[2], 0, c[0], c[1], c[2], 0)
+}
+
+/**
+ * 4个BGRA像素与系数的点积, 得到4个i32
+ */
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn dot_sse2(px: __m128i, c: __m128i) -> __m128i {
+    let zero = _mm_setzero_si128();
+    let lo = _mm_castsi128_ps(_mm_madd_epi16(_mm_unpacklo_epi8(px, zero), c));
+    let hi = _mm_castsi128_ps(_mm_madd_epi16(_mm_unpackhi_epi8(px, zero), c));
+    let even = _mm_castps_si128(_mm_shuffle_ps::<EVEN>(lo, hi));
+    let odd = _mm_castps_si128(_mm_shuffle_ps::<ODD>(lo, hi));
+    _mm_add_epi32(even, odd)
+}
+
+/**
+ * 从p读4个像素, step为2时隔一个取一个
+ */
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn load_sse2(p: *const u8, step: usize) -> __m128i {
+    let a = _mm_loadu_si128(p as *const __m128i);
+    if step == 1 {
+        return a;
+    }
+    let b = _mm_loadu_si128(p.add(16) as *const __m128i);
+    _mm_castps_si128(_mm_shuffle_ps::<EVEN>(_mm_castsi128_ps(a), _mm_castsi128_ps(b)))
+}
+
+/**
+ * 点积加上scalar::bias后右移
+ */
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn fin_sse2(t: __m128i, bias: __m128i) -> __m128i {
+    _mm_srai_epi32::<FIX>(_mm_add_epi32(t, bias))
+}
+
+/**
+ * 16个i32饱和压缩为16个u8
+ */
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn pack_sse2(v: [__m128i; 4]) -> __m128i {
+    _mm_packus_epi16(_mm_packs_epi32(v[0], v[1]), _mm_packs_epi32(v[2], v[3]))
+}
+
+#[target_feature(enable = "sse2")]
+unsafe fn y_sse2(c: &Coef, src: &[u8], dst: &mut [u8]) {
+    let (cy, bias) = (coef_sse2(c.y), _mm_set1_epi32(scalar::bias(c.y_off)));
+    let mut i = 0;
+    while i + 16 <= dst.len() && (i + 16) * 4 <= src.len() {
+        let p = src.as_ptr().add(i * 4);
+        let mut y = [_mm_setzero_si128(); 4];
+        for (k, y) in y.iter_mut().enumerate() {
+            *y = fin_sse2(dot_sse2(load_sse2(p.add(k * 16), 1), cy), bias);
+        }
+        _mm_storeu_si128(dst.as_mut_ptr().add(i) as *mut __m128i, pack_sse2(y));
+        i += 16;
+    }
+    scalar::y_row(c, &src[i * 4..], &mut dst[i..]);
+}
+
+#[target_feature(enable = "sse2")]
+unsafe fn uv_sse2(c: &Coef, src: &[u8], step: usize, du: &mut [u8], dv: &mut [u8]) {
+    let (cu, cv) = (coef_sse2(c.u), coef_sse2(c.v));
+    let bias = _mm_set1_epi32(scalar::bias(128));
+    let mut i = 0;
+    while i + 16 <= du.len() && (i + 16) * step * 4 <= src.len() {
+        let p = src.as_ptr().add(i * step * 4);
+        let mut u = [_mm_setzero_si128(); 4];
+        let mut v = [_mm_setzero_si128(); 4];
+        for k in 0..4 {
+            let px = load_sse2(p.add(k * 16 * step), step);
+            u[k] = fin_sse2(dot_sse2(px, cu), bias);
+            v[k] = fin_sse2(dot_sse2(px, cv), bias);
+        }
+        _mm_storeu_si128(du.as_mut_ptr().add(i) as *mut __m128i, pack_sse2(u));
+        _mm_storeu_si128(dv.as_mut_ptr().add(i) as *mut __m128i, pack_sse2(v));
+        i += 16;
+    }
+    scalar::uv_row(c, &src[i * step * 4..], step, &mut du[i..], &mut dv[i..]);
+}
+
+/**
+ * 16位有符号乘法后右移8位, 积在32位中计算, 结果可放入16位时与标量完全相同
+ */
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn mul_sse2(x: __m128i, c: i16) -> __m128i {
+    let c = _mm_set1_epi16(c);
+    let lo = _mm_mullo_epi16(x, c);
+    let hi = _mm_mulhi_epi16(x, c);
+    _mm_or_si128(_mm_slli_epi16::<8>(hi), _mm_srli_epi16::<8>(lo))
+}
+
+/**
+ * 8个像素的Y U V(U V已减128)转R G B, 各项保留4位小数, 中间值不超出16位
+ */
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn yuv_sse2(c: &Coef, y: __m128i, u: __m128i, v: __m128i) -> [__m128i; 3] {
+    // 四舍五入的0.5先加到Y上
+    let y = _mm_add_epi16(mul_sse2(_mm_sub_epi16(y, _mm_set1_epi16(c.y_off as i16)), c.cy), _mm_set1_epi16(8));
+    [
+        _mm_srai_epi16::<4>(_mm_add_epi16(y, mul_sse2(v, c.rv))),
+        _mm_srai_epi16::<4>(_mm_sub_epi16(_mm_sub_epi16(y, mul_sse2(u, c.gu)), mul_sse2(v, c.gv))),
+        _mm_srai_epi16::<4>(_mm_add_epi16(y, mul_sse2(u, c.bu))),
+    ]
+}
+
+/**
+ * 读16个像素的色度, step为2时读8个并各重复两次, 减去128后分为前后8个
+ */
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn chroma_sse2(p: *const u8, step: usize) -> [__m128i; 2] {
+    let x = if step == 1 {
+        _mm_loadu_si128(p as *const __m128i)
+    } else {
+        let x = _mm_loadl_epi64(p as *const __m128i);
+        _mm_unpacklo_epi8(x, x)
+    };
+    let zero = _mm_setzero_si128();
+    let c = _mm_set1_epi16(128);
+    [
+        _mm_sub_epi16(_mm_unpacklo_epi8(x, zero), c),
+        _mm_sub_epi16(_mm_unpackhi_epi8(x, zero), c),
+    ]
+}
+
+/**
+ * 16个像素的R G B交错为RGB0, 每个元素4个像素
+ */
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn rgb0_sse2(r: __m128i, g: __m128i, b: __m128i) -> [__m128i; 4] {
+    let zero = _mm_setzero_si128();
+    let (rg_lo, rg_hi) = (_mm_unpacklo_epi8(r, g), _mm_unpackhi_epi8(r, g));
+    let (b_lo, b_hi) = (_mm_unpacklo_epi8(b, zero), _mm_unpackhi_epi8(b, zero));
+    [
+        _mm_unpacklo_epi16(rg_lo, b_lo),
+        _mm_unpackhi_epi16(rg_lo, b_lo),
+        _mm_unpacklo_epi16(rg_hi, b_hi),
+        _mm_unpackhi_epi16(rg_hi, b_hi),
+    ]
+}
+
+/**
+ * 16个像素的Y U V转为R G B各16字节
+ */
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn rgb16_sse2(c: &Coef, y: *const u8, u: *const u8, v: *const u8, step: usize) -> [__m128i; 3] {
+    let zero = _mm_setzero_si128();
+    let y8 = _mm_loadu_si128(y as *const __m128i);
+    let [u_lo, u_hi] = chroma_sse2(u, step);
+    let [v_lo, v_hi] = chroma_sse2(v, step);
+    let lo = yuv_sse2(c, _mm_unpacklo_epi8(y8, zero), u_lo, v_lo);
+    let hi = yuv_sse2(c, _mm_unpackhi_epi8(y8, zero), u_hi, v_hi);
+    [
+        _mm_packus_epi16(lo[0], hi[0]),
+        _mm_packus_epi16(lo[1], hi[1]),
+        _mm_packus_epi16(lo[2], hi[2]),
+    ]
+}
+
+#[target_feature(enable = "sse2")]
+unsafe fn rgb_sse2(c: &Coef, y: &[u8], u: &[u8], v: &[u8], step: usize, dst: &mut [u8]) {
+    let mut px = [0u32; 16];
+    let mut i = 0;
+    // 每个像素按4字节写入, 后一个像素覆盖多写的字节, 最后一组需要多留1字节
+    while (i + 16) * 3 < dst.len() && i + 16 <= y.len() && (i + 16) / step <= u.len().min(v.len()) {
+        let [r, g, b] = rgb16_sse2(c, y.as_ptr().add(i), u.as_ptr().add(i / step), v.as_ptr().add(i / step), step);
+        for (k, p) in rgb0_sse2(r, g, b).into_iter().enumerate() {
+            _mm_storeu_si128(px.as_mut_ptr().add(k * 4) as *mut __m128i, p);
+        }
+        let d = dst.as_mut_ptr().add(i * 3);
+        for (k, p) in px.iter().enumerate() {
+            (d.add(k * 3) as *mut u32).write_unaligned(*p);
+        }
+        i += 16;
+    }
+    scalar::rgb_row(c, &y[i..], &u[i / step..], &v[i / step..], step, &mut dst[i * 3..]);
+}
+
+// SSE2 end
+
+// AVX2 start
+
+#[inline]
+#[target_feature(enable = "avx2")]
+unsafe fn coef_avx2(c: [i16; 3]) -> __m256i {
+    _mm256_setr_epi16(c[0], c[1], c[2], 0, c[0], c[1], c[2], 0, c[0], c[1], c[2], 0, c[0], c[1], c[2], 0)
+}
+
+/**
+ * 8个BGRA像素与系数的点积, 各128位通道内的顺序与dot_sse2相同, 结果按像素顺序排列
+ */
+#[inline]
+#[target_feature(enable = "avx2")]
+unsafe fn dot_avx2(px: __m256i, c: __m256i) -> __m256i {
+    let zero = _mm256_setzero_si256();
+    let lo = _mm256_castsi256_ps(_mm256_madd_epi16(_mm256_unpacklo_epi8(px, zero), c));
+    let hi = _mm256_castsi256_ps(_mm256_madd_epi16(_mm256_unpackhi_epi8(px, zero), c));
+    let even = _mm256_castps_si256(_mm256_shuffle_ps::<EVEN>(lo, hi));
+    let odd = _mm256_castps_si256(_mm256_shuffle_ps::<ODD>(lo, hi));
+    _mm256_add_epi32(even, odd)
+}
+
+/**
+ * 从p读8个像素, step为2时隔一个取一个
+ */
+#[inline]
+#[target_feature(enable = "avx2")]
+unsafe fn load_avx2(p: *const u8, step: usize) -> __m256i {
+    let a = _mm256_loadu_si256(p as *const __m256i);
+    if step == 1 {
+        return a;
+    }
+    let b = _mm256_loadu_si256(p.add(32) as *const __m256i);
+    // 通道内取偶数像素得到0 2 8 10 | 4 6 12 14, 再按64位交换中间两组
+    let even = _mm256_shuffle_ps::<EVEN>(_mm256_castsi256_ps(a), _mm256_castsi256_ps(b));
+    _mm256_permute4x64_epi64::<0b11_01_10_00>(_mm256_castps_si256(even))
+}
+
+#[inline]
+#[target_feature(enable = "avx2")]
+unsafe fn fin_avx2(t: __m256i, bias: __m256i) -> __m256i {
+    _mm256_srai_epi32::<FIX>(_mm256_add_epi32(t, bias))
+}
+
+/**
+ * 32个i32饱和压缩为32个u8, pack按128位通道交错, 最后按32位重排
+ */
+#[inline]
+#[target_feature(enable = "avx2")]
+unsafe fn pack_avx2(v: [__m256i; 4]) -> __m256i {
+    let p = _mm256_packus_epi16(_mm256_packs_epi32(v[0], v[1]), _mm256_packs_epi32(v[2], v[3]));
+    _mm256_permutevar8x32_epi32(p, _mm256_setr_epi32(0, 4, 1, 5, 2, 6, 3, 7))
+}
+
+#[target_feature(enable = "avx2")]
+unsafe fn y_avx2(c: &Coef, src: &[u8], dst: &mut [u8]) {
+    let (cy, bias) = (coef_avx2(c.y), _mm256_set1_epi32(scalar::bias(c.y_off)));
+    let mut i = 0;
+    while i + 32 <= dst.len() && (i + 32) * 4 <= src.len() {
+        let p = src.as_ptr().add(i * 4);
+        let mut y = [_mm256_setzero_si256(); 4];
+        for (k, y) in y.iter_mut().enumerate() {
+            *y = fin_avx2(dot_avx2(load_avx2(p.add(k * 32), 1), cy), bias);
+        }
+        _mm256_storeu_si256(dst.as_mut_ptr().add(i) as *mut __m256i, pack_avx2(y));
+        i += 32;
+    }
+    y_sse2(c, &src[i * 4..], &mut dst[i..]);
+}
+
+#[target_feature(enable = "avx2")]
+unsafe fn uv_avx2(c: &Coef, src: &[u8], step: usize, du: &mut [u8], dv: &mut [u8]) {
+    let (cu, cv) = (coef_avx2(c.u), coef_avx2(c.v));
+    let bias = _mm256_set1_epi32(scalar::bias(128));
+    let mut i = 0;
+    while i + 32 <= du.len() && (i + 32) * step * 4 <= src.len() {
+        let p = src.as_ptr().add(i * step * 4);
+        let mut u = [_mm256_setzero_si256(); 4];
+        let mut v = [_mm256_setzero_si256(); 4];
+        for k in 0..4 {
+            let px = load_avx2(p.add(k * 32 * step), step);
+            u[k] = fin_avx2(dot_avx2(px, cu), bias);
+            v[k] = fin_avx2(dot_avx2(px, cv), bias);
+        }
+        _mm256_storeu_si256(du.as_mut_ptr().add(i) as *mut __m256i, pack_avx2(u));
+        _mm256_storeu_si256(dv.as_mut_ptr().add(i) as *mut __m256i, pack_avx2(v));
+        i += 32;
+    }
+    uv_sse2(c, &src[i * step * 4..], step, &mut du[i..], &mut dv[i..]);
+}
+
+#[target_feature(enable = "avx2")]
+unsafe fn rgb_avx2(c: &Coef, y: &[u8], u: &[u8], v: &[u8], step: usize, dst: &mut [u8]) {
+    // RGB0中去掉每个像素的第4字节
+    let compact = _mm_setr_epi8(0, 1, 2, 4, 5, 6, 8, 9, 10, 12, 13, 14, -1, -1, -1, -1);
+    let mut i = 0;
+    // 每4个像素写16字节, 后一组覆盖多写的4字节, 最后一组需要多留4字节
+    while (i + 16) * 3 + 4 <= dst.len() && i + 16 <= y.len() && (i + 16) / step <= u.len().min(v.len()) {
+        let [r, g, b] = rgb16_sse2(c, y.as_ptr().add(i), u.as_ptr().add(i / step), v.as_ptr().add(i / step), step);
+        let d = dst.as_mut_ptr().add(i * 3);
+        for (k, p) in rgb0_sse2(r, g, b).into_iter().enumerate() {
+            _mm_storeu_si128(d.add(k * 12) as *mut __m128i, _mm_shuffle_epi8(p, compact));
+        }
+        i += 16;
+    }
+    rgb_sse2(c, &y[i..], &u[i / step..], &v[i / step..], step, &mut dst[i * 3..]);
+}
+
+// AVX2 end
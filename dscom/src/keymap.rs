@@ -0,0 +1,308 @@
+/*
+按键标识, 使用USB HID usage, 与键盘布局和平台无关
++------------+------------+
+|    page    |   usage    |  u16 u16
++------------+------------+
+page: 0x07键盘, 0x0C消费类(媒体键)
+usage: page内的usage id, 见 HID Usage Tables 第10章与第15章
+*/
+
+pub const PAGE_KEYBOARD: u32 = 0x07;
+pub const PAGE_CONSUMER: u32 = 0x0C;
+
+const fn kb(usage: u32) -> u32 {
+    (PAGE_KEYBOARD << 16) | usage
+}
+
+const fn cs(usage: u32) -> u32 {
+    (PAGE_CONSUMER << 16) | usage
+}
+
+pub fn page(key: u32) -> u32 {
+    key >> 16
+}
+
+pub fn usage(key: u32) -> u32 {
+    key & 0xffff
+}
+
+// 字母 start
+pub const KEY_A: u32 = kb(0x04);
+pub const KEY_B: u32 = kb(0x05);
+pub const KEY_C: u32 = kb(0x06);
+pub const KEY_D: u32 = kb(0x07);
+pub const KEY_E: u32 = kb(0x08);
+pub const KEY_F: u32 = kb(0x09);
+pub const KEY_G: u32 = kb(0x0A);
+pub const KEY_H: u32 = kb(0x0B);
+pub const KEY_I: u32 = kb(0x0C);
+pub const KEY_J: u32 = kb(0x0D);
+pub const KEY_K: u32 = kb(0x0E);
+pub const KEY_L: u32 = kb(0x0F);
+pub const KEY_M: u32 = kb(0x10);
+pub const KEY_N: u32 = kb(0x11);
+pub const KEY_O: u32 = kb(0x12);
+pub const KEY_P: u32 = kb(0x13);
+pub const KEY_Q: u32 = kb(0x14);
+pub const KEY_R: u32 = kb(0x15);
+pub const KEY_S: u32 = kb(0x16);
+pub const KEY_T: u32 = kb(0x17);
+pub const KEY_U: u32 = kb(0x18);
+pub const KEY_V: u32 = kb(0x19);
+pub const KEY_W: u32 = kb(0x1A);
+pub const KEY_X: u32 = kb(0x1B);
+pub const KEY_Y: u32 = kb(0x1C);
+pub const KEY_Z: u32 = kb(0x1D);
+// 字母 end
+
+// 数字 start
+pub const KEY_1: u32 = kb(0x1E);
+pub const KEY_2: u32 = kb(0x1F);
+pub const KEY_3: u32 = kb(0x20);
+pub const KEY_4: u32 = kb(0x21);
+pub const KEY_5: u32 = kb(0x22);
+pub const KEY_6: u32 = kb(0x23);
+pub const KEY_7: u32 = kb(0x24);
+pub const KEY_8: u32 = kb(0x25);
+pub const KEY_9: u32 = kb(0x26);
+pub const KEY_0: u32 = kb(0x27);
+// 数字 end
+
+pub const KEY_ENTER: u32 = kb(0x28);
+pub const KEY_ESCAPE: u32 = kb(0x29);
+pub const KEY_BACKSPACE: u32 = kb(0x2A);
+pub const KEY_TAB: u32 = kb(0x2B);
+pub const KEY_SPACE: u32 = kb(0x2C);
+/// - _
+pub const KEY_MINUS: u32 = kb(0x2D);
+/// = +
+pub const KEY_EQUAL: u32 = kb(0x2E);
+/// [ {
+pub const KEY_LEFT_BRACE: u32 = kb(0x2F);
+/// ] }
+pub const KEY_RIGHT_BRACE: u32 = kb(0x30);
+/// \ |
+pub const KEY_BACKSLASH: u32 = kb(0x31);
+/// ; :
+pub const KEY_SEMICOLON: u32 = kb(0x33);
+/// ' "
+pub const KEY_APOSTROPHE: u32 = kb(0x34);
+/// ` ~
+pub const KEY_GRAVE: u32 = kb(0x35);
+/// , <
+pub const KEY_COMMA: u32 = kb(0x36);
+/// . >
+pub const KEY_DOT: u32 = kb(0x37);
+/// / ?
+pub const KEY_SLASH: u32 = kb(0x38);
+pub const KEY_CAPS_LOCK: u32 = kb(0x39);
+
+// 功能键 start
+pub const KEY_F1: u32 = kb(0x3A);
+pub const KEY_F2: u32 = kb(0x3B);
+pub const KEY_F3: u32 = kb(0x3C);
+pub const KEY_F4: u32 = kb(0x3D);
+pub const KEY_F5: u32 = kb(0x3E);
+pub const KEY_F6: u32 = kb(0x3F);
+pub const KEY_F7: u32 = kb(0x40);
+pub const KEY_F8: u32 = kb(0x41);
+pub const KEY_F9: u32 = kb(0x42);
+pub const KEY_F10: u32 = kb(0x43);
+pub const KEY_F11: u32 = kb(0x44);
+pub const KEY_F12: u32 = kb(0x45);
+// 功能键 end
+
+pub const KEY_PRINT_SCREEN: u32 = kb(0x46);
+pub const KEY_SCROLL_LOCK: u32 = kb(0x47);
+pub const KEY_PAUSE: u32 = kb(0x48);
+pub const KEY_INSERT: u32 = kb(0x49);
+pub const KEY_HOME: u32 = kb(0x4A);
+pub const KEY_PAGE_UP: u32 = kb(0x4B);
+pub const KEY_DELETE: u32 = kb(0x4C);
+pub const KEY_END: u32 = kb(0x4D);
+pub const KEY_PAGE_DOWN: u32 = kb(0x4E);
+pub const KEY_RIGHT: u32 = kb(0x4F);
+pub const KEY_LEFT: u32 = kb(0x50);
+pub const KEY_DOWN: u32 = kb(0x51);
+pub const KEY_UP: u32 = kb(0x52);
+
+// 小键盘 start
+pub const KEY_NUM_LOCK: u32 = kb(0x53);
+pub const KEY_KP_DIVIDE: u32 = kb(0x54);
+pub const KEY_KP_MULTIPLY: u32 = kb(0x55);
+pub const KEY_KP_SUBTRACT: u32 = kb(0x56);
+pub const KEY_KP_ADD: u32 = kb(0x57);
+pub const KEY_KP_ENTER: u32 = kb(0x58);
+pub const KEY_KP_1: u32 = kb(0x59);
+pub const KEY_KP_2: u32 = kb(0x5A);
+pub const KEY_KP_3: u32 = kb(0x5B);
+pub const KEY_KP_4: u32 = kb(0x5C);
+pub const KEY_KP_5: u32 = kb(0x5D);
+pub const KEY_KP_6: u32 = kb(0x5E);
+pub const KEY_KP_7: u32 = kb(0x5F);
+pub const KEY_KP_8: u32 = kb(0x60);
+pub const KEY_KP_9: u32 = kb(0x61);
+pub const KEY_KP_0: u32 = kb(0x62);
+pub const KEY_KP_DECIMAL: u32 = kb(0x63);
+pub const KEY_KP_EQUAL: u32 = kb(0x67);
+// 小键盘 end
+
+/// ISO键盘左Shift右侧的 < > 键
+pub const KEY_102ND: u32 = kb(0x64);
+/// 右键菜单键
+pub const KEY_MENU: u32 = kb(0x65);
+pub const KEY_HELP: u32 = kb(0x75);
+pub const KEY_MUTE: u32 = kb(0x7F);
+pub const KEY_VOLUME_UP: u32 = kb(0x80);
+pub const KEY_VOLUME_DOWN: u32 = kb(0x81);
+
+// 修饰键 start
+pub const KEY_LEFT_CTRL: u32 = kb(0xE0);
+pub const KEY_LEFT_SHIFT: u32 = kb(0xE1);
+pub const KEY_LEFT_ALT: u32 = kb(0xE2);
+/// Windows / Command / Super
+pub const KEY_LEFT_META: u32 = kb(0xE3);
+pub const KEY_RIGHT_CTRL: u32 = kb(0xE4);
+pub const KEY_RIGHT_SHIFT: u32 = kb(0xE5);
+pub const KEY_RIGHT_ALT: u32 = kb(0xE6);
+pub const KEY_RIGHT_META: u32 = kb(0xE7);
+// 修饰键 end
+
+// 媒体键 start
+pub const MEDIA_NEXT: u32 = cs(0xB5);
+pub const MEDIA_PREV: u32 = cs(0xB6);
+pub const MEDIA_STOP: u32 = cs(0xB7);
+pub const MEDIA_PLAY_PAUSE: u32 = cs(0xCD);
+// 媒体键 end
+
+/// 协议定义的全部按键, 两端的转换表都应覆盖
+pub const ALL: &[u32] = &[
+    KEY_A,
+    KEY_B,
+    KEY_C,
+    KEY_D,
+    KEY_E,
+    KEY_F,
+    KEY_G,
+    KEY_H,
+    KEY_I,
+    KEY_J,
+    KEY_K,
+    KEY_L,
+    KEY_M,
+    KEY_N,
+    KEY_O,
+    KEY_P,
+    KEY_Q,
+    KEY_R,
+    KEY_S,
+    KEY_T,
+    KEY_U,
+    KEY_V,
+    KEY_W,
+    KEY_X,
+    KEY_Y,
+    KEY_Z,
+    KEY_1,
+    KEY_2,
+    KEY_3,
+    KEY_4,
+    KEY_5,
+    KEY_6,
+    KEY_7,
+    KEY_8,
+    KEY_9,
+    KEY_0,
+    KEY_ENTER,
+    KEY_ESCAPE,
+    KEY_BACKSPACE,
+    KEY_TAB,
+    KEY_SPACE,
+    KEY_MINUS,
+    KEY_EQUAL,
+    KEY_LEFT_BRACE,
+    KEY_RIGHT_BRACE,
+    KEY_BACKSLASH,
+    KEY_SEMICOLON,
+    KEY_APOSTROPHE,
+    KEY_GRAVE,
+    KEY_COMMA,
+    KEY_DOT,
+    KEY_SLASH,
+    KEY_CAPS_LOCK,
+    KEY_F1,
+    KEY_F2,
+    KEY_F3,
+    KEY_F4,
+    KEY_F5,
+    KEY_F6,
+    KEY_F7,
+    KEY_F8,
+    KEY_F9,
+    KEY_F10,
+    KEY_F11,
+    KEY_F12,
+    KEY_PRINT_SCREEN,
+    KEY_SCROLL_LOCK,
+    KEY_PAUSE,
+    KEY_INSERT,
+    KEY_HOME,
+    KEY_PAGE_UP,
+    KEY_DELETE,
+    KEY_END,
+    KEY_PAGE_DOWN,
+    KEY_RIGHT,
+    KEY_LEFT,
+    KEY_DOWN,
+    KEY_UP,
+    KEY_NUM_LOCK,
+    KEY_KP_DIVIDE,
+    KEY_KP_MULTIPLY,
+    KEY_KP_SUBTRACT,
+    KEY_KP_ADD,
+    KEY_KP_ENTER,
+    KEY_KP_1,
+    KEY_KP_2,
+    KEY_KP_3,
+    KEY_KP_4,
+    KEY_KP_5,
+    KEY_KP_6,
+    KEY_KP_7,
+    KEY_KP_8,
+    KEY_KP_9,
+    KEY_KP_0,
+    KEY_KP_DECIMAL,
+    KEY_KP_EQUAL,
+    KEY_102ND,
+    KEY_MENU,
+    KEY_HELP,
+    KEY_MUTE,
+    KEY_VOLUME_UP,
+    KEY_VOLUME_DOWN,
+    KEY_LEFT_CTRL,
+    KEY_LEFT_SHIFT,
+    KEY_LEFT_ALT,
+    KEY_LEFT_META,
+    KEY_RIGHT_CTRL,
+    KEY_RIGHT_SHIFT,
+    KEY_RIGHT_ALT,
+    KEY_RIGHT_META,
+    MEDIA_NEXT,
+    MEDIA_PREV,
+    MEDIA_STOP,
+    MEDIA_PLAY_PAUSE,
+];
+
+#[test]
+fn test_all() {
+    let mut keys = ALL.to_vec();
+    keys.sort();
+    keys.dedup();
+    assert_eq!(keys.len(), ALL.len());
+    for &k in ALL {
+        assert!(page(k) == PAGE_KEYBOARD || page(k) == PAGE_CONSUMER);
+        assert_eq!(k, (page(k) << 16) | usage(k));
+    }
+    assert_eq!(usage(KEY_A), 0x04);
+    assert_eq!(page(MEDIA_PLAY_PAUSE), PAGE_CONSUMER);
+}
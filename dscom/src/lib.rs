@@ -8,4 +8,11 @@ pub const MOUSE_WHEEL_UP: u8 = 5;
 pub const MOUSE_WHEEL_DOWN: u8 = 6;
 pub const MOVE: u8 = 7;
 // key事件 end
-pub mod convert;
\ No newline at end of file
+pub mod auth;
+pub mod clipboard;
+pub mod convert;
+pub mod keymap;
+pub mod proto;
+pub mod stream;
+pub mod tls;
+pub mod transfer;
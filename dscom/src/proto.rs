@@ -0,0 +1,893 @@
+use std::io;
+use std::io::Read;
+use std::io::Write;
+
+use crate::auth::MAC_LEN;
+use crate::auth::NONCE_LEN;
+use crate::convert::ColorSpace;
+
+/*
+消息帧
++------------+
+|    type    |  u8
++------------+
+|   length   |  u32 大端
++------------+
+|  payload   |
++------------+
+type: 消息类型, 未知类型按length整体跳过
+length: payload长度
+*/
+
+/// 协议版本, 握手时双方必须一致
+pub const VERSION: u16 = 4;
+
+// 能力位 start
+pub const CAP_VIDEO: u32 = 1;
+pub const CAP_INPUT: u32 = 1 << 1;
+/// 支持文本输入消息
+pub const CAP_TEXT: u32 = 1 << 2;
+/// 剪贴板由客户端同步到服务端
+pub const CAP_CLIP_UP: u32 = 1 << 3;
+/// 剪贴板由服务端同步到客户端
+pub const CAP_CLIP_DOWN: u32 = 1 << 4;
+/// 文件传输
+pub const CAP_FILE: u32 = 1 << 5;
+/// 多显示器选择
+pub const CAP_MONITOR: u32 = 1 << 6;
+/// 客户端回报接收情况, 服务端据此调整码率和帧率
+pub const CAP_FEEDBACK: u32 = 1 << 7;
+/// 关键帧单独标记, 客户端可请求关键帧
+pub const CAP_KEYFRAME: u32 = 1 << 8;
+/// 视频编码为VP9, 未协商时为VP8
+pub const CAP_VP9: u32 = 1 << 9;
+/// 视频色度不降采样(I444), 仅用于VP9, 码率足够时服务端才启用
+pub const CAP_I444: u32 = 1 << 10;
+/// META带颜色空间, 未协商时服务端固定使用BT.601有限范围
+pub const CAP_COLOR: u32 = 1 << 11;
+/// 光标形状和位置单独发送, 客户端在本地绘制
+pub const CAP_CURSOR: u32 = 1 << 12;
+// 能力位 end
+
+/// 本端实现的全部能力
+pub const CAPS: u32 = CAP_VIDEO
+    | CAP_INPUT
+    | CAP_TEXT
+    | CAP_CLIP_UP
+    | CAP_CLIP_DOWN
+    | CAP_FILE
+    | CAP_MONITOR
+    | CAP_FEEDBACK
+    | CAP_KEYFRAME
+    | CAP_VP9
+    | CAP_I444
+    | CAP_COLOR
+    | CAP_CURSOR;
+
+// 消息类型 start, 1-7为key事件, 见crate根
+pub const HELLO: u8 = 16;
+pub const AUTH: u8 = 17;
+pub const AUTH_RESULT: u8 = 18;
+pub const META: u8 = 19;
+pub const VIDEO: u8 = 20;
+pub const CHALLENGE: u8 = 21;
+pub const TEXT: u8 = 22;
+pub const CLIPBOARD: u8 = 23;
+pub const FILE_PUT: u8 = 24;
+pub const FILE_RESUME: u8 = 25;
+pub const FILE_GET: u8 = 26;
+pub const FILE_DATA: u8 = 27;
+pub const FILE_END: u8 = 28;
+pub const FILE_STATUS: u8 = 29;
+pub const FILE_LIST: u8 = 30;
+pub const MONITORS: u8 = 31;
+pub const SELECT_MONITOR: u8 = 32;
+pub const FEEDBACK: u8 = 33;
+/// 关键帧, payload同VIDEO
+pub const VIDEO_KEY: u8 = 34;
+pub const KEYFRAME_REQUEST: u8 = 35;
+pub const CURSOR_SHAPE: u8 = 36;
+pub const CURSOR_POS: u8 = 37;
+// 消息类型 end
+
+// 认证结果
+pub const AUTH_OK: u8 = 1;
+pub const AUTH_FAIL: u8 = 2;
+/// 失败次数过多, 暂时拒绝
+pub const AUTH_LIMITED: u8 = 3;
+
+// 文件传输结果
+pub const FILE_OK: u8 = 1;
+pub const FILE_ERROR: u8 = 2;
+
+/// SelectMonitor的特殊编号, 查看所有显示器拼成的虚拟桌面
+pub const ALL_MONITORS: u8 = 0xff;
+
+/// 光标图像宽高上限
+pub const MAX_CURSOR: u16 = 256;
+
+/// 单帧payload上限, 防止异常长度耗尽内存
+pub const MAX_PAYLOAD: usize = 64 << 20;
+
+/// 认证完成前的payload上限, 握手和认证消息都很小, 未认证的连接不能申请大块内存
+pub const MAX_HANDSHAKE: usize = 4 << 10;
+
+/**
+ * 显示器在虚拟桌面中的位置, 鼠标坐标据此换算
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Monitor {
+    pub x: i32,
+    pub y: i32,
+    pub width: u16,
+    pub height: u16,
+    pub primary: bool,
+}
+
+/**
+ * 包含所有显示器的虚拟桌面
+ */
+pub fn desktop(monitors: &[Monitor]) -> Option<Monitor> {
+    let left = monitors.iter().map(|m| m.x).min()?;
+    let top = monitors.iter().map(|m| m.y).min()?;
+    let right = monitors.iter().map(|m| m.x + m.width as i32).max()?;
+    let bottom = monitors.iter().map(|m| m.y + m.height as i32).max()?;
+    Some(Monitor {
+        x: left,
+        y: top,
+        width: (right - left).min(u16::MAX as i32) as u16,
+        height: (bottom - top).min(u16::MAX as i32) as u16,
+        primary: false,
+    })
+}
+
+/**
+ * 视频编码, 由协商后的能力位决定, 两端据此配置编码器和解码器
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    VP8,
+    VP9,
+}
+
+impl Codec {
+    pub fn from_caps(caps: u32) -> Codec {
+        if caps & CAP_VP9 != 0 {
+            Codec::VP9
+        } else {
+            Codec::VP8
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Codec::VP8 => "VP8",
+            Codec::VP9 => "VP9",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// 握手: 协议版本与能力位
+    Hello { version: u16, caps: u32 },
+    /// 服务端下发的一次性nonce
+    Challenge([u8; NONCE_LEN]),
+    /// 客户端对nonce的HMAC应答
+    Auth([u8; MAC_LEN]),
+    /// 认证结果 AUTH_OK / AUTH_FAIL / AUTH_LIMITED, 成功时附带权限
+    AuthResult { code: u8, level: u8 },
+    /// 画面宽高和颜色空间, 切换显示器或分辨率变化时重发, 其后第一帧为关键帧
+    /// 旧版本只有宽高, 颜色空间按默认值处理
+    Meta { width: u16, height: u16, color: ColorSpace },
+    /// 一帧编码后的视频数据, key为关键帧, 未协商CAP_KEYFRAME时总为false
+    Video { key: bool, data: Vec<u8> },
+    /// 客户端解码出错或丢帧, 请求服务端尽快发送关键帧
+    KeyframeRequest,
+    /// 按键, 值为keymap中的HID usage
+    KeyUp(u32),
+    KeyDown(u32),
+    /// 输入法或非美式布局提交的文本, UTF-8
+    Text(String),
+    /// 剪贴板内容, kind见clipboard::CLIP_TEXT / CLIP_PNG
+    Clipboard { kind: u8, data: Vec<u8> },
+    /// 发送方告知文件大小与名称
+    FilePut { id: u32, size: u64, name: String },
+    /// 接收方告知从何处续传
+    FileResume { id: u32, offset: u64 },
+    /// 客户端请求下载服务端文件, offset为本地已收到的长度
+    FileGet { id: u32, offset: u64, name: String },
+    FileData { id: u32, offset: u64, data: Vec<u8> },
+    /// 数据发送完毕, 附整个文件的SHA-256
+    FileEnd { id: u32, digest: [u8; 32] },
+    /// 传输结果 FILE_OK / FILE_ERROR
+    FileStatus { id: u32, code: u8, msg: String },
+    /// 服务端可下载的文件及大小, 客户端发送空列表表示请求
+    FileList(Vec<(String, u64)>),
+    /// 服务端的显示器, 认证成功后发送, 下标即显示器编号
+    Monitors(Vec<Monitor>),
+    /// 客户端切换查看的显示器, ALL_MONITORS为全部
+    SelectMonitor(u8),
+    /// 客户端定时回报: 累计收到的视频字节数, 已解码未显示的帧数
+    Feedback { received: u64, pending: u16 },
+    MouseKeyUp(u8),
+    MouseKeyDown(u8),
+    MouseWheelUp,
+    MouseWheelDown,
+    /// 鼠标绝对坐标
+    Move { x: u16, y: u16 },
+    /// 光标图像, 非预乘的RGBA, (hot_x, hot_y)为热点在图像中的位置, 形状变化时发送
+    CursorShape { width: u16, height: u16, hot_x: u16, hot_y: u16, rgba: Vec<u8> },
+    /// 光标热点相对画面左上角的坐标, 可能在画面外, visible为false时隐藏光标
+    CursorPos { x: i32, y: i32, visible: bool },
+    /// 不认识的消息类型, payload已被跳过
+    Unknown(u8),
+}
+
+impl Message {
+    /**
+     * 读取一条完整消息
+     */
+    pub fn read_from<R: Read>(r: &mut R) -> io::Result<Message> {
+        Message::read_from_limited(r, MAX_PAYLOAD)
+    }
+
+    /**
+     * 读取一条payload不超过max的消息, 超过时不分配内存直接返回错误
+     */
+    pub fn read_from_limited<R: Read>(r: &mut R, max: usize) -> io::Result<Message> {
+        let mut header = [0u8; 5];
+        r.read_exact(&mut header)?;
+        let kind = header[0];
+        let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+        if len > max {
+            return Err(invalid("payload too large"));
+        }
+        let mut payload = vec![0u8; len];
+        r.read_exact(&mut payload)?;
+        Message::decode(kind, payload)
+    }
+
+    /**
+     * 按类型解析payload, 多余的尾部字节忽略以便后续版本追加字段
+     */
+    pub fn decode(kind: u8, payload: Vec<u8>) -> io::Result<Message> {
+        let p = &payload[..];
+        let msg = match kind {
+            HELLO => Message::Hello {
+                version: get_u16(p, 0)?,
+                caps: get_u32(p, 2)?,
+            },
+            CHALLENGE => {
+                let mut nonce = [0u8; NONCE_LEN];
+                nonce.copy_from_slice(get_bytes(p, 0, NONCE_LEN)?);
+                Message::Challenge(nonce)
+            }
+            AUTH => {
+                let mut mac = [0u8; MAC_LEN];
+                mac.copy_from_slice(get_bytes(p, 0, MAC_LEN)?);
+                Message::Auth(mac)
+            }
+            AUTH_RESULT => Message::AuthResult {
+                code: get_u8(p, 0)?,
+                level: get_u8(p, 1)?,
+            },
+            META => Message::Meta {
+                width: get_u16(p, 0)?,
+                height: get_u16(p, 2)?,
+                color: p.get(4).map(|b| ColorSpace::from_byte(*b)).unwrap_or_default(),
+            },
+            VIDEO => Message::Video {
+                key: false,
+                data: payload,
+            },
+            VIDEO_KEY => Message::Video {
+                key: true,
+                data: payload,
+            },
+            KEYFRAME_REQUEST => Message::KeyframeRequest,
+            crate::KEY_UP => Message::KeyUp(get_u32(p, 0)?),
+            crate::KEY_DOWN => Message::KeyDown(get_u32(p, 0)?),
+            TEXT => {
+                let text = String::from_utf8(payload).map_err(|_| invalid("text not utf-8"))?;
+                Message::Text(text)
+            }
+            CLIPBOARD => Message::Clipboard {
+                kind: get_u8(p, 0)?,
+                data: payload[1..].to_vec(),
+            },
+            FILE_PUT => Message::FilePut {
+                id: get_u32(p, 0)?,
+                size: get_u64(p, 4)?,
+                name: get_str(p, 12)?,
+            },
+            FILE_RESUME => Message::FileResume {
+                id: get_u32(p, 0)?,
+                offset: get_u64(p, 4)?,
+            },
+            FILE_GET => Message::FileGet {
+                id: get_u32(p, 0)?,
+                offset: get_u64(p, 4)?,
+                name: get_str(p, 12)?,
+            },
+            FILE_DATA => Message::FileData {
+                id: get_u32(p, 0)?,
+                offset: get_u64(p, 4)?,
+                data: payload[12..].to_vec(),
+            },
+            FILE_END => {
+                let mut digest = [0u8; 32];
+                digest.copy_from_slice(get_bytes(p, 4, 32)?);
+                Message::FileEnd {
+                    id: get_u32(p, 0)?,
+                    digest,
+                }
+            }
+            FILE_STATUS => Message::FileStatus {
+                id: get_u32(p, 0)?,
+                code: get_u8(p, 4)?,
+                msg: get_str(p, 5)?,
+            },
+            FILE_LIST => {
+                let mut files = Vec::new();
+                let mut at = 0;
+                while at < p.len() {
+                    let size = get_u64(p, at)?;
+                    let len = get_u16(p, at + 8)? as usize;
+                    let name = get_bytes(p, at + 10, len)?;
+                    let name = String::from_utf8(name.to_vec()).map_err(|_| invalid("name not utf-8"))?;
+                    files.push((name, size));
+                    at += 10 + len;
+                }
+                Message::FileList(files)
+            }
+            MONITORS => {
+                let monitors = p
+                    .chunks(MONITOR_LEN)
+                    .map(|m| {
+                        Ok(Monitor {
+                            x: get_u32(m, 0)? as i32,
+                            y: get_u32(m, 4)? as i32,
+                            width: get_u16(m, 8)?,
+                            height: get_u16(m, 10)?,
+                            primary: get_u8(m, 12)? != 0,
+                        })
+                    })
+                    .collect::<io::Result<Vec<Monitor>>>()?;
+                Message::Monitors(monitors)
+            }
+            SELECT_MONITOR => Message::SelectMonitor(get_u8(p, 0)?),
+            FEEDBACK => Message::Feedback {
+                received: get_u64(p, 0)?,
+                pending: get_u16(p, 8)?,
+            },
+            crate::MOUSE_KEY_UP => Message::MouseKeyUp(get_u8(p, 0)?),
+            crate::MOUSE_KEY_DOWN => Message::MouseKeyDown(get_u8(p, 0)?),
+            crate::MOUSE_WHEEL_UP => Message::MouseWheelUp,
+            crate::MOUSE_WHEEL_DOWN => Message::MouseWheelDown,
+            crate::MOVE => Message::Move {
+                x: get_u16(p, 0)?,
+                y: get_u16(p, 2)?,
+            },
+            CURSOR_SHAPE => {
+                let width = get_u16(p, 0)?;
+                let height = get_u16(p, 2)?;
+                if width > MAX_CURSOR || height > MAX_CURSOR {
+                    return Err(invalid("cursor too large"));
+                }
+                let rgba = get_bytes(p, 8, width as usize * height as usize * 4)?;
+                Message::CursorShape {
+                    width,
+                    height,
+                    hot_x: get_u16(p, 4)?,
+                    hot_y: get_u16(p, 6)?,
+                    rgba: rgba.to_vec(),
+                }
+            }
+            CURSOR_POS => Message::CursorPos {
+                x: get_u32(p, 0)? as i32,
+                y: get_u32(p, 4)? as i32,
+                visible: get_u8(p, 8)? != 0,
+            },
+            _ => Message::Unknown(kind),
+        };
+        Ok(msg)
+    }
+
+    /**
+     * 写出一条完整消息
+     */
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        match self {
+            Message::Hello { version, caps } => {
+                let mut p = [0u8; 6];
+                p[..2].copy_from_slice(&version.to_be_bytes());
+                p[2..].copy_from_slice(&caps.to_be_bytes());
+                write_frame(w, HELLO, &p)
+            }
+            Message::Challenge(nonce) => write_frame(w, CHALLENGE, nonce),
+            Message::Auth(mac) => write_frame(w, AUTH, mac),
+            Message::AuthResult { code, level } => write_frame(w, AUTH_RESULT, &[*code, *level]),
+            Message::Meta { width, height, color } => {
+                let mut p = pack_u16x2(*width, *height).to_vec();
+                p.push(color.to_byte());
+                write_frame(w, META, &p)
+            }
+            Message::Video { key, data } => write_frame(w, if *key { VIDEO_KEY } else { VIDEO }, data),
+            Message::KeyframeRequest => write_frame(w, KEYFRAME_REQUEST, &[]),
+            Message::KeyUp(key) => write_frame(w, crate::KEY_UP, &key.to_be_bytes()),
+            Message::KeyDown(key) => write_frame(w, crate::KEY_DOWN, &key.to_be_bytes()),
+            Message::Text(text) => write_frame(w, TEXT, text.as_bytes()),
+            Message::Clipboard { kind, data } => {
+                let mut p = Vec::with_capacity(data.len() + 1);
+                p.push(*kind);
+                p.extend_from_slice(data);
+                write_frame(w, CLIPBOARD, &p)
+            }
+            Message::FilePut { id, size, name } => {
+                write_frame(w, FILE_PUT, &pack_file(*id, *size, name.as_bytes()))
+            }
+            Message::FileResume { id, offset } => {
+                write_frame(w, FILE_RESUME, &pack_file(*id, *offset, &[]))
+            }
+            Message::FileGet { id, offset, name } => {
+                write_frame(w, FILE_GET, &pack_file(*id, *offset, name.as_bytes()))
+            }
+            Message::FileData { id, offset, data } => {
+                write_frame(w, FILE_DATA, &pack_file(*id, *offset, data))
+            }
+            Message::FileEnd { id, digest } => {
+                let mut p = id.to_be_bytes().to_vec();
+                p.extend_from_slice(digest);
+                write_frame(w, FILE_END, &p)
+            }
+            Message::FileStatus { id, code, msg } => {
+                let mut p = id.to_be_bytes().to_vec();
+                p.push(*code);
+                p.extend_from_slice(msg.as_bytes());
+                write_frame(w, FILE_STATUS, &p)
+            }
+            Message::FileList(files) => {
+                let mut p = Vec::new();
+                for (name, size) in files {
+                    let name = name.as_bytes();
+                    p.extend_from_slice(&size.to_be_bytes());
+                    p.extend_from_slice(&(name.len() as u16).to_be_bytes());
+                    p.extend_from_slice(name);
+                }
+                write_frame(w, FILE_LIST, &p)
+            }
+            Message::Monitors(monitors) => {
+                let mut p = Vec::with_capacity(monitors.len() * MONITOR_LEN);
+                for m in monitors {
+                    p.extend_from_slice(&m.x.to_be_bytes());
+                    p.extend_from_slice(&m.y.to_be_bytes());
+                    p.extend_from_slice(&pack_u16x2(m.width, m.height));
+                    p.push(m.primary as u8);
+                }
+                write_frame(w, MONITORS, &p)
+            }
+            Message::SelectMonitor(index) => write_frame(w, SELECT_MONITOR, &[*index]),
+            Message::Feedback { received, pending } => {
+                let mut p = received.to_be_bytes().to_vec();
+                p.extend_from_slice(&pending.to_be_bytes());
+                write_frame(w, FEEDBACK, &p)
+            }
+            Message::MouseKeyUp(key) => write_frame(w, crate::MOUSE_KEY_UP, &[*key]),
+            Message::MouseKeyDown(key) => write_frame(w, crate::MOUSE_KEY_DOWN, &[*key]),
+            Message::MouseWheelUp => write_frame(w, crate::MOUSE_WHEEL_UP, &[]),
+            Message::MouseWheelDown => write_frame(w, crate::MOUSE_WHEEL_DOWN, &[]),
+            Message::Move { x, y } => write_frame(w, crate::MOVE, &pack_u16x2(*x, *y)),
+            Message::CursorShape {
+                width,
+                height,
+                hot_x,
+                hot_y,
+                rgba,
+            } => {
+                let mut p = Vec::with_capacity(8 + rgba.len());
+                p.extend_from_slice(&pack_u16x2(*width, *height));
+                p.extend_from_slice(&pack_u16x2(*hot_x, *hot_y));
+                p.extend_from_slice(rgba);
+                write_frame(w, CURSOR_SHAPE, &p)
+            }
+            Message::CursorPos { x, y, visible } => {
+                let mut p = x.to_be_bytes().to_vec();
+                p.extend_from_slice(&y.to_be_bytes());
+                p.push(*visible as u8);
+                write_frame(w, CURSOR_POS, &p)
+            }
+            Message::Unknown(kind) => write_frame(w, *kind, &[]),
+        }
+    }
+}
+
+/**
+ * 写出一帧, 视频等大块数据直接调用以免拷贝
+ */
+pub fn write_frame<W: Write>(w: &mut W, kind: u8, payload: &[u8]) -> io::Result<()> {
+    if payload.len() > MAX_PAYLOAD {
+        return Err(invalid("payload too large"));
+    }
+    let mut header = [0u8; 5];
+    header[0] = kind;
+    header[1..].copy_from_slice(&(payload.len() as u32).to_be_bytes());
+    w.write_all(&header)?;
+    w.write_all(payload)
+}
+
+/**
+ * 服务端握手: 读取客户端Hello, 回复协商后的能力
+ */
+pub fn server_hello<S: Read + Write>(s: &mut S, caps: u32) -> io::Result<u32> {
+    let (version, peer_caps) = match Message::read_from_limited(s, MAX_HANDSHAKE)? {
+        Message::Hello { version, caps } => (version, caps),
+        _ => return Err(invalid("expect hello")),
+    };
+    let caps = caps & peer_caps;
+    Message::Hello {
+        version: VERSION,
+        caps,
+    }
+    .write_to(s)?;
+    if version != VERSION {
+        return Err(unsupported(version));
+    }
+    Ok(caps)
+}
+
+/**
+ * 客户端握手: 发送Hello, 返回服务端确认的能力
+ */
+pub fn client_hello<S: Read + Write>(s: &mut S, caps: u32) -> io::Result<u32> {
+    Message::Hello {
+        version: VERSION,
+        caps,
+    }
+    .write_to(s)?;
+    match Message::read_from(s)? {
+        Message::Hello { version, .. } if version != VERSION => Err(unsupported(version)),
+        Message::Hello { caps: peer_caps, .. } => Ok(caps & peer_caps),
+        _ => Err(invalid("expect hello")),
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn unsupported(version: u16) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("protocol version {} != {}", version, VERSION),
+    )
+}
+
+fn pack_u16x2(a: u16, b: u16) -> [u8; 4] {
+    let [a0, a1] = a.to_be_bytes();
+    let [b0, b1] = b.to_be_bytes();
+    [a0, a1, b0, b1]
+}
+
+/// MONITORS中每个显示器: x i32, y i32, 宽高 u16, 是否主显示器 u8
+const MONITOR_LEN: usize = 13;
+
+/**
+ * 文件消息的公共头: id u32, offset/size u64, 其后为名称或数据
+ */
+fn pack_file(id: u32, n: u64, tail: &[u8]) -> Vec<u8> {
+    let mut p = Vec::with_capacity(12 + tail.len());
+    p.extend_from_slice(&id.to_be_bytes());
+    p.extend_from_slice(&n.to_be_bytes());
+    p.extend_from_slice(tail);
+    p
+}
+
+fn get_bytes(p: &[u8], at: usize, n: usize) -> io::Result<&[u8]> {
+    p.get(at..at + n).ok_or_else(|| invalid("payload too short"))
+}
+
+fn get_u8(p: &[u8], at: usize) -> io::Result<u8> {
+    Ok(get_bytes(p, at, 1)?[0])
+}
+
+fn get_u16(p: &[u8], at: usize) -> io::Result<u16> {
+    let b = get_bytes(p, at, 2)?;
+    Ok(u16::from_be_bytes([b[0], b[1]]))
+}
+
+fn get_u32(p: &[u8], at: usize) -> io::Result<u32> {
+    let b = get_bytes(p, at, 4)?;
+    Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn get_u64(p: &[u8], at: usize) -> io::Result<u64> {
+    let b = get_bytes(p, at, 8)?;
+    Ok(u64::from_be_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+}
+
+/**
+ * 从at到末尾的UTF-8字符串
+ */
+fn get_str(p: &[u8], at: usize) -> io::Result<String> {
+    let b = p.get(at..).ok_or_else(|| invalid("payload too short"))?;
+    String::from_utf8(b.to_vec()).map_err(|_| invalid("text not utf-8"))
+}
+
+#[test]
+fn test_round_trip() {
+    let msgs = vec![
+        Message::Hello {
+            version: VERSION,
+            caps: CAPS,
+        },
+        Message::Challenge([7; NONCE_LEN]),
+        Message::Auth([1; MAC_LEN]),
+        Message::AuthResult {
+            code: AUTH_OK,
+            level: 3,
+        },
+        Message::Meta {
+            width: 1920,
+            height: 1080,
+            color: ColorSpace::default(),
+        },
+        Message::Meta {
+            width: 3840,
+            height: 2160,
+            color: ColorSpace::ALL[3],
+        },
+        Message::Video {
+            key: false,
+            data: vec![9; 1000],
+        },
+        Message::Video {
+            key: true,
+            data: vec![8; 10],
+        },
+        Message::KeyframeRequest,
+        Message::KeyUp(crate::keymap::KEY_A),
+        Message::KeyDown(crate::keymap::MEDIA_PLAY_PAUSE),
+        Message::Text("中文 é ß".to_string()),
+        Message::Text(String::new()),
+        Message::Clipboard {
+            kind: crate::clipboard::CLIP_TEXT,
+            data: b"copy".to_vec(),
+        },
+        Message::Clipboard {
+            kind: crate::clipboard::CLIP_PNG,
+            data: vec![],
+        },
+        Message::FilePut {
+            id: 1,
+            size: u64::MAX,
+            name: "报告.pdf".to_string(),
+        },
+        Message::FileResume { id: 1, offset: 4096 },
+        Message::FileGet {
+            id: 2,
+            offset: 0,
+            name: "a.txt".to_string(),
+        },
+        Message::FileData {
+            id: 2,
+            offset: 1 << 40,
+            data: vec![5; 300],
+        },
+        Message::FileEnd {
+            id: 2,
+            digest: [3; 32],
+        },
+        Message::FileStatus {
+            id: 2,
+            code: FILE_ERROR,
+            msg: "checksum mismatch".to_string(),
+        },
+        Message::FileList(vec![]),
+        Message::FileList(vec![("a.txt".to_string(), 10), ("b".to_string(), 0)]),
+        Message::Monitors(vec![]),
+        Message::Monitors(vec![
+            Monitor {
+                x: 0,
+                y: 0,
+                width: 2560,
+                height: 1440,
+                primary: true,
+            },
+            Monitor {
+                x: -1920,
+                y: 200,
+                width: 1920,
+                height: 1080,
+                primary: false,
+            },
+        ]),
+        Message::SelectMonitor(ALL_MONITORS),
+        Message::Feedback {
+            received: 1 << 33,
+            pending: 3,
+        },
+        Message::MouseKeyUp(233),
+        Message::MouseKeyDown(235),
+        Message::MouseWheelUp,
+        Message::MouseWheelDown,
+        Message::Move { x: 65535, y: 300 },
+        Message::CursorShape {
+            width: 2,
+            height: 3,
+            hot_x: 1,
+            hot_y: 2,
+            rgba: (0..24).collect(),
+        },
+        Message::CursorShape {
+            width: 0,
+            height: 0,
+            hot_x: 0,
+            hot_y: 0,
+            rgba: vec![],
+        },
+        Message::CursorPos {
+            x: -5,
+            y: 1 << 20,
+            visible: true,
+        },
+        Message::CursorPos {
+            x: 0,
+            y: 0,
+            visible: false,
+        },
+    ];
+    let mut buf = Vec::new();
+    for m in &msgs {
+        m.write_to(&mut buf).unwrap();
+    }
+    let mut r = &buf[..];
+    for m in &msgs {
+        assert_eq!(&Message::read_from(&mut r).unwrap(), m);
+    }
+    assert!(r.is_empty());
+}
+
+#[test]
+fn test_desktop() {
+    assert_eq!(desktop(&[]), None);
+    let left = Monitor {
+        x: -1920,
+        y: 200,
+        width: 1920,
+        height: 1080,
+        primary: false,
+    };
+    let main = Monitor {
+        x: 0,
+        y: 0,
+        width: 2560,
+        height: 1440,
+        primary: true,
+    };
+    assert_eq!(
+        desktop(&[main, left]),
+        Some(Monitor {
+            x: -1920,
+            y: 0,
+            width: 4480,
+            height: 1440,
+            primary: false,
+        })
+    );
+}
+
+#[test]
+fn test_skip_unknown() {
+    let mut buf = Vec::new();
+    write_frame(&mut buf, 200, &[1, 2, 3]).unwrap();
+    Message::KeyDown(crate::keymap::KEY_ENTER).write_to(&mut buf).unwrap();
+    let mut r = &buf[..];
+    assert_eq!(Message::read_from(&mut r).unwrap(), Message::Unknown(200));
+    assert_eq!(
+        Message::read_from(&mut r).unwrap(),
+        Message::KeyDown(crate::keymap::KEY_ENTER)
+    );
+
+    // 旧版本的META只有宽高
+    let mut buf = Vec::new();
+    write_frame(&mut buf, META, &pack_u16x2(800, 600)).unwrap();
+    assert_eq!(
+        Message::read_from(&mut &buf[..]).unwrap(),
+        Message::Meta {
+            width: 800,
+            height: 600,
+            color: ColorSpace::default(),
+        }
+    );
+}
+
+#[test]
+fn test_bad_payload() {
+    let mut buf = Vec::new();
+    write_frame(&mut buf, crate::MOVE, &[1, 2]).unwrap();
+    assert!(Message::read_from(&mut &buf[..]).is_err());
+
+    // 旧版本的单字节按键
+    let mut buf = Vec::new();
+    write_frame(&mut buf, crate::KEY_DOWN, &[13]).unwrap();
+    assert!(Message::read_from(&mut &buf[..]).is_err());
+
+    let mut buf = Vec::new();
+    write_frame(&mut buf, TEXT, &[0xff, 0xfe]).unwrap();
+    assert!(Message::read_from(&mut &buf[..]).is_err());
+
+    // 文件列表名称长度越界
+    let mut buf = Vec::new();
+    write_frame(&mut buf, FILE_LIST, &[0, 0, 0, 0, 0, 0, 0, 1, 0, 9, b'a']).unwrap();
+    assert!(Message::read_from(&mut &buf[..]).is_err());
+
+    // 显示器信息不完整
+    let mut buf = Vec::new();
+    write_frame(&mut buf, MONITORS, &[0; MONITOR_LEN + 1]).unwrap();
+    assert!(Message::read_from(&mut &buf[..]).is_err());
+
+    // 光标图像数据不足或超过上限
+    let mut buf = Vec::new();
+    write_frame(&mut buf, CURSOR_SHAPE, &[0, 2, 0, 2, 0, 0, 0, 0, 1, 2, 3]).unwrap();
+    assert!(Message::read_from(&mut &buf[..]).is_err());
+    let mut buf = Vec::new();
+    let mut p = pack_u16x2(MAX_CURSOR + 1, 1).to_vec();
+    p.resize(8 + (MAX_CURSOR as usize + 1) * 4, 0);
+    write_frame(&mut buf, CURSOR_SHAPE, &p).unwrap();
+    assert!(Message::read_from(&mut &buf[..]).is_err());
+
+    let mut buf = vec![VIDEO];
+    buf.extend_from_slice(&u32::MAX.to_be_bytes());
+    assert!(Message::read_from(&mut &buf[..]).is_err());
+
+    // 认证前的长度上限, 只有头部也能拒绝
+    let mut buf = vec![AUTH];
+    buf.extend_from_slice(&(MAX_HANDSHAKE as u32 + 1).to_be_bytes());
+    let e = Message::read_from_limited(&mut &buf[..], MAX_HANDSHAKE).err().unwrap();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    let mut buf = Vec::new();
+    Message::Auth([7; MAC_LEN]).write_to(&mut buf).unwrap();
+    assert!(matches!(Message::read_from_limited(&mut &buf[..], MAX_HANDSHAKE), Ok(Message::Auth(_))));
+}
+
+#[test]
+fn test_hello() {
+    use std::net::TcpListener;
+    use std::net::TcpStream;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let th = std::thread::spawn(move || {
+        let (mut s, _) = listener.accept().unwrap();
+        server_hello(&mut s, CAP_VIDEO).unwrap()
+    });
+    let mut c = TcpStream::connect(addr).unwrap();
+    assert_eq!(client_hello(&mut c, CAPS).unwrap(), CAP_VIDEO);
+    assert_eq!(th.join().unwrap(), CAP_VIDEO);
+
+    // 版本不一致
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let th = std::thread::spawn(move || {
+        let (mut s, _) = listener.accept().unwrap();
+        server_hello(&mut s, CAPS).is_err()
+    });
+    let mut c = TcpStream::connect(addr).unwrap();
+    Message::Hello {
+        version: VERSION + 1,
+        caps: CAPS,
+    }
+    .write_to(&mut c)
+    .unwrap();
+    assert!(matches!(Message::read_from(&mut c).unwrap(), Message::Hello { .. }));
+    assert!(th.join().unwrap());
+}
+
+#[test]
+fn test_codec() {
+    assert_eq!(Codec::from_caps(CAPS), Codec::VP9);
+    // 任一端不支持VP9时退回VP8
+    assert_eq!(Codec::from_caps(CAPS & !CAP_VP9), Codec::VP8);
+    assert_eq!(Codec::from_caps(0), Codec::VP8);
+}
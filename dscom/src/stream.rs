@@ -0,0 +1,115 @@
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::net::Shutdown;
+use std::net::SocketAddr;
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::proto;
+use crate::proto::Message;
+use crate::tls::TlsStream;
+
+/**
+ * 明文或TLS连接, 上层统一按Read + Write使用
+ */
+pub enum Stream {
+    Tcp(TcpStream),
+    Tls(TlsStream),
+}
+
+impl Stream {
+    pub fn try_clone(&self) -> io::Result<Stream> {
+        match self {
+            Stream::Tcp(s) => Ok(Stream::Tcp(s.try_clone()?)),
+            Stream::Tls(s) => Ok(Stream::Tls(s.try_clone()?)),
+        }
+    }
+
+    pub fn tcp(&self) -> &TcpStream {
+        match self {
+            Stream::Tcp(s) => s,
+            Stream::Tls(s) => s.get_ref(),
+        }
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.tcp().peer_addr()
+    }
+
+    pub fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        self.tcp().set_read_timeout(dur)
+    }
+
+    pub fn shutdown(&self) -> io::Result<()> {
+        self.tcp().shutdown(Shutdown::Both)
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(s) => s.read(buf),
+            Stream::Tls(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(s) => s.write(buf),
+            Stream::Tls(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(s) => s.flush(),
+            Stream::Tls(s) => s.flush(),
+        }
+    }
+}
+
+/**
+ * 多个线程共用的发送端, 每条消息持锁整体写出, 画面/剪贴板/文件等消息不会交错
+ */
+#[derive(Clone)]
+pub struct Writer {
+    stream: Arc<Mutex<Stream>>,
+    // 关闭连接不必等待正在进行的写
+    tcp: Arc<TcpStream>,
+}
+
+impl Writer {
+    pub fn new(stream: Stream) -> io::Result<Writer> {
+        let tcp = stream.tcp().try_clone()?;
+        Ok(Writer {
+            stream: Arc::new(Mutex::new(stream)),
+            tcp: Arc::new(tcp),
+        })
+    }
+
+    pub fn send(&self, msg: &Message) -> io::Result<()> {
+        msg.write_to(&mut *self.lock()?)
+    }
+
+    /**
+     * 直接写出一帧, 视频等大块数据免拷贝
+     */
+    pub fn send_frame(&self, kind: u8, payload: &[u8]) -> io::Result<()> {
+        proto::write_frame(&mut *self.lock()?, kind, payload)
+    }
+
+    pub fn shutdown(&self) -> io::Result<()> {
+        self.tcp.shutdown(Shutdown::Both)
+    }
+
+    fn lock(&self) -> io::Result<std::sync::MutexGuard<'_, Stream>> {
+        self.stream
+            .lock()
+            .map_err(|_| io::Error::other("writer poisoned"))
+    }
+}
@@ -0,0 +1,442 @@
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::mem;
+use std::net::TcpStream;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::TryLockError;
+
+use rustls::client::danger::HandshakeSignatureValid;
+use rustls::client::danger::ServerCertVerified;
+use rustls::client::danger::ServerCertVerifier;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::CertificateDer;
+use rustls::pki_types::PrivateKeyDer;
+use rustls::pki_types::ServerName;
+use rustls::pki_types::UnixTime;
+use rustls::ClientConnection;
+use rustls::Connection;
+use rustls::DigitallySignedStruct;
+use rustls::ServerConnection;
+use rustls::SignatureScheme;
+use sha2::Digest;
+use sha2::Sha256;
+
+pub use rustls::ClientConfig;
+pub use rustls::ServerConfig;
+
+/// 自签证书使用的名称, 客户端只校验指纹
+const SERVER_NAME: &str = "diffscreen";
+
+/**
+ * TLS连接, 可clone出读写两半分别交给不同线程
+ * 只在加解密时持conn锁, 读写socket时不持锁, 一端的发送阻塞不影响另一线程接收
+ */
+pub struct TlsStream {
+    conn: Arc<Mutex<Connection>>,
+    // 已加密待发送的记录, 持conn锁时追加, 保持记录顺序
+    out: Arc<Mutex<Vec<u8>>>,
+    // 串行化socket写
+    send: Arc<Mutex<()>>,
+    sock: TcpStream,
+}
+
+impl TlsStream {
+    /**
+     * 服务端握手
+     */
+    pub fn accept(mut sock: TcpStream, config: Arc<ServerConfig>) -> io::Result<TlsStream> {
+        let mut conn = ServerConnection::new(config).map_err(to_io)?;
+        while conn.is_handshaking() {
+            conn.complete_io(&mut sock)?;
+        }
+        Ok(TlsStream::new(conn.into(), sock))
+    }
+
+    /**
+     * 客户端握手
+     */
+    pub fn connect(mut sock: TcpStream, config: Arc<ClientConfig>) -> io::Result<TlsStream> {
+        let name = ServerName::try_from(SERVER_NAME).map_err(to_io)?;
+        let mut conn = ClientConnection::new(config, name).map_err(to_io)?;
+        while conn.is_handshaking() {
+            conn.complete_io(&mut sock)?;
+        }
+        Ok(TlsStream::new(conn.into(), sock))
+    }
+
+    fn new(conn: Connection, sock: TcpStream) -> TlsStream {
+        TlsStream {
+            conn: Arc::new(Mutex::new(conn)),
+            out: Arc::new(Mutex::new(Vec::new())),
+            send: Arc::new(Mutex::new(())),
+            sock,
+        }
+    }
+
+    pub fn try_clone(&self) -> io::Result<TlsStream> {
+        Ok(TlsStream {
+            conn: self.conn.clone(),
+            out: self.out.clone(),
+            send: self.send.clone(),
+            sock: self.sock.try_clone()?,
+        })
+    }
+
+    pub fn get_ref(&self) -> &TcpStream {
+        &self.sock
+    }
+
+    fn lock(&self) -> io::Result<std::sync::MutexGuard<'_, Connection>> {
+        self.conn.lock().map_err(|_| poisoned())
+    }
+
+    /**
+     * 持conn锁时取出加密好的记录, 追加到待发送队列
+     */
+    fn encrypt(&self, conn: &mut Connection) -> io::Result<()> {
+        let mut out = self.out.lock().map_err(|_| poisoned())?;
+        while conn.wants_write() {
+            conn.write_tls(&mut *out)?;
+        }
+        Ok(())
+    }
+
+    /**
+     * 不持conn锁发送队列中的记录, wait为false时已有线程在发送则直接返回
+     * 发送线程持锁期间会继续取走新追加的记录, 恰在其释放前追加的留到下一次发送
+     */
+    fn send(&self, wait: bool) -> io::Result<()> {
+        let _guard = match self.send.try_lock() {
+            Ok(guard) => guard,
+            Err(TryLockError::WouldBlock) if wait => self.send.lock().map_err(|_| poisoned())?,
+            Err(TryLockError::WouldBlock) => return Ok(()),
+            Err(TryLockError::Poisoned(_)) => return Err(poisoned()),
+        };
+        loop {
+            let data = mem::take(&mut *self.out.lock().map_err(|_| poisoned())?);
+            if data.is_empty() {
+                return Ok(());
+            }
+            (&self.sock).write_all(&data)?;
+        }
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut raw = [0u8; 16 * 1024];
+        loop {
+            match self.lock()?.reader().read(buf) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                r => return r,
+            }
+            // 不持锁读socket, 写线程可同时发送
+            let n = self.sock.read(&mut raw)?;
+            {
+                let mut conn = self.lock()?;
+                let mut rd = &raw[..n];
+                loop {
+                    conn.read_tls(&mut rd)?;
+                    conn.process_new_packets().map_err(to_io)?;
+                    if rd.is_empty() {
+                        break;
+                    }
+                }
+                self.encrypt(&mut conn)?;
+            }
+            // 写线程正在发送时由它顺带发出, 读线程不等待socket写
+            self.send(false)?;
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = {
+            let mut conn = self.lock()?;
+            let n = conn.writer().write(buf)?;
+            self.encrypt(&mut conn)?;
+            n
+        };
+        self.send(true)?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        {
+            let mut conn = self.lock()?;
+            conn.writer().flush()?;
+            self.encrypt(&mut conn)?;
+        }
+        self.send(true)
+    }
+}
+
+/**
+ * 加载服务端证书, 不存在时生成自签证书并保存
+ */
+pub fn server_config(cert_path: &Path, key_path: &Path) -> io::Result<Arc<ServerConfig>> {
+    if !cert_path.exists() || !key_path.exists() {
+        let ck = rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_string()]).map_err(to_io)?;
+        fs::write(cert_path, ck.cert.pem())?;
+        write_private(key_path, ck.key_pair.serialize_pem().as_bytes())?;
+        println!("Generate certificate {}", cert_path.display());
+    }
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .map_err(to_io)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(to_io)?;
+    if certs.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "no certificate"));
+    }
+    println!("TLS fingerprint {}", fingerprint(&certs[0]));
+    let key = PrivateKeyDer::from_pem_file(key_path).map_err(to_io)?;
+    let config = ServerConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .map_err(to_io)?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(to_io)?;
+    Ok(Arc::new(config))
+}
+
+/**
+ * 客户端配置: 首次连接记录证书指纹, 之后指纹变化即拒绝
+ */
+pub fn client_config(host: &str, known_hosts: &Path) -> io::Result<Arc<ClientConfig>> {
+    let provider = provider();
+    let verifier = Pinned {
+        host: host.to_string(),
+        known_hosts: known_hosts.to_path_buf(),
+        provider: provider.clone(),
+    };
+    let config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(to_io)?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+    Ok(Arc::new(config))
+}
+
+/**
+ * 证书SHA-256指纹, 形如 ab:cd:...
+ */
+pub fn fingerprint(cert: &CertificateDer) -> String {
+    Sha256::digest(cert.as_ref())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+fn write_private(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut opts = fs::OpenOptions::new();
+    opts.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        opts.mode(0o600);
+    }
+    opts.open(path)?.write_all(data)
+}
+
+fn poisoned() -> io::Error {
+    io::Error::other("tls state poisoned")
+}
+
+fn to_io<E: std::error::Error + Send + Sync + 'static>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/*
+known_hosts 每行一条
+host fingerprint
+*/
+fn lookup(known_hosts: &Path, host: &str) -> Option<String> {
+    let text = fs::read_to_string(known_hosts).ok()?;
+    text.lines().find_map(|line| {
+        let mut it = line.split_whitespace();
+        match (it.next(), it.next()) {
+            (Some(h), Some(fp)) if h == host => Some(fp.to_string()),
+            _ => None,
+        }
+    })
+}
+
+fn remember(known_hosts: &Path, host: &str, fp: &str) -> io::Result<()> {
+    let mut f = fs::OpenOptions::new().append(true).create(true).open(known_hosts)?;
+    writeln!(f, "{} {}", host, fp)
+}
+
+#[derive(Debug)]
+struct Pinned {
+    host: String,
+    known_hosts: PathBuf,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for Pinned {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let fp = fingerprint(end_entity);
+        match lookup(&self.known_hosts, &self.host) {
+            Some(pinned) if pinned == fp => Ok(ServerCertVerified::assertion()),
+            Some(_) => Err(rustls::Error::General(format!(
+                "certificate of {} changed, remove it from {} if expected",
+                self.host,
+                self.known_hosts.display()
+            ))),
+            None => {
+                println!("Trust {} fingerprint {}", self.host, fp);
+                remember(&self.known_hosts, &self.host, &fp)
+                    .map_err(|e| rustls::Error::General(e.to_string()))?;
+                Ok(ServerCertVerified::assertion())
+            }
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+#[test]
+fn test_tls_pinning() {
+    use std::net::TcpListener;
+
+    let dir = std::env::temp_dir().join(format!("dscom-tls-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let known_hosts = dir.join("known_hosts");
+    let server = server_config(&dir.join("a.crt"), &dir.join("a.key")).unwrap();
+    let other = server_config(&dir.join("b.crt"), &dir.join("b.key")).unwrap();
+
+    let serve = |config: Arc<ServerConfig>| {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let th = std::thread::spawn(move || {
+            let (sock, _) = listener.accept().unwrap();
+            let mut s = TlsStream::accept(sock, config)?;
+            let mut buf = [0u8; 5];
+            s.read_exact(&mut buf)?;
+            s.write_all(&buf)?;
+            Ok::<_, io::Error>(buf)
+        });
+        (addr, th)
+    };
+
+    // 首次连接记录指纹, 读写可分到两个clone上
+    for _ in 0..2 {
+        let (addr, th) = serve(server.clone());
+        let config = client_config("local", &known_hosts).unwrap();
+        let c = TlsStream::connect(TcpStream::connect(addr).unwrap(), config).unwrap();
+        let mut rd = c.try_clone().unwrap();
+        let mut wr = c;
+        wr.write_all(b"hello").unwrap();
+        let mut buf = [0u8; 5];
+        rd.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+        assert_eq!(&th.join().unwrap().unwrap(), b"hello");
+    }
+
+    // 证书变化
+    let (addr, th) = serve(other);
+    let config = client_config("local", &known_hosts).unwrap();
+    assert!(TlsStream::connect(TcpStream::connect(addr).unwrap(), config).is_err());
+    assert!(th.join().unwrap().is_err());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_tls_duplex() {
+    use std::net::TcpListener;
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    // 双向同时发送, 数据量大于本机回环连接自动调整后的缓冲区, 双方的发送都会阻塞
+    const LEN: usize = 48 << 20;
+    let dir = std::env::temp_dir().join(format!("dscom-tls-duplex-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let server = server_config(&dir.join("a.crt"), &dir.join("a.key")).unwrap();
+    let client = client_config("local", &dir.join("known_hosts")).unwrap();
+
+    fn data(seed: u8) -> Vec<u8> {
+        (0..LEN).map(|i| (i % 251) as u8 ^ seed).collect()
+    }
+    // 一个线程发送, 当前线程接收, 返回收到的数据是否为对端所发
+    fn duplex(s: TlsStream, seed: u8) -> bool {
+        let mut wr = s.try_clone().unwrap();
+        let th = std::thread::spawn(move || wr.write_all(&data(seed)).and_then(|_| wr.flush()));
+        let mut rd = s;
+        let mut buf = vec![0u8; LEN];
+        rd.read_exact(&mut buf).unwrap();
+        th.join().unwrap().unwrap();
+        buf == data(seed ^ 3)
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (tx, rx) = channel();
+    let done = tx.clone();
+    std::thread::spawn(move || {
+        let (sock, _) = listener.accept().unwrap();
+        done.send(duplex(TlsStream::accept(sock, server).unwrap(), 1)).unwrap();
+    });
+    std::thread::spawn(move || {
+        let c = TlsStream::connect(TcpStream::connect(addr).unwrap(), client).unwrap();
+        tx.send(duplex(c, 2)).unwrap();
+    });
+    // 互相等待时读写都卡在锁上, 读超时不起作用, 由这里判断
+    for _ in 0..2 {
+        assert!(rx.recv_timeout(Duration::from_secs(60)).expect("tls deadlock"));
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,281 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use sha2::Digest;
+use sha2::Sha256;
+
+use crate::proto::Message;
+use crate::stream::Writer;
+
+/*
+文件传输, 与画面和输入复用同一连接
+上传
+client                  server
+  | ---- FILE_PUT ----->  |  大小和名称
+  | <-- FILE_RESUME ----  |  服务端已有的.part长度, 重连后从此续传
+  | ---- FILE_DATA ---->  |  每块CHUNK字节
+  | ---- FILE_END ----->  |  整个文件的SHA-256
+  | <-- FILE_STATUS ----  |  校验结果
+下载
+  | ---- FILE_GET ----->  |  名称和客户端已有的.part长度
+  | <--- FILE_PUT ------  |
+  | <--- FILE_DATA -----  |
+  | <--- FILE_END ------  |
+出错时回复 FILE_STATUS
+*/
+
+/// 每个FILE_DATA的数据长度
+pub const CHUNK: usize = 64 << 10;
+/// 未完成文件的后缀
+const PART: &str = ".part";
+
+/**
+ * 只接受目录下的普通文件名, 防止路径穿越
+ */
+pub fn safe_name(name: &str) -> Option<&str> {
+    if name.is_empty()
+        || name == "."
+        || name == ".."
+        || name.contains(['/', '\\', ':', '\0'])
+        || name.ends_with(PART)
+    {
+        return None;
+    }
+    Some(name)
+}
+
+fn part_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}{}", name, PART))
+}
+
+/**
+ * 目录下可下载的文件及大小, 按名称排序
+ */
+pub fn list(dir: &Path) -> Vec<(String, u64)> {
+    let mut files: Vec<(String, u64)> = match fs::read_dir(dir) {
+        Ok(rd) => rd
+            .filter_map(|e| e.ok())
+            .filter_map(|e| {
+                let meta = e.metadata().ok()?;
+                let name = e.file_name().into_string().ok()?;
+                if !meta.is_file() || safe_name(&name).is_none() {
+                    return None;
+                }
+                Some((name, meta.len()))
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    files.sort();
+    files
+}
+
+/**
+ * 本端已收到的长度, 请求下载时用于续传
+ */
+pub fn received(dir: &Path, name: &str) -> u64 {
+    match safe_name(name) {
+        Some(name) => fs::metadata(part_path(dir, name)).map(|m| m.len()).unwrap_or(0),
+        None => 0,
+    }
+}
+
+/**
+ * 正在接收的文件, 数据先写入 name.part, 校验通过后改名
+ */
+pub struct Incoming {
+    pub name: String,
+    pub size: u64,
+    pub received: u64,
+    dir: PathBuf,
+    file: File,
+}
+
+impl Incoming {
+    /**
+     * 打开dir下的.part文件, 已有数据保留用于续传
+     */
+    pub fn open(dir: &Path, name: &str, size: u64) -> io::Result<Incoming> {
+        let name = safe_name(name).ok_or_else(|| invalid("bad file name"))?;
+        fs::create_dir_all(dir)?;
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(part_path(dir, name))?;
+        let mut received = file.metadata()?.len();
+        if received > size {
+            // 对端文件已变化
+            file.set_len(0)?;
+            received = 0;
+        }
+        file.seek(SeekFrom::Start(received))?;
+        Ok(Incoming {
+            name: name.to_string(),
+            size,
+            received,
+            dir: dir.to_path_buf(),
+            file,
+        })
+    }
+
+    pub fn write(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+        if offset != self.received || offset + data.len() as u64 > self.size {
+            return Err(invalid("unexpected file data"));
+        }
+        self.file.write_all(data)?;
+        self.received += data.len() as u64;
+        Ok(())
+    }
+
+    /**
+     * 校验并改为正式文件名, 校验失败删除.part, 下次从头传输
+     */
+    pub fn finish(self, digest: &[u8; 32]) -> io::Result<PathBuf> {
+        self.file.sync_all()?;
+        drop(self.file);
+        let part = part_path(&self.dir, &self.name);
+        if self.received != self.size || file_digest(&part)? != *digest {
+            let _ = fs::remove_file(&part);
+            return Err(invalid("checksum mismatch"));
+        }
+        let path = self.dir.join(&self.name);
+        // Windows下rename不覆盖已有文件
+        let _ = fs::remove_file(&path);
+        fs::rename(&part, &path)?;
+        Ok(path)
+    }
+}
+
+/**
+ * 从offset处发送文件, 最后附上整个文件的SHA-256, progress(已发送, 总大小)
+ */
+pub fn send<F: FnMut(u64, u64)>(
+    w: &Writer,
+    id: u32,
+    path: &Path,
+    offset: u64,
+    mut progress: F,
+) -> io::Result<()> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut pos = offset.min(size);
+    // 续传时已传部分也要计入摘要
+    let mut hasher = Sha256::new();
+    io::copy(&mut (&mut file).take(pos), &mut hasher)?;
+    let mut buf = vec![0u8; CHUNK];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        w.send(&Message::FileData {
+            id,
+            offset: pos,
+            data: buf[..n].to_vec(),
+        })?;
+        pos += n as u64;
+        progress(pos, size);
+    }
+    w.send(&Message::FileEnd {
+        id,
+        digest: hasher.finalize().into(),
+    })
+}
+
+pub fn file_digest(path: &Path) -> io::Result<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize().into())
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[test]
+fn test_safe_name() {
+    assert_eq!(safe_name("a.txt"), Some("a.txt"));
+    assert_eq!(safe_name("报告 1.pdf"), Some("报告 1.pdf"));
+    for bad in ["", ".", "..", "../a", "a/b", "a\\b", "C:a", "a.txt.part"] {
+        assert_eq!(safe_name(bad), None, "{}", bad);
+    }
+}
+
+#[test]
+fn test_transfer() {
+    use crate::stream::Stream;
+    use std::net::TcpListener;
+    use std::net::TcpStream;
+
+    let dir = std::env::temp_dir().join(format!("dscom-transfer-{}", std::process::id()));
+    let src_dir = dir.join("src");
+    let dst_dir = dir.join("dst");
+    fs::create_dir_all(&src_dir).unwrap();
+    let src = src_dir.join("data.bin");
+    let data: Vec<u8> = (0..CHUNK * 3 + 100).map(|i| (i % 251) as u8).collect();
+    fs::write(&src, &data).unwrap();
+    assert_eq!(list(&src_dir), vec![("data.bin".to_string(), data.len() as u64)]);
+
+    // 模拟断线前已收到一部分
+    fs::create_dir_all(&dst_dir).unwrap();
+    fs::write(part_path(&dst_dir, "data.bin"), &data[..CHUNK + 7]).unwrap();
+    assert_eq!(received(&dst_dir, "data.bin"), CHUNK as u64 + 7);
+    assert!(list(&dst_dir).is_empty());
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let th = std::thread::spawn(move || {
+        let (mut s, _) = listener.accept().unwrap();
+        let mut inc: Option<Incoming> = None;
+        loop {
+            match Message::read_from(&mut s).unwrap() {
+                Message::FilePut { size, name, .. } => {
+                    inc = Some(Incoming::open(&dst_dir, &name, size).unwrap());
+                }
+                Message::FileData { offset, data, .. } => {
+                    inc.as_mut().unwrap().write(offset, &data).unwrap();
+                }
+                Message::FileEnd { digest, .. } => {
+                    return inc.take().unwrap().finish(&digest);
+                }
+                m => panic!("{:?}", m),
+            }
+        }
+    });
+    let w = Writer::new(Stream::Tcp(TcpStream::connect(addr).unwrap())).unwrap();
+    w.send(&Message::FilePut {
+        id: 1,
+        size: data.len() as u64,
+        name: "data.bin".to_string(),
+    })
+    .unwrap();
+    let mut last = 0;
+    send(&w, 1, &src, CHUNK as u64 + 7, |n, size| {
+        assert!(n > last && n <= size);
+        last = n;
+    })
+    .unwrap();
+    assert_eq!(last, data.len() as u64);
+    let path = th.join().unwrap().unwrap();
+    assert_eq!(fs::read(&path).unwrap(), data);
+
+    // 校验失败删除.part
+    let dst_dir = dir.join("dst");
+    let mut inc = Incoming::open(&dst_dir, "x", 3).unwrap();
+    inc.write(0, b"abc").unwrap();
+    assert!(inc.write(3, b"d").is_err());
+    assert!(inc.finish(&[0; 32]).is_err());
+    assert_eq!(received(&dst_dir, "x"), 0);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
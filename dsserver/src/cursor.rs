@@ -0,0 +1,318 @@
+use std::time::Duration;
+
+use dscom::proto::MAX_CURSOR;
+
+/*
+光标采集
+画面不含光标, 光标图像和位置单独采集后发给客户端在本地绘制, 移动鼠标不必等待视频
+X11使用XFixes, Windows使用GetCursorInfo, 其他平台和Wayland不支持
+*/
+
+/// 轮询间隔, 约60Hz
+pub const POLL: Duration = Duration::from_millis(16);
+
+/**
+ * 光标图像, 非预乘的RGBA
+ */
+#[derive(Debug, PartialEq, Eq)]
+pub struct Shape {
+    pub width: u16,
+    pub height: u16,
+    pub hot_x: u16,
+    pub hot_y: u16,
+    pub rgba: Vec<u8>,
+}
+
+/**
+ * 一次轮询的结果, (x, y)为热点在虚拟桌面中的坐标, 形状变化时附带图像
+ */
+pub struct State {
+    pub x: i32,
+    pub y: i32,
+    pub visible: bool,
+    pub shape: Option<Shape>,
+}
+
+pub struct Cursor {
+    backend: imp::Backend,
+}
+
+impl Cursor {
+    /**
+     * 平台不支持时返回None
+     */
+    pub fn new() -> Option<Cursor> {
+        Some(Cursor {
+            backend: imp::Backend::new()?,
+        })
+    }
+
+    pub fn poll(&mut self) -> Option<State> {
+        self.backend.poll()
+    }
+}
+
+/**
+ * 由逐像素的RGBA生成光标图像, 超过MAX_CURSOR的部分裁掉
+ */
+fn shape(width: usize, height: usize, hot: (u32, u32), pixel: impl Fn(usize, usize) -> [u8; 4]) -> Shape {
+    let (w, h) = (width.min(MAX_CURSOR as usize), height.min(MAX_CURSOR as usize));
+    let mut rgba = Vec::with_capacity(w * h * 4);
+    for y in 0..h {
+        for x in 0..w {
+            rgba.extend_from_slice(&pixel(x, y));
+        }
+    }
+    Shape {
+        width: w as u16,
+        height: h as u16,
+        hot_x: hot.0.min(MAX_CURSOR as u32 - 1) as u16,
+        hot_y: hot.1.min(MAX_CURSOR as u32 - 1) as u16,
+        rgba,
+    }
+}
+
+/**
+ * 预乘alpha的ARGB转为非预乘的RGBA
+ */
+#[cfg(any(test, all(unix, not(target_os = "macos"))))]
+fn unpremultiply(argb: u32) -> [u8; 4] {
+    let [b, g, r, a] = argb.to_le_bytes();
+    if a == 0 {
+        return [0, 0, 0, 0];
+    }
+    let f = |c: u8| ((c as u32 * 255 + a as u32 / 2) / a as u32).min(255) as u8;
+    [f(r), f(g), f(b), a]
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod imp {
+    use x11rb::connection::RequestConnection;
+    use x11rb::protocol::xfixes;
+    use x11rb::protocol::xfixes::ConnectionExt;
+    use x11rb::rust_connection::RustConnection;
+
+    use super::State;
+
+    pub struct Backend {
+        conn: RustConnection,
+        serial: Option<u32>,
+    }
+
+    impl Backend {
+        pub fn new() -> Option<Backend> {
+            // Wayland下XWayland只知道自己窗口上的光标
+            if crate::screen::wayland_session() {
+                return None;
+            }
+            let (conn, _) = x11rb::connect(None).ok()?;
+            conn.extension_information(xfixes::X11_EXTENSION_NAME).ok()??;
+            // 使用XFixes前必须先协商版本, 光标图像需要2.0以上
+            let version = conn.xfixes_query_version(5, 0).ok()?.reply().ok()?;
+            if version.major_version < 2 {
+                return None;
+            }
+            Some(Backend { conn, serial: None })
+        }
+
+        pub fn poll(&mut self) -> Option<State> {
+            let reply = self.conn.xfixes_get_cursor_image().ok()?.reply().ok()?;
+            let shape = if self.serial != Some(reply.cursor_serial) {
+                self.serial = Some(reply.cursor_serial);
+                let (w, h) = (reply.width as usize, reply.height as usize);
+                let image = &reply.cursor_image;
+                Some(super::shape(w, h, (reply.xhot as u32, reply.yhot as u32), |x, y| {
+                    super::unpremultiply(image[y * w + x])
+                }))
+            } else {
+                None
+            };
+            Some(State {
+                x: reply.x as i32,
+                y: reply.y as i32,
+                visible: true,
+                shape,
+            })
+        }
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::ffi::c_void;
+    use std::mem::size_of;
+
+    use windows::Win32::Graphics::Gdi::CreateCompatibleDC;
+    use windows::Win32::Graphics::Gdi::DeleteDC;
+    use windows::Win32::Graphics::Gdi::DeleteObject;
+    use windows::Win32::Graphics::Gdi::GetDIBits;
+    use windows::Win32::Graphics::Gdi::GetObjectW;
+    use windows::Win32::Graphics::Gdi::BITMAP;
+    use windows::Win32::Graphics::Gdi::BITMAPINFO;
+    use windows::Win32::Graphics::Gdi::BITMAPINFOHEADER;
+    use windows::Win32::Graphics::Gdi::BI_RGB;
+    use windows::Win32::Graphics::Gdi::DIB_RGB_COLORS;
+    use windows::Win32::Graphics::Gdi::HBITMAP;
+    use windows::Win32::Graphics::Gdi::HDC;
+    use windows::Win32::UI::WindowsAndMessaging::GetCursorInfo;
+    use windows::Win32::UI::WindowsAndMessaging::GetIconInfo;
+    use windows::Win32::UI::WindowsAndMessaging::CURSORINFO;
+    use windows::Win32::UI::WindowsAndMessaging::CURSOR_SHOWING;
+    use windows::Win32::UI::WindowsAndMessaging::HCURSOR;
+    use windows::Win32::UI::WindowsAndMessaging::HICON;
+    use windows::Win32::UI::WindowsAndMessaging::ICONINFO;
+
+    use super::Shape;
+    use super::State;
+
+    pub struct Backend {
+        // 上次发送的光标句柄, 系统光标的句柄不变
+        last: Option<usize>,
+    }
+
+    impl Backend {
+        pub fn new() -> Option<Backend> {
+            Some(Backend { last: None })
+        }
+
+        pub fn poll(&mut self) -> Option<State> {
+            let mut info = CURSORINFO {
+                cbSize: size_of::<CURSORINFO>() as u32,
+                ..Default::default()
+            };
+            unsafe { GetCursorInfo(&mut info).ok()? };
+            let visible = info.flags.0 & CURSOR_SHOWING.0 != 0 && !info.hCursor.is_invalid();
+            let handle = info.hCursor.0 as usize;
+            let shape = if visible && self.last != Some(handle) {
+                let shape = unsafe { shape(info.hCursor) };
+                if shape.is_some() {
+                    self.last = Some(handle);
+                }
+                shape
+            } else {
+                None
+            };
+            Some(State {
+                x: info.ptScreenPos.x,
+                y: info.ptScreenPos.y,
+                visible,
+                shape,
+            })
+        }
+    }
+
+    unsafe fn shape(cursor: HCURSOR) -> Option<Shape> {
+        let mut icon = ICONINFO::default();
+        GetIconInfo(HICON(cursor.0), &mut icon).ok()?;
+        let mask = bits(icon.hbmMask);
+        let color = if icon.hbmColor.is_invalid() { None } else { bits(icon.hbmColor) };
+        let _ = DeleteObject(icon.hbmMask);
+        if !icon.hbmColor.is_invalid() {
+            let _ = DeleteObject(icon.hbmColor);
+        }
+        let (mw, mh, mask) = mask?;
+        let hot = (icon.xHotspot, icon.yHotspot);
+        match color {
+            Some((w, h, color)) => {
+                // 旧式的彩色光标alpha全为0, 透明部分由掩码决定
+                let alpha = color.chunks_exact(4).any(|p| p[3] != 0);
+                Some(super::shape(w, h, hot, |x, y| {
+                    let i = (y * w + x) * 4;
+                    let a = if alpha {
+                        color[i + 3]
+                    } else if y < mh && x < mw && mask[(y * mw + x) * 4] == 0 {
+                        255
+                    } else {
+                        0
+                    };
+                    [color[i + 2], color[i + 1], color[i], a]
+                }))
+            }
+            None => {
+                // 单色光标的掩码上半为AND, 下半为XOR
+                let h = mh / 2;
+                Some(super::shape(mw, h, hot, |x, y| {
+                    let and = mask[(y * mw + x) * 4] != 0;
+                    let xor = mask[((y + h) * mw + x) * 4] != 0;
+                    match (and, xor) {
+                        (false, false) => [0, 0, 0, 255],
+                        (false, true) => [255, 255, 255, 255],
+                        (true, false) => [0, 0, 0, 0],
+                        // 反色无法用alpha表示, 画成半透明的黑色
+                        (true, true) => [0, 0, 0, 128],
+                    }
+                }))
+            }
+        }
+    }
+
+    /**
+     * 读取位图为自上而下的32位BGRA
+     */
+    unsafe fn bits(bitmap: HBITMAP) -> Option<(usize, usize, Vec<u8>)> {
+        let mut bm = BITMAP::default();
+        let len = size_of::<BITMAP>() as i32;
+        if GetObjectW(bitmap, len, Some(&mut bm as *mut BITMAP as *mut c_void)) == 0 {
+            return None;
+        }
+        let (w, h) = (bm.bmWidth as usize, bm.bmHeight as usize);
+        let mut info = BITMAPINFO {
+            bmiHeader: BITMAPINFOHEADER {
+                biSize: size_of::<BITMAPINFOHEADER>() as u32,
+                biWidth: bm.bmWidth,
+                biHeight: -bm.bmHeight,
+                biPlanes: 1,
+                biBitCount: 32,
+                biCompression: BI_RGB.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut data = vec![0u8; w * h * 4];
+        let dc = CreateCompatibleDC(HDC::default());
+        let lines = GetDIBits(dc, bitmap, 0, h as u32, Some(data.as_mut_ptr() as *mut c_void), &mut info, DIB_RGB_COLORS);
+        let _ = DeleteDC(dc);
+        if lines == 0 {
+            None
+        } else {
+            Some((w, h, data))
+        }
+    }
+}
+
+#[cfg(not(any(windows, all(unix, not(target_os = "macos")))))]
+mod imp {
+    use super::State;
+
+    pub struct Backend;
+
+    impl Backend {
+        pub fn new() -> Option<Backend> {
+            None
+        }
+
+        pub fn poll(&mut self) -> Option<State> {
+            None
+        }
+    }
+}
+
+#[test]
+fn test_shape() {
+    assert_eq!(unpremultiply(0), [0, 0, 0, 0]);
+    assert_eq!(unpremultiply(0xff10_2030), [0x10, 0x20, 0x30, 0xff]);
+    // 半透明白色
+    assert_eq!(unpremultiply(0x8080_8080), [255, 255, 255, 0x80]);
+    assert_eq!(unpremultiply(0x4020_1000), [0x80, 0x40, 0, 0x40]);
+
+    let s = shape(3, 2, (2, 1), |x, y| [x as u8, y as u8, 0, 255]);
+    assert_eq!((s.width, s.height, s.hot_x, s.hot_y), (3, 2, 2, 1));
+    assert_eq!(&s.rgba[..8], &[0, 0, 0, 255, 1, 0, 0, 255]);
+    assert_eq!(&s.rgba[20..], &[2, 1, 0, 255]);
+
+    // 超大光标裁剪
+    let big = MAX_CURSOR as usize + 10;
+    let s = shape(big, 4, (big as u32, 1), |_, _| [1; 4]);
+    assert_eq!((s.width, s.height, s.hot_x), (MAX_CURSOR, 4, MAX_CURSOR - 1));
+    assert_eq!(s.rgba.len(), MAX_CURSOR as usize * 16);
+}
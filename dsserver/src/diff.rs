@@ -0,0 +1,112 @@
+use std::time::Duration;
+
+/*
+帧差异检测
+画面按TILE*TILE像素分块, 每块计算哈希与上一帧比较, 没有变化的帧不转换也不编码
+画面静止时至少每MIN_REFRESH编码一帧, 保持码流和客户端回报, 也纠正哈希碰撞漏掉的变化
+*/
+
+/// 分块边长, 像素
+const TILE: usize = 64;
+/// 画面不变时两次编码的最长间隔
+pub const MIN_REFRESH: Duration = Duration::from_secs(1);
+
+/**
+ * 上一帧各块的哈希
+ */
+pub struct Diff {
+    width: usize,
+    height: usize,
+    hashes: Vec<u64>,
+}
+
+impl Diff {
+    pub fn new() -> Diff {
+        Diff {
+            width: 0,
+            height: 0,
+            hashes: Vec::new(),
+        }
+    }
+
+    /**
+     * 与上一帧比较并记住新一帧, 返回变化的块数, 0为没有变化
+     * 第一帧或宽高变化时所有块都算变化
+     */
+    pub fn update(&mut self, bgra: &[u8], width: usize, height: usize, stride: usize) -> usize {
+        let cols = width.div_ceil(TILE);
+        let rows = height.div_ceil(TILE);
+        let resized = (width, height) != (self.width, self.height);
+        if resized {
+            self.width = width;
+            self.height = height;
+            self.hashes = vec![0; cols * rows];
+        }
+        let mut dirty = 0;
+        let mut band = vec![0u64; cols];
+        for r in 0..rows {
+            band.fill(0);
+            for y in r * TILE..((r + 1) * TILE).min(height) {
+                let line = &bgra[y * stride..y * stride + width * 4];
+                for (h, seg) in band.iter_mut().zip(line.chunks(TILE * 4)) {
+                    *h = hash(*h, seg);
+                }
+            }
+            for (old, new) in self.hashes[r * cols..(r + 1) * cols].iter_mut().zip(&band) {
+                if resized || old != new {
+                    *old = *new;
+                    dirty += 1;
+                }
+            }
+        }
+        dirty
+    }
+}
+
+/**
+ * 每8字节一次乘法的简单哈希, 只用于判断画面是否变化
+ */
+#[inline]
+fn hash(mut h: u64, data: &[u8]) -> u64 {
+    let mix = |h: u64, x: u64| (h ^ x).wrapping_mul(0x9e37_79b9_7f4a_7c15).rotate_left(31);
+    let mut words = data.chunks_exact(8);
+    for w in &mut words {
+        h = mix(h, u64::from_le_bytes(w.try_into().unwrap()));
+    }
+    for b in words.remainder() {
+        h = mix(h, *b as u64);
+    }
+    h
+}
+
+#[test]
+fn test_diff() {
+    // 130x70, 3x2块, 最右和最下的块不完整, 每行有8字节填充
+    let (w, h) = (130, 70);
+    let stride = w * 4 + 8;
+    let mut frame = vec![0u8; stride * h];
+    let mut diff = Diff::new();
+    assert_eq!(diff.update(&frame, w, h, stride), 6);
+    assert_eq!(diff.update(&frame, w, h, stride), 0);
+
+    // 填充变化不算画面变化
+    frame[w * 4] = 1;
+    assert_eq!(diff.update(&frame, w, h, stride), 0);
+
+    // 一个像素变化只影响所在的块
+    frame[stride * 69 + 129 * 4 + 2] = 255;
+    assert_eq!(diff.update(&frame, w, h, stride), 1);
+    assert_eq!(diff.update(&frame, w, h, stride), 0);
+    frame[0] = 9;
+    frame[stride * 64 + 64 * 4] = 9;
+    assert_eq!(diff.update(&frame, w, h, stride), 2);
+
+    // 同一块内像素互换位置也能发现
+    frame[4] = 9;
+    frame[0] = 0;
+    assert_eq!(diff.update(&frame, w, h, stride), 1);
+
+    // 宽高变化后重新开始
+    assert_eq!(diff.update(&frame, 64, 64, stride), 1);
+    assert_eq!(diff.update(&frame, 64, 64, stride), 0);
+}
@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+
+use dscom::proto;
+use dscom::proto::Message;
+use dscom::stream::Writer;
+use dscom::transfer;
+use dscom::transfer::Incoming;
+
+/**
+ * 会话的文件传输, 上传下载都限定在dir目录内
+ */
+pub struct Files {
+    dir: PathBuf,
+    writer: Writer,
+    incoming: HashMap<u32, Incoming>,
+}
+
+impl Files {
+    pub fn new(dir: PathBuf, writer: Writer) -> Files {
+        Files {
+            dir,
+            writer,
+            incoming: HashMap::new(),
+        }
+    }
+
+    /**
+     * 处理客户端的文件消息
+     */
+    pub fn handle(&mut self, msg: Message) {
+        match msg {
+            Message::FilePut { id, size, name } => match Incoming::open(&self.dir, &name, size) {
+                Ok(inc) => {
+                    let offset = inc.received;
+                    self.incoming.insert(id, inc);
+                    let _ = self.writer.send(&Message::FileResume { id, offset });
+                }
+                Err(e) => self.status(id, proto::FILE_ERROR, format!("{}: {}", name, e)),
+            },
+            Message::FileData { id, offset, data } => {
+                if let Some(inc) = self.incoming.get_mut(&id) {
+                    if let Err(e) = inc.write(offset, &data) {
+                        let name = inc.name.clone();
+                        self.incoming.remove(&id);
+                        self.status(id, proto::FILE_ERROR, format!("{}: {}", name, e));
+                    }
+                }
+            }
+            Message::FileEnd { id, digest } => {
+                if let Some(inc) = self.incoming.remove(&id) {
+                    let name = inc.name.clone();
+                    match inc.finish(&digest) {
+                        Ok(path) => {
+                            println!("Received {}", path.display());
+                            self.status(id, proto::FILE_OK, name);
+                        }
+                        Err(e) => self.status(id, proto::FILE_ERROR, format!("{}: {}", name, e)),
+                    }
+                }
+            }
+            Message::FileGet { id, offset, name } => {
+                if let Err(e) = self.get(id, offset, &name) {
+                    self.status(id, proto::FILE_ERROR, format!("{}: {}", name, e));
+                }
+            }
+            Message::FileList(_) => {
+                let _ = self.writer.send(&Message::FileList(transfer::list(&self.dir)));
+            }
+            _ => {}
+        }
+    }
+
+    /**
+     * 另起线程发送文件, 不阻塞输入事件
+     */
+    fn get(&self, id: u32, offset: u64, name: &str) -> io::Result<()> {
+        let name = transfer::safe_name(name)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "bad file name"))?;
+        let path = self.dir.join(name);
+        let size = std::fs::metadata(&path)?.len();
+        self.writer.send(&Message::FilePut {
+            id,
+            size,
+            name: name.to_string(),
+        })?;
+        let writer = self.writer.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = transfer::send(&writer, id, &path, offset, |_, _| {}) {
+                println!("Send {} error {}", path.display(), e);
+                let _ = writer.send(&Message::FileStatus {
+                    id,
+                    code: proto::FILE_ERROR,
+                    msg: format!("{}: {}", path.display(), e),
+                });
+            }
+        });
+        Ok(())
+    }
+
+    fn status(&self, id: u32, code: u8, msg: String) {
+        let _ = self.writer.send(&Message::FileStatus { id, code, msg });
+    }
+}
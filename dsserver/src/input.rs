@@ -0,0 +1,107 @@
+use std::sync::mpsc::channel;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use enigo::agent::Agent;
+use enigo::agent::Token;
+use enigo::Enigo;
+use enigo::Settings;
+
+/*
+输入注入
+各会话的事件线程把输入消息转为enigo的Token, 发给唯一的输入线程执行
+enigo的对象不一定能跨线程, 由输入线程创建并独占
+*/
+
+/**
+ * 执行输入动作的后端
+ */
+pub trait Input {
+    fn execute(&mut self, token: &Token);
+}
+
+impl Input for Enigo {
+    fn execute(&mut self, token: &Token) {
+        // 单个动作失败不影响后续输入
+        let _ = Agent::execute(self, token);
+    }
+}
+
+/**
+ * 只记录不执行的后端, 用于测试, 复制出的Recorder共用同一份记录
+ */
+#[derive(Clone, Default)]
+pub struct Recorder {
+    tokens: Arc<Mutex<Vec<Token>>>,
+}
+
+impl Recorder {
+    pub fn tokens(&self) -> Vec<Token> {
+        self.tokens.lock().unwrap().clone()
+    }
+}
+
+impl Input for Recorder {
+    fn execute(&mut self, token: &Token) {
+        self.tokens.lock().unwrap().push(token.clone());
+    }
+}
+
+/**
+ * 系统输入, 平台不支持时返回错误
+ */
+pub fn system() -> Result<Box<dyn Input>, String> {
+    match Enigo::new(&Settings::default()) {
+        Ok(enigo) => Ok(Box::new(enigo)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/**
+ * 启动输入线程, 返回的发送端可复制给各会话
+ * 收到第一个输入时才创建后端, Wayland下此时才弹出授权对话框, 创建失败时丢弃之后的输入
+ */
+pub fn spawn<F>(make: F) -> Sender<Token>
+where
+    F: FnOnce() -> Result<Box<dyn Input>, String> + Send + 'static,
+{
+    let (tx, rx) = channel::<Token>();
+    std::thread::spawn(move || {
+        let first = match rx.recv() {
+            Ok(token) => token,
+            Err(_) => return,
+        };
+        let mut input = match make() {
+            Ok(input) => input,
+            Err(e) => {
+                println!("Input unavailable {}", e);
+                return;
+            }
+        };
+        input.execute(&first);
+        for token in rx {
+            input.execute(&token);
+        }
+    });
+    tx
+}
+
+#[test]
+fn test_spawn() {
+    use enigo::Coordinate;
+
+    let rec = Recorder::default();
+    let make = {
+        let rec = rec.clone();
+        move || Ok(Box::new(rec) as Box<dyn Input>)
+    };
+    let tx = spawn(make);
+    tx.send(Token::MoveMouse(1, 2, Coordinate::Abs)).unwrap();
+    tx.send(Token::Text("a".to_string())).unwrap();
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    while rec.tokens().len() < 2 && std::time::Instant::now() < deadline {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    assert_eq!(rec.tokens(), [Token::MoveMouse(1, 2, Coordinate::Abs), Token::Text("a".to_string())]);
+}
@@ -1,3 +1,8 @@
+use dscom::keymap::*;
+use enigo::agent::Token;
+use enigo::Direction;
+use enigo::Key;
+
 pub fn mouse_to_engin(key: u8) -> Option<enigo::Button> {
     match key {
         233 => Some(enigo::Button::Left),
@@ -6,67 +11,405 @@ pub fn mouse_to_engin(key: u8) -> Option<enigo::Button> {
     }
 }
 
-pub fn key_to_enigo(key: u8) -> Option<enigo::Key> {
-    match key {
-        27 => Some(enigo::Key::Escape),
-        190 => Some(enigo::Key::F1),
-        191 => Some(enigo::Key::F2),
-        192 => Some(enigo::Key::F3),
-        193 => Some(enigo::Key::F4),
-        194 => Some(enigo::Key::F5),
-        195 => Some(enigo::Key::F6),
-        196 => Some(enigo::Key::F7),
-        197 => Some(enigo::Key::F8),
-        198 => Some(enigo::Key::F9),
-        199 => Some(enigo::Key::F10),
-        200 => Some(enigo::Key::F11),
-        201 => Some(enigo::Key::F12),
-        // 19 => Some(enigo::Key::Pause), // Pause
-        // 97 => Some(enigo::Key::Print), // Print
-        255 => Some(enigo::Key::Delete),
-        87 => Some(enigo::Key::End),
-        96 => Some(enigo::Key::Unicode('`')),
-        48 => Some(enigo::Key::Unicode('0')),
-        49 => Some(enigo::Key::Unicode('1')),
-        50 => Some(enigo::Key::Unicode('2')),
-        51 => Some(enigo::Key::Unicode('3')),
-        52 => Some(enigo::Key::Unicode('4')),
-        53 => Some(enigo::Key::Unicode('5')),
-        54 => Some(enigo::Key::Unicode('6')),
-        55 => Some(enigo::Key::Unicode('7')),
-        56 => Some(enigo::Key::Unicode('8')),
-        57 => Some(enigo::Key::Unicode('9')),
-        45 => Some(enigo::Key::Unicode('-')),
-        61 => Some(enigo::Key::Unicode('=')),
-        8 => Some(enigo::Key::Backspace),
-        9 => Some(enigo::Key::Tab),
-        91 => Some(enigo::Key::Unicode('[')),
-        93 => Some(enigo::Key::Unicode(']')),
-        92 => Some(enigo::Key::Unicode('\\')),
-        229 => Some(enigo::Key::CapsLock),
-        59 => Some(enigo::Key::Unicode(';')),
-        39 => Some(enigo::Key::Unicode('\'')),
-        13 => Some(enigo::Key::Return),
-        225 => Some(enigo::Key::Shift), // ShiftL
-        44 => Some(enigo::Key::Unicode(',')),
-        46 => Some(enigo::Key::Unicode('.')),
-        47 => Some(enigo::Key::Unicode('/')),
-        226 => Some(enigo::Key::Shift), // ShiftR
-        82 => Some(enigo::Key::UpArrow),
-        227 => Some(enigo::Key::Control), // ControlL
-        233 => Some(enigo::Key::Alt),     // AltL
-        32 => Some(enigo::Key::Space),
-        234 => Some(enigo::Key::Alt), // AltR
-        // 103 => Some(enigo::Key::Menu),
-        228 => Some(enigo::Key::Control), // ControlR
-        81 => Some(enigo::Key::LeftArrow),
-        84 => Some(enigo::Key::DownArrow),
-        83 => Some(enigo::Key::RightArrow),
-        // 99 => Some(enigo::Key::Raw(45)), // Insert
-        86 => Some(enigo::Key::PageDown),
-        80 => Some(enigo::Key::Home),
-        85 => Some(enigo::Key::PageUp),
-        a if a >= 97 && a <= 122 => Some(enigo::Key::Unicode((a - 97 + ('a' as u8)) as char)),
-        _ => None,
+/*
+字母数字符号按物理位置发送原始键码, 与服务端键盘布局无关
+Linux为X键码(evdev+8), Windows为扫描码, macOS为虚拟键码
+*/
+#[cfg(all(unix, not(target_os = "macos")))]
+static RAW: &[(u32, u16)] = &[
+    (KEY_A, 38),
+    (KEY_B, 56),
+    (KEY_C, 54),
+    (KEY_D, 40),
+    (KEY_E, 26),
+    (KEY_F, 41),
+    (KEY_G, 42),
+    (KEY_H, 43),
+    (KEY_I, 31),
+    (KEY_J, 44),
+    (KEY_K, 45),
+    (KEY_L, 46),
+    (KEY_M, 58),
+    (KEY_N, 57),
+    (KEY_O, 32),
+    (KEY_P, 33),
+    (KEY_Q, 24),
+    (KEY_R, 27),
+    (KEY_S, 39),
+    (KEY_T, 28),
+    (KEY_U, 30),
+    (KEY_V, 55),
+    (KEY_W, 25),
+    (KEY_X, 53),
+    (KEY_Y, 29),
+    (KEY_Z, 52),
+    (KEY_1, 10),
+    (KEY_2, 11),
+    (KEY_3, 12),
+    (KEY_4, 13),
+    (KEY_5, 14),
+    (KEY_6, 15),
+    (KEY_7, 16),
+    (KEY_8, 17),
+    (KEY_9, 18),
+    (KEY_0, 19),
+    (KEY_MINUS, 20),
+    (KEY_EQUAL, 21),
+    (KEY_LEFT_BRACE, 34),
+    (KEY_RIGHT_BRACE, 35),
+    (KEY_BACKSLASH, 51),
+    (KEY_SEMICOLON, 47),
+    (KEY_APOSTROPHE, 48),
+    (KEY_GRAVE, 49),
+    (KEY_COMMA, 59),
+    (KEY_DOT, 60),
+    (KEY_SLASH, 61),
+    (KEY_102ND, 94),
+];
+#[cfg(target_os = "windows")]
+static RAW: &[(u32, u16)] = &[
+    (KEY_A, 0x1e),
+    (KEY_B, 0x30),
+    (KEY_C, 0x2e),
+    (KEY_D, 0x20),
+    (KEY_E, 0x12),
+    (KEY_F, 0x21),
+    (KEY_G, 0x22),
+    (KEY_H, 0x23),
+    (KEY_I, 0x17),
+    (KEY_J, 0x24),
+    (KEY_K, 0x25),
+    (KEY_L, 0x26),
+    (KEY_M, 0x32),
+    (KEY_N, 0x31),
+    (KEY_O, 0x18),
+    (KEY_P, 0x19),
+    (KEY_Q, 0x10),
+    (KEY_R, 0x13),
+    (KEY_S, 0x1f),
+    (KEY_T, 0x14),
+    (KEY_U, 0x16),
+    (KEY_V, 0x2f),
+    (KEY_W, 0x11),
+    (KEY_X, 0x2d),
+    (KEY_Y, 0x15),
+    (KEY_Z, 0x2c),
+    (KEY_1, 0x02),
+    (KEY_2, 0x03),
+    (KEY_3, 0x04),
+    (KEY_4, 0x05),
+    (KEY_5, 0x06),
+    (KEY_6, 0x07),
+    (KEY_7, 0x08),
+    (KEY_8, 0x09),
+    (KEY_9, 0x0a),
+    (KEY_0, 0x0b),
+    (KEY_MINUS, 0x0c),
+    (KEY_EQUAL, 0x0d),
+    (KEY_LEFT_BRACE, 0x1a),
+    (KEY_RIGHT_BRACE, 0x1b),
+    (KEY_BACKSLASH, 0x2b),
+    (KEY_SEMICOLON, 0x27),
+    (KEY_APOSTROPHE, 0x28),
+    (KEY_GRAVE, 0x29),
+    (KEY_COMMA, 0x33),
+    (KEY_DOT, 0x34),
+    (KEY_SLASH, 0x35),
+    (KEY_102ND, 0x56),
+];
+#[cfg(target_os = "macos")]
+static RAW: &[(u32, u16)] = &[
+    (KEY_A, 0x00),
+    (KEY_B, 0x0b),
+    (KEY_C, 0x08),
+    (KEY_D, 0x02),
+    (KEY_E, 0x0e),
+    (KEY_F, 0x03),
+    (KEY_G, 0x05),
+    (KEY_H, 0x04),
+    (KEY_I, 0x22),
+    (KEY_J, 0x26),
+    (KEY_K, 0x28),
+    (KEY_L, 0x25),
+    (KEY_M, 0x2e),
+    (KEY_N, 0x2d),
+    (KEY_O, 0x1f),
+    (KEY_P, 0x23),
+    (KEY_Q, 0x0c),
+    (KEY_R, 0x0f),
+    (KEY_S, 0x01),
+    (KEY_T, 0x11),
+    (KEY_U, 0x20),
+    (KEY_V, 0x09),
+    (KEY_W, 0x0d),
+    (KEY_X, 0x07),
+    (KEY_Y, 0x10),
+    (KEY_Z, 0x06),
+    (KEY_1, 0x12),
+    (KEY_2, 0x13),
+    (KEY_3, 0x14),
+    (KEY_4, 0x15),
+    (KEY_5, 0x17),
+    (KEY_6, 0x16),
+    (KEY_7, 0x1a),
+    (KEY_8, 0x1c),
+    (KEY_9, 0x19),
+    (KEY_0, 0x1d),
+    (KEY_MINUS, 0x1b),
+    (KEY_EQUAL, 0x18),
+    (KEY_LEFT_BRACE, 0x21),
+    (KEY_RIGHT_BRACE, 0x1e),
+    (KEY_BACKSLASH, 0x2a),
+    (KEY_SEMICOLON, 0x29),
+    (KEY_APOSTROPHE, 0x27),
+    (KEY_GRAVE, 0x32),
+    (KEY_COMMA, 0x2b),
+    (KEY_DOT, 0x2f),
+    (KEY_SLASH, 0x2c),
+    (KEY_102ND, 0x0a),
+];
+
+/*
+HID usage到enigo按键的转换表
+enigo没有对应按键的, Linux用keysym, macOS用虚拟键码, 经Key::Other发送
+*/
+static KEYS: &[(u32, Key)] = &[
+    (KEY_ENTER, Key::Return),
+    (KEY_ESCAPE, Key::Escape),
+    (KEY_BACKSPACE, Key::Backspace),
+    (KEY_TAB, Key::Tab),
+    (KEY_SPACE, Key::Space),
+    (KEY_CAPS_LOCK, Key::CapsLock),
+    (KEY_F1, Key::F1),
+    (KEY_F2, Key::F2),
+    (KEY_F3, Key::F3),
+    (KEY_F4, Key::F4),
+    (KEY_F5, Key::F5),
+    (KEY_F6, Key::F6),
+    (KEY_F7, Key::F7),
+    (KEY_F8, Key::F8),
+    (KEY_F9, Key::F9),
+    (KEY_F10, Key::F10),
+    (KEY_F11, Key::F11),
+    (KEY_F12, Key::F12),
+    #[cfg(not(target_os = "macos"))]
+    (KEY_PRINT_SCREEN, Key::PrintScr),
+    #[cfg(target_os = "windows")]
+    (KEY_SCROLL_LOCK, Key::Scroll),
+    #[cfg(all(unix, not(target_os = "macos")))]
+    (KEY_SCROLL_LOCK, Key::ScrollLock),
+    #[cfg(not(target_os = "macos"))]
+    (KEY_PAUSE, Key::Pause),
+    #[cfg(not(target_os = "macos"))]
+    (KEY_INSERT, Key::Insert),
+    (KEY_HOME, Key::Home),
+    (KEY_PAGE_UP, Key::PageUp),
+    (KEY_DELETE, Key::Delete),
+    (KEY_END, Key::End),
+    (KEY_PAGE_DOWN, Key::PageDown),
+    (KEY_RIGHT, Key::RightArrow),
+    (KEY_LEFT, Key::LeftArrow),
+    (KEY_DOWN, Key::DownArrow),
+    (KEY_UP, Key::UpArrow),
+    #[cfg(not(target_os = "macos"))]
+    (KEY_NUM_LOCK, Key::Numlock),
+    #[cfg(target_os = "macos")]
+    (KEY_NUM_LOCK, Key::Other(0x47)),
+    #[cfg(target_os = "windows")]
+    (KEY_KP_DIVIDE, Key::Divide),
+    #[cfg(all(unix, not(target_os = "macos")))]
+    (KEY_KP_DIVIDE, Key::Other(0xffaf)),
+    #[cfg(target_os = "macos")]
+    (KEY_KP_DIVIDE, Key::Other(0x4b)),
+    #[cfg(target_os = "windows")]
+    (KEY_KP_MULTIPLY, Key::Multiply),
+    #[cfg(all(unix, not(target_os = "macos")))]
+    (KEY_KP_MULTIPLY, Key::Other(0xffaa)),
+    #[cfg(target_os = "macos")]
+    (KEY_KP_MULTIPLY, Key::Other(0x43)),
+    #[cfg(target_os = "windows")]
+    (KEY_KP_SUBTRACT, Key::Subtract),
+    #[cfg(all(unix, not(target_os = "macos")))]
+    (KEY_KP_SUBTRACT, Key::Other(0xffad)),
+    #[cfg(target_os = "macos")]
+    (KEY_KP_SUBTRACT, Key::Other(0x4e)),
+    #[cfg(target_os = "windows")]
+    (KEY_KP_ADD, Key::Add),
+    #[cfg(all(unix, not(target_os = "macos")))]
+    (KEY_KP_ADD, Key::Other(0xffab)),
+    #[cfg(target_os = "macos")]
+    (KEY_KP_ADD, Key::Other(0x45)),
+    #[cfg(target_os = "windows")]
+    (KEY_KP_ENTER, Key::Return),
+    #[cfg(all(unix, not(target_os = "macos")))]
+    (KEY_KP_ENTER, Key::Other(0xff8d)),
+    #[cfg(target_os = "macos")]
+    (KEY_KP_ENTER, Key::Other(0x4c)),
+    #[cfg(target_os = "windows")]
+    (KEY_KP_1, Key::Numpad1),
+    #[cfg(all(unix, not(target_os = "macos")))]
+    (KEY_KP_1, Key::Other(0xffb1)),
+    #[cfg(target_os = "macos")]
+    (KEY_KP_1, Key::Other(0x53)),
+    #[cfg(target_os = "windows")]
+    (KEY_KP_2, Key::Numpad2),
+    #[cfg(all(unix, not(target_os = "macos")))]
+    (KEY_KP_2, Key::Other(0xffb2)),
+    #[cfg(target_os = "macos")]
+    (KEY_KP_2, Key::Other(0x54)),
+    #[cfg(target_os = "windows")]
+    (KEY_KP_3, Key::Numpad3),
+    #[cfg(all(unix, not(target_os = "macos")))]
+    (KEY_KP_3, Key::Other(0xffb3)),
+    #[cfg(target_os = "macos")]
+    (KEY_KP_3, Key::Other(0x55)),
+    #[cfg(target_os = "windows")]
+    (KEY_KP_4, Key::Numpad4),
+    #[cfg(all(unix, not(target_os = "macos")))]
+    (KEY_KP_4, Key::Other(0xffb4)),
+    #[cfg(target_os = "macos")]
+    (KEY_KP_4, Key::Other(0x56)),
+    #[cfg(target_os = "windows")]
+    (KEY_KP_5, Key::Numpad5),
+    #[cfg(all(unix, not(target_os = "macos")))]
+    (KEY_KP_5, Key::Other(0xffb5)),
+    #[cfg(target_os = "macos")]
+    (KEY_KP_5, Key::Other(0x57)),
+    #[cfg(target_os = "windows")]
+    (KEY_KP_6, Key::Numpad6),
+    #[cfg(all(unix, not(target_os = "macos")))]
+    (KEY_KP_6, Key::Other(0xffb6)),
+    #[cfg(target_os = "macos")]
+    (KEY_KP_6, Key::Other(0x58)),
+    #[cfg(target_os = "windows")]
+    (KEY_KP_7, Key::Numpad7),
+    #[cfg(all(unix, not(target_os = "macos")))]
+    (KEY_KP_7, Key::Other(0xffb7)),
+    #[cfg(target_os = "macos")]
+    (KEY_KP_7, Key::Other(0x59)),
+    #[cfg(target_os = "windows")]
+    (KEY_KP_8, Key::Numpad8),
+    #[cfg(all(unix, not(target_os = "macos")))]
+    (KEY_KP_8, Key::Other(0xffb8)),
+    #[cfg(target_os = "macos")]
+    (KEY_KP_8, Key::Other(0x5b)),
+    #[cfg(target_os = "windows")]
+    (KEY_KP_9, Key::Numpad9),
+    #[cfg(all(unix, not(target_os = "macos")))]
+    (KEY_KP_9, Key::Other(0xffb9)),
+    #[cfg(target_os = "macos")]
+    (KEY_KP_9, Key::Other(0x5c)),
+    #[cfg(target_os = "windows")]
+    (KEY_KP_0, Key::Numpad0),
+    #[cfg(all(unix, not(target_os = "macos")))]
+    (KEY_KP_0, Key::Other(0xffb0)),
+    #[cfg(target_os = "macos")]
+    (KEY_KP_0, Key::Other(0x52)),
+    #[cfg(target_os = "windows")]
+    (KEY_KP_DECIMAL, Key::Decimal),
+    #[cfg(all(unix, not(target_os = "macos")))]
+    (KEY_KP_DECIMAL, Key::Other(0xffae)),
+    #[cfg(target_os = "macos")]
+    (KEY_KP_DECIMAL, Key::Other(0x41)),
+    #[cfg(all(unix, not(target_os = "macos")))]
+    (KEY_KP_EQUAL, Key::Other(0xffbd)),
+    #[cfg(target_os = "macos")]
+    (KEY_KP_EQUAL, Key::Other(0x51)),
+    #[cfg(target_os = "windows")]
+    (KEY_MENU, Key::Apps),
+    #[cfg(all(unix, not(target_os = "macos")))]
+    (KEY_MENU, Key::Other(0xff67)),
+    (KEY_HELP, Key::Help),
+    (KEY_MUTE, Key::VolumeMute),
+    (KEY_VOLUME_UP, Key::VolumeUp),
+    (KEY_VOLUME_DOWN, Key::VolumeDown),
+    (KEY_LEFT_CTRL, Key::LControl),
+    (KEY_LEFT_SHIFT, Key::LShift),
+    (KEY_LEFT_ALT, Key::Alt),
+    #[cfg(target_os = "windows")]
+    (KEY_LEFT_META, Key::LWin),
+    #[cfg(not(target_os = "windows"))]
+    (KEY_LEFT_META, Key::Meta),
+    (KEY_RIGHT_CTRL, Key::RControl),
+    (KEY_RIGHT_SHIFT, Key::RShift),
+    #[cfg(target_os = "windows")]
+    (KEY_RIGHT_ALT, Key::RMenu),
+    #[cfg(all(unix, not(target_os = "macos")))]
+    (KEY_RIGHT_ALT, Key::Other(0xffea)),
+    #[cfg(target_os = "macos")]
+    (KEY_RIGHT_ALT, Key::ROption),
+    #[cfg(target_os = "windows")]
+    (KEY_RIGHT_META, Key::RWin),
+    #[cfg(all(unix, not(target_os = "macos")))]
+    (KEY_RIGHT_META, Key::Other(0xffec)),
+    #[cfg(target_os = "macos")]
+    (KEY_RIGHT_META, Key::RCommand),
+    (MEDIA_NEXT, Key::MediaNextTrack),
+    (MEDIA_PREV, Key::MediaPrevTrack),
+    #[cfg(not(target_os = "macos"))]
+    (MEDIA_STOP, Key::MediaStop),
+    (MEDIA_PLAY_PAUSE, Key::MediaPlayPause),
+];
+
+/// 本平台无法模拟的按键
+#[cfg(all(test, target_os = "windows"))]
+const UNSUPPORTED: &[u32] = &[KEY_KP_EQUAL];
+#[cfg(all(test, target_os = "macos"))]
+const UNSUPPORTED: &[u32] = &[
+    KEY_PRINT_SCREEN,
+    KEY_SCROLL_LOCK,
+    KEY_PAUSE,
+    KEY_INSERT,
+    KEY_MENU,
+    MEDIA_STOP,
+];
+#[cfg(all(test, unix, not(target_os = "macos")))]
+const UNSUPPORTED: &[u32] = &[];
+
+pub fn key_to_token(key: u32, direction: Direction) -> Option<Token> {
+    if let Some((_, code)) = RAW.iter().find(|(h, _)| *h == key) {
+        return Some(Token::Raw(*code, direction));
     }
-}
\ No newline at end of file
+    KEYS.iter().find(|(h, _)| *h == key).map(|(_, k)| Token::Key(*k, direction))
+}
+
+#[test]
+fn test_keymap() {
+    for &h in ALL {
+        let keys: Vec<Key> = KEYS.iter().filter(|(x, _)| *x == h).map(|(_, k)| *k).collect();
+        let raws: Vec<u16> = RAW.iter().filter(|(x, _)| *x == h).map(|(_, c)| *c).collect();
+        if UNSUPPORTED.contains(&h) {
+            assert!(keys.is_empty() && raws.is_empty(), "usage {:#x}", h);
+            continue;
+        }
+        assert_eq!(keys.len() + raws.len(), 1, "usage {:#x}", h);
+        if let Some(&code) = raws.first() {
+            let back: Vec<u32> = RAW.iter().filter(|(_, c)| *c == code).map(|(x, _)| *x).collect();
+            assert_eq!(back, vec![h], "usage {:#x}", h);
+            continue;
+        }
+        // 反查回同一个usage
+        let back: Vec<u32> = KEYS.iter().filter(|(_, k)| *k == keys[0]).map(|(x, _)| *x).collect();
+        if cfg!(target_os = "windows") && h == KEY_KP_ENTER {
+            // Windows上小键盘回车与主回车同为VK_RETURN
+            assert_eq!(back, vec![KEY_ENTER, KEY_KP_ENTER]);
+        } else if !(cfg!(target_os = "windows") && h == KEY_ENTER) {
+            assert_eq!(back, vec![h], "usage {:#x}", h);
+        }
+    }
+    assert_eq!(KEYS.len() + RAW.len() + UNSUPPORTED.len(), ALL.len());
+    // Q在任何布局下都是字母区左上角的物理键
+    let q = if cfg!(target_os = "windows") {
+        0x10
+    } else if cfg!(target_os = "macos") {
+        0x0c
+    } else {
+        24
+    };
+    assert_eq!(key_to_token(KEY_Q, Direction::Press), Some(Token::Raw(q, Direction::Press)));
+    assert_eq!(
+        key_to_token(KEY_ENTER, Direction::Release),
+        Some(Token::Key(Key::Return, Direction::Release))
+    );
+    assert_eq!(key_to_token(0, Direction::Press), None);
+}
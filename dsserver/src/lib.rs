@@ -0,0 +1,15 @@
+pub mod cursor;
+mod diff;
+pub mod key_mouse;
+mod limit;
+mod rate;
+pub mod screen;
+pub mod server;
+mod session;
+mod files;
+pub mod input;
+pub mod synthetic;
+#[cfg(all(unix, not(target_os = "macos")))]
+mod x11;
+#[cfg(all(feature = "wayland", unix, not(target_os = "macos")))]
+mod wayland;
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Duration;
+use std::time::Instant;
+
+/**
+ * 按来源地址限制认证失败次数
+ * 进行中的尝试也计入次数, 同一地址的并发连接不能在失败记录前绕过限制
+ */
+pub struct Limiter {
+    fails: HashMap<IpAddr, (u32, Instant)>,
+    // 已允许但还没有结果的尝试
+    pending: HashMap<IpAddr, u32>,
+    max_fails: u32,
+    lock: Duration,
+}
+
+impl Limiter {
+    pub fn new(max_fails: u32, lock: Duration) -> Limiter {
+        Limiter {
+            fails: HashMap::new(),
+            pending: HashMap::new(),
+            max_fails,
+            lock,
+        }
+    }
+
+    /**
+     * 是否允许该地址继续尝试, 允许时占用一次尝试, 之后必须以fail或success结束
+     */
+    pub fn allow(&mut self, ip: IpAddr) -> bool {
+        let now = Instant::now();
+        let lock = self.lock;
+        // 清理过期记录
+        self.fails.retain(|_, (_, last)| now - *last < lock);
+        let fails = self.fails.get(&ip).map_or(0, |(count, _)| *count);
+        let pending = self.pending.entry(ip).or_insert(0);
+        if fails + *pending >= self.max_fails {
+            return false;
+        }
+        *pending += 1;
+        true
+    }
+
+    pub fn fail(&mut self, ip: IpAddr) {
+        self.release(ip);
+        let e = self.fails.entry(ip).or_insert((0, Instant::now()));
+        e.0 += 1;
+        e.1 = Instant::now();
+    }
+
+    pub fn success(&mut self, ip: IpAddr) {
+        self.release(ip);
+        self.fails.remove(&ip);
+    }
+
+    fn release(&mut self, ip: IpAddr) {
+        if let Some(pending) = self.pending.get_mut(&ip) {
+            *pending = pending.saturating_sub(1);
+            if *pending == 0 {
+                self.pending.remove(&ip);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_limiter() {
+    let a: IpAddr = "10.0.0.1".parse().unwrap();
+    let b: IpAddr = "10.0.0.2".parse().unwrap();
+    let mut l = Limiter::new(3, Duration::from_millis(100));
+    for _ in 0..3 {
+        assert!(l.allow(a));
+        l.fail(a);
+    }
+    assert!(!l.allow(a));
+    assert!(l.allow(b));
+    std::thread::sleep(Duration::from_millis(150));
+    assert!(l.allow(a));
+
+    l.fail(b);
+    l.success(b);
+    assert!(l.allow(b));
+}
+
+#[test]
+fn test_limiter_concurrent() {
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    let a: IpAddr = "10.0.0.1".parse().unwrap();
+    let l = Arc::new(Mutex::new(Limiter::new(3, Duration::from_secs(60))));
+    // 同时发起的连接在任何失败记录前最多允许max_fails个
+    let threads: Vec<_> = (0..16)
+        .map(|_| {
+            let l = l.clone();
+            std::thread::spawn(move || l.lock().unwrap().allow(a))
+        })
+        .collect();
+    let allowed = threads.into_iter().map(|t| t.join().unwrap()).filter(|ok| *ok).count();
+    assert_eq!(allowed, 3);
+
+    // 进行中的尝试失败后仍占用次数, 成功则释放并清除失败记录
+    let mut l = l.lock().unwrap();
+    l.fail(a);
+    l.fail(a);
+    assert!(!l.allow(a));
+    l.success(a);
+    assert!(l.allow(a));
+    assert!(l.allow(a));
+    assert!(l.allow(a));
+    assert!(!l.allow(a));
+}
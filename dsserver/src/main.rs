@@ -1,7 +1,14 @@
-mod key_mouse;
-mod screen;
-mod server;
-mod convert;
+use dscom::auth::Level;
+use dscom::convert::ColorSpace;
+use dscom::convert::Matrix;
+use dscom::proto;
+use dsserver::cursor;
+use dsserver::input;
+use dsserver::screen::Source;
+use dsserver::server;
+use std::path::Path;
+use std::path::PathBuf;
+
 fn main() {
     let args: Vec<String> = std::env::args().collect();
 
@@ -17,6 +24,103 @@ fn main() {
         port = args[2].parse::<u16>().unwrap();
     }
 
+    // 其余参数任意顺序
+    // tls: 启用加密, 首次启动生成自签证书
+    // mouse=<密码>: 仅可操作鼠标
+    // view=<密码>: 仅可观看
+    // clip=<both|up|down|off>: 剪贴板同步方向, up为客户端到服务端
+    // files=<目录|off>: 文件传输目录, 默认diffscreen_files
+    // vp9=off: 不提供VP9编码, 所有会话使用VP8
+    // color=<bt601|bt709>[-full]: 颜色空间, 默认bt709-full, 旧版本客户端固定为bt601
+    // capture=<auto|x11|synthetic[:宽x高]>: 截屏来源, x11可用于Xvfb, synthetic为合成画面, 默认1280x720, 宽高可为奇数
+    let mut tls = None;
+    let mut files = PathBuf::from("diffscreen_files");
+    let mut caps = proto::CAPS;
+    let mut pwds = vec![(Level::Full, pwd)];
+    let mut color = ColorSpace {
+        matrix: Matrix::BT709,
+        full_range: true,
+    };
+    let mut source = Source::Auto;
+    for arg in args.iter().skip(3) {
+        if arg == "tls" {
+            let config = dscom::tls::server_config(Path::new("diffscreen.crt"), Path::new("diffscreen.key")).unwrap();
+            tls = Some(config);
+        } else if let Some(p) = arg.strip_prefix("mouse=") {
+            pwds.push((Level::Mouse, p.to_string()));
+        } else if let Some(p) = arg.strip_prefix("view=") {
+            pwds.push((Level::View, p.to_string()));
+        } else if let Some(dir) = arg.strip_prefix("clip=") {
+            caps &= !(proto::CAP_CLIP_UP | proto::CAP_CLIP_DOWN);
+            match dir {
+                "both" => caps |= proto::CAP_CLIP_UP | proto::CAP_CLIP_DOWN,
+                "up" => caps |= proto::CAP_CLIP_UP,
+                "down" => caps |= proto::CAP_CLIP_DOWN,
+                "off" => {}
+                _ => println!("Unknown clipboard direction {}", dir),
+            }
+        } else if arg == "vp9=off" {
+            caps &= !proto::CAP_VP9;
+        } else if let Some(cs) = arg.strip_prefix("color=") {
+            let (matrix, full) = match cs.strip_suffix("-full") {
+                Some(m) => (m, true),
+                None => (cs, false),
+            };
+            match matrix {
+                "bt601" => color = ColorSpace { matrix: Matrix::BT601, full_range: full },
+                "bt709" => color = ColorSpace { matrix: Matrix::BT709, full_range: full },
+                _ => println!("Unknown color space {}", cs),
+            }
+        } else if let Some(s) = arg.strip_prefix("capture=") {
+            match capture(s) {
+                Some(s) => source = s,
+                None => println!("Unknown capture source {}", s),
+            }
+        } else if let Some(dir) = arg.strip_prefix("files=") {
+            if dir == "off" {
+                caps &= !proto::CAP_FILE;
+            } else {
+                files = PathBuf::from(dir);
+            }
+        } else {
+            println!("Unknown argument {}", arg);
+        }
+    }
+
+    // 无法单独采集光标时不提供, 客户端不绘制光标, 合成画面与桌面光标无关
+    if cursor::Cursor::new().is_none() || matches!(source, Source::Synthetic { .. }) {
+        caps &= !proto::CAP_CURSOR;
+    }
+
     // run forever
-    server::run(port, pwd);
+    let config = server::Config {
+        port,
+        pwds,
+        tls,
+        caps,
+        files,
+        color,
+        source,
+    };
+    server::run(config, input::spawn(input::system));
+}
+
+/**
+ * 解析capture=参数
+ */
+fn capture(s: &str) -> Option<Source> {
+    match s {
+        "auto" => Some(Source::Auto),
+        #[cfg(all(unix, not(target_os = "macos")))]
+        "x11" => Some(Source::X11),
+        "synthetic" => Some(Source::Synthetic { width: 1280, height: 720 }),
+        _ => {
+            let (w, h) = s.strip_prefix("synthetic:")?.split_once('x')?;
+            let (width, height) = (w.parse().ok()?, h.parse().ok()?);
+            if width == 0 || height == 0 {
+                return None;
+            }
+            Some(Source::Synthetic { width, height })
+        }
+    }
 }
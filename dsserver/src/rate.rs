@@ -0,0 +1,236 @@
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+/*
+拥塞控制
+服务端记录已写入连接的视频字节数, 客户端定时回报累计收到的字节数
+两者之差为积压在网络中的数据, 除以客户端的接收速率即排队时延
+时延高或客户端来不及显示时降低码率和帧率, 时延低时逐步提高
+接收速率受限于发送了多少, 画面静止或跳帧时不代表链路容量:
+上次回报前发出的数据到这次仍未收完, 说明整个间隔内链路都在排队, 此时才按接收速率估计时延和容量
+刚发出的一个关键帧还在路上不算积压
+上一间隔实际发送接近目标码率, 且这些数据已无排队地收完, 才说明链路承受得了, 可以提高码率
+*/
+
+// 码率范围, kbps
+pub const MIN_BITRATE: u32 = 256;
+pub const START_BITRATE: u32 = 2048;
+pub const MAX_BITRATE: u32 = 20480;
+// 帧率范围
+pub const MIN_FPS: u32 = 5;
+pub const MAX_FPS: u32 = 30;
+
+// I444的色度数据是I420的4倍, 码率升到I444_ON才启用, 降到I444_OFF以下退回I420
+// 两个阈值之间保持不变, 避免码率波动时反复切换, 每次切换都要等关键帧
+pub const I444_ON: u32 = 6144;
+pub const I444_OFF: u32 = 4096;
+
+// 排队时延超过HIGH降码率, 超过SEVERE同时降帧率, 低于LOW升码率
+const HIGH: Duration = Duration::from_millis(250);
+const SEVERE: Duration = Duration::from_millis(500);
+const LOW: Duration = Duration::from_millis(60);
+// 客户端积压的帧数超过此值说明解码显示跟不上
+const MAX_PENDING: u16 = 2;
+// 间隔内实际发送不到目标码率的这一比例时, 视为发送受限, 链路是否能承受更高码率未知
+const APP_LIMITED: f64 = 0.5;
+
+/**
+ * 单个会话的码率帧率控制
+ */
+pub struct Rate {
+    // 已写入连接的视频字节数, 由发送线程累加
+    sent: Arc<AtomicU64>,
+    bitrate: u32,
+    fps: u32,
+    // 上次回报的时间, 累计接收字节数和当时已发送的字节数
+    last: Option<(Instant, u64, u64)>,
+    // 上一间隔是否发送受限
+    app_limited: bool,
+}
+
+impl Rate {
+    pub fn new(sent: Arc<AtomicU64>) -> Rate {
+        Rate {
+            sent,
+            bitrate: START_BITRATE,
+            fps: MAX_FPS,
+            last: None,
+            app_limited: true,
+        }
+    }
+
+    /**
+     * 处理一次客户端回报, 返回新的 (码率, 帧率)
+     */
+    pub fn update(&mut self, now: Instant, received: u64, pending: u16) -> (u32, u32) {
+        let sent = self.sent.load(Ordering::Relaxed);
+        let last = self.last.replace((now, received, sent));
+        let (then, before, sent_before) = match last {
+            Some(last) => last,
+            None => return (self.bitrate, self.fps),
+        };
+        let secs = now.duration_since(then).as_secs_f64();
+        if secs <= 0.0 {
+            return (self.bitrate, self.fps);
+        }
+        // 接收速率, 字节每秒
+        let rate = received.saturating_sub(before) as f64 / secs;
+        let backlog = sent.saturating_sub(received) as f64;
+        // 上次回报前发出仍未收到的数据, 有则链路整个间隔都在排队, 接收速率即链路容量
+        let queued = sent_before > received;
+        let delay = if !queued {
+            Duration::ZERO
+        } else if rate > 0.0 {
+            Duration::from_secs_f64((backlog / rate).min(60.0))
+        } else {
+            Duration::MAX
+        };
+        // 间隔内实际发送的码率, kbps, 这些数据到下次回报才知道是否顺利收完
+        let offered = sent.saturating_sub(sent_before) as f64 * 8.0 / 1000.0 / secs;
+        let app_limited = std::mem::replace(&mut self.app_limited, offered < self.bitrate as f64 * APP_LIMITED);
+
+        if delay > HIGH || pending > MAX_PENDING {
+            // 乘性减, 链路排队时不超过实测容量
+            let mut bitrate = self.bitrate * 3 / 4;
+            if queued && rate > 0.0 {
+                bitrate = bitrate.min((rate * 8.0 / 1000.0 * 0.85) as u32);
+            }
+            self.bitrate = bitrate.max(MIN_BITRATE);
+            if delay > SEVERE || pending > MAX_PENDING {
+                self.fps = (self.fps * 2 / 3).max(MIN_FPS);
+            }
+        } else if delay < LOW && !app_limited {
+            // 加性增, 只在上一间隔编码器确实用满了目标码率时
+            self.bitrate = (self.bitrate + self.bitrate / 20 + 64).min(MAX_BITRATE);
+            self.fps = (self.fps + 2).min(MAX_FPS);
+        }
+        (self.bitrate, self.fps)
+    }
+}
+
+/**
+ * 按码率决定是否使用I444, current为当前是否使用
+ */
+pub fn i444(current: bool, bitrate: u32) -> bool {
+    if current {
+        bitrate >= I444_OFF
+    } else {
+        bitrate >= I444_ON
+    }
+}
+
+#[test]
+fn test_i444() {
+    assert!(!i444(false, START_BITRATE));
+    assert!(i444(false, I444_ON));
+    assert!(i444(true, I444_OFF + 1));
+    assert!(!i444(false, I444_OFF + 1));
+    assert!(!i444(true, I444_OFF - 1));
+}
+
+/**
+ * 测试用的链路: 每次回报间隔500毫秒, 编码器按目标码率的ratio发送, 链路每秒最多传输capacity字节
+ */
+#[cfg(test)]
+struct Link {
+    sent: Arc<AtomicU64>,
+    rate: Rate,
+    start: Instant,
+    ms: u64,
+    received: u64,
+}
+
+#[cfg(test)]
+impl Link {
+    fn new() -> Link {
+        let sent = Arc::new(AtomicU64::new(0));
+        let mut rate = Rate::new(sent.clone());
+        let start = Instant::now();
+        assert_eq!(rate.update(start, 0, 0), (START_BITRATE, MAX_FPS));
+        Link {
+            sent,
+            rate,
+            start,
+            ms: 0,
+            received: 0,
+        }
+    }
+
+    fn step(&mut self, ratio: f64, capacity: u64, pending: u16) -> (u32, u32) {
+        let bytes = (self.rate.bitrate as f64 * 1000.0 / 8.0 / 2.0 * ratio) as u64;
+        let sent = self.sent.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        self.received = sent.min(self.received + capacity / 2);
+        self.ms += 500;
+        let now = self.start + Duration::from_millis(self.ms);
+        self.rate.update(now, self.received, pending)
+    }
+}
+
+#[test]
+fn test_rate() {
+    let mut link = Link::new();
+
+    // 链路通畅且编码器用满码率, 码率逐步上升直到上限
+    let mut last = START_BITRATE;
+    for _ in 1..200 {
+        let (bitrate, fps) = link.step(0.9, u64::MAX / 4, 0);
+        assert!(bitrate >= last && fps == MAX_FPS);
+        last = bitrate;
+    }
+    assert_eq!(last, MAX_BITRATE);
+
+    // 链路只有200KB/s, 排队后码率降到实测容量以下, 帧率也降低
+    let mut state = (MAX_BITRATE, MAX_FPS);
+    for _ in 0..4 {
+        state = link.step(1.0, 200_000, 0);
+    }
+    let (bitrate, fps) = state;
+    assert!(bitrate <= 1600 * 85 / 100);
+    assert!(fps < MAX_FPS);
+
+    // 客户端显示跟不上
+    let (b2, f2) = link.step(0.0, 200_000, 5);
+    assert!(b2 < bitrate && f2 < fps);
+
+    // 持续拥塞不低于下限
+    for _ in 0..50 {
+        let (bitrate, fps) = link.step(1.0, 10_000, 0);
+        assert!(bitrate >= MIN_BITRATE && fps >= MIN_FPS);
+    }
+    assert_eq!(link.step(1.0, 10_000, 0), (MIN_BITRATE, MIN_FPS));
+}
+
+#[test]
+fn test_rate_idle() {
+    let mut link = Link::new();
+    for _ in 0..10 {
+        link.step(0.9, 1_000_000, 0);
+    }
+    // 第一次静止的回报评价的是之前繁忙的间隔
+    let busy = link.step(0.01, 1_000_000, 0);
+
+    // 画面静止时几乎不发送, 链路能否承受更高码率未知, 不提高
+    for _ in 0..20 {
+        assert_eq!(link.step(0.01, 1_000_000, 0), busy);
+    }
+
+    // 静止后的关键帧在回报前刚发出, 只收到一小部分, 不是持续积压
+    let sent = link.sent.fetch_add(300_000, Ordering::Relaxed) + 300_000;
+    link.ms += 500;
+    let now = link.start + Duration::from_millis(link.ms);
+    assert_eq!(link.rate.update(now, link.received + 20_000, 0), busy);
+    // 下一次回报前收完, 链路确实承受了这一突发, 码率不降
+    link.received = sent;
+    let (bitrate, fps) = link.step(0.0, 1_000_000, 0);
+    assert!(bitrate >= busy.0 && fps == busy.1);
+    let busy = link.step(0.0, 1_000_000, 0);
+
+    // 关键帧之后仍收不完, 才按排队降码率
+    link.sent.fetch_add(600_000, Ordering::Relaxed);
+    link.step(0.0, 100_000, 0);
+    let (bitrate, _) = link.step(0.0, 100_000, 0);
+    assert!(bitrate < busy.0);
+}
@@ -1,119 +1,525 @@
 use scrap::Capturer;
 use scrap::Display;
+use std::io;
 use std::io::ErrorKind::WouldBlock;
 use std::slice::from_raw_parts;
-use std::sync::LazyLock;
-use std::sync::Mutex;
 use std::time::Duration;
+use std::time::Instant;
 
-use crate::convert;
+use dscom::convert;
+use dscom::convert::ColorSpace;
+use dscom::proto;
+use dscom::proto::Monitor;
 
-static mut CAP: LazyLock<Mutex<Cap>> = LazyLock::new(||{
-    Mutex::new(Cap::new()) 
-});
+use crate::diff::Diff;
+use crate::synthetic::Synthetic;
 
-pub fn cap_wh() -> Option<(usize, usize)> {
-    unsafe {
-        match CAP.lock() {
-            Ok(cap)=> {
-                Some(cap.wh())
-            }
-            Err(_) => {
-                None
-            }
+/**
+ * 是否为Wayland会话, scrap在Wayland下截不到画面, 光标也无法单独采集
+ */
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn wayland_session() -> bool {
+    std::env::var("XDG_SESSION_TYPE").is_ok_and(|t| t == "wayland") || std::env::var_os("WAYLAND_DISPLAY").is_some()
+}
+
+/**
+ * 画面的像素格式, 每像素4字节
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// 内存中依次为B G R A, A可能未使用
+    Bgra,
+    /// 内存中依次为R G B A, 转换前交换R和B
+    Rgba,
+}
+
+/**
+ * 截到的一帧
+ */
+pub struct Frame<'a> {
+    pub data: &'a [u8],
+    pub width: usize,
+    pub height: usize,
+    // 每行的字节数, 可能大于width * 4
+    pub stride: usize,
+    pub format: Format,
+    // 截屏时刻, 编码时作为pts
+    pub timestamp: Instant,
+}
+
+/**
+ * 单个显示器的截屏来源
+ */
+pub trait CaptureSource {
+    /**
+     * 取一帧, 暂时没有新画面时返回WouldBlock
+     */
+    fn frame(&mut self) -> io::Result<Frame<'_>>;
+}
+
+/**
+ * 截屏来源的选择, 由启动参数capture=指定
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// scrap, Wayland会话下为portal
+    Auto,
+    /// X11共享内存, 可用于Xvfb
+    #[cfg(all(unix, not(target_os = "macos")))]
+    X11,
+    /// 按帧号生成的合成画面, 不需要显示器
+    Synthetic { width: u16, height: u16 },
+}
+
+impl CaptureSource for Capturer {
+    fn frame(&mut self) -> io::Result<Frame<'_>> {
+        let (width, height) = (self.width(), self.height());
+        let buffer = Capturer::frame(self)?;
+        // 截屏器按对齐分配行宽, scrap只给出整帧数据, 不提供DXGI的pitch或macOS的bytes_per_row
+        // 每行的字节数只能由整帧长度得出, 不足一行像素时说明推算有误
+        let stride = buffer.len() / height.max(1);
+        if stride < width * 4 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "short scrap frame"));
         }
+        Ok(Frame {
+            data: unsafe { from_raw_parts(buffer.as_ptr(), buffer.len()) },
+            width,
+            height,
+            stride,
+            format: Format::Bgra,
+            timestamp: Instant::now(),
+        })
     }
 }
 
-pub fn cap_screen(yuv: &mut Vec<u8>) -> Option<(usize, usize)> {
-    unsafe {
-        match CAP.lock() {
-            Ok(mut cap)=> {
-                let (bgra, width, height) = cap.cap();
-                convert::bgra_to_i420(width, height, &bgra, yuv);
-                Some((width, height))
-            }
-            Err(_) => {
-                None
-            }
+/**
+ * 打开第index个显示器的截屏来源, 编号与monitors()一致
+ */
+fn open_source(source: Source, index: usize) -> io::Result<Box<dyn CaptureSource>> {
+    match source {
+        Source::Synthetic { width, height } => {
+            return Ok(Box::new(Synthetic::new(width as usize, height as usize)));
         }
+        #[cfg(all(unix, not(target_os = "macos")))]
+        Source::X11 => return Ok(Box::new(crate::x11::Capturer::new(index)?)),
+        Source::Auto => {}
+    }
+    #[cfg(all(feature = "wayland", unix, not(target_os = "macos")))]
+    if wayland_session() {
+        return Ok(Box::new(crate::wayland::Capturer::new(index)?));
+    }
+    let display = Display::all()?
+        .into_iter()
+        .nth(index)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no display"))?;
+    Ok(Box::new(Capturer::new(display)?))
+}
+
+/**
+ * 枚举截屏来源的显示器, 下标即SelectMonitor的编号
+ */
+pub fn monitors(source: Source) -> Vec<Monitor> {
+    match source {
+        Source::Synthetic { width, height } => vec![Monitor {
+            x: 0,
+            y: 0,
+            width,
+            height,
+            primary: true,
+        }],
+        #[cfg(all(unix, not(target_os = "macos")))]
+        Source::X11 => crate::x11::monitors(),
+        Source::Auto => displays(),
+    }
+}
+
+/**
+ * 枚举显示器
+ * Wayland下为portal授权的各路画面
+ */
+#[cfg(all(unix, not(target_os = "macos")))]
+fn displays() -> Vec<Monitor> {
+    use scrap::x11::Server;
+    use std::rc::Rc;
+
+    #[cfg(feature = "wayland")]
+    if wayland_session() {
+        return crate::wayland::monitors();
     }
-    
+
+    // 与Display::all同一枚举顺序, 额外取得各显示器在根窗口中的位置
+    let server = match Server::default() {
+        Ok(server) => Rc::new(server),
+        Err(_) => return Vec::new(),
+    };
+    Server::displays(server)
+        .map(|d| {
+            let r = d.rect();
+            Monitor {
+                x: r.x as i32,
+                y: r.y as i32,
+                width: r.w,
+                height: r.h,
+                primary: d.is_default(),
+            }
+        })
+        .collect()
+}
+
+/**
+ * 枚举显示器
+ * scrap不提供显示器位置, 按枚举顺序从左到右排列, 第一个为主显示器
+ */
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+fn displays() -> Vec<Monitor> {
+    let mut x = 0;
+    Display::all()
+        .unwrap_or_default()
+        .iter()
+        .enumerate()
+        .map(|(i, d)| {
+            let m = Monitor {
+                x,
+                y: 0,
+                width: d.width() as u16,
+                height: d.height() as u16,
+                primary: i == 0,
+            };
+            x += d.width() as i32;
+            m
+        })
+        .collect()
+}
+
+/**
+ * 主显示器的编号
+ */
+pub fn primary(monitors: &[Monitor]) -> u8 {
+    monitors.iter().position(|m| m.primary).unwrap_or(0) as u8
 }
 
+/**
+ * 画面左上角在虚拟桌面中的位置
+ */
+pub fn origin(monitors: &[Monitor], monitor: u8) -> (i32, i32) {
+    let m = if monitor == proto::ALL_MONITORS {
+        proto::desktop(monitors)
+    } else {
+        monitors.get(monitor as usize).copied()
+    };
+    m.map(|m| (m.x, m.y)).unwrap_or((0, 0))
+}
 
 /**
- * 截屏
+ * 截屏, 查看全部显示器时把各显示器拼接到canvas
  */
 pub struct Cap {
+    source: Source,
+    monitor: u8,
     w: usize,
     h: usize,
-    capturer: Option<Capturer>,
+    // 各显示器及其在画面中的位置
+    capturers: Vec<(Box<dyn CaptureSource>, usize, usize)>,
+    // 各显示器是否已截到过画面
+    filled: Vec<bool>,
+    // 拼接后或交换R和B后的画面
+    canvas: Vec<u8>,
+    // 拼接画面中最新一帧的截屏时刻
+    latest: Instant,
+    // 上次转换出的yuv的宽高, 没有新画面时据此判断能否重新编码
+    yuv_wh: (usize, usize),
     sleep: Duration,
 }
 impl Cap {
-    pub fn new() -> Cap {
-        let display = Display::primary().unwrap();
-        let capturer = Capturer::new(display).unwrap();
-        let (w, h) = (capturer.width(), capturer.height());
-        Cap {
-            w,
-            h,
-            capturer: Some(capturer),
+    pub fn new(source: Source, monitor: u8) -> Cap {
+        let mut cap = Cap {
+            source,
+            monitor,
+            w: 0,
+            h: 0,
+            capturers: Vec::new(),
+            filled: Vec::new(),
+            canvas: Vec::new(),
+            latest: Instant::now(),
+            yuv_wh: (0, 0),
             sleep: Duration::new(1, 0) / 60,
+        };
+        cap.open();
+        cap
+    }
+    fn open(&mut self) {
+        let monitors = monitors(self.source);
+        let mut picked: Vec<(usize, Monitor)> = monitors.iter().copied().enumerate().collect();
+        if self.monitor != proto::ALL_MONITORS {
+            let mut i = self.monitor as usize;
+            if i >= picked.len() {
+                // 显示器已拔出
+                println!("No monitor {}, use primary", self.monitor);
+                i = primary(&monitors) as usize;
+            }
+            picked = picked.into_iter().skip(i).take(1).collect();
+        }
+        let desktop = match proto::desktop(&picked.iter().map(|(_, m)| *m).collect::<Vec<_>>()) {
+            Some(desktop) => desktop,
+            None => return,
+        };
+        for (index, m) in picked {
+            let capturer = match open_source(self.source, index) {
+                Ok(capturer) => capturer,
+                Err(_) => {
+                    self.capturers.clear();
+                    return;
+                }
+            };
+            let (x, y) = ((m.x - desktop.x) as usize, (m.y - desktop.y) as usize);
+            self.capturers.push((capturer, x, y));
         }
+        self.w = desktop.width as usize;
+        self.h = desktop.height as usize;
+        self.filled = vec![false; self.capturers.len()];
+        self.canvas.clear();
+        self.canvas.resize(self.w * self.h * 4, 0);
     }
     fn reload(&mut self) {
         println!("Reload capturer");
-        drop(self.capturer.take());
-        let display = match Display::primary() {
-            Ok(display) => display,
-            Err(_) => {
-                return;
-            }
-        };
-
-        let capturer = match Capturer::new(display) {
-            Ok(capturer) => capturer,
-            Err(_) => return,
-        };
-        self.h = capturer.height();
-        self.w = capturer.width();
-        self.capturer = Some(capturer);
+        self.capturers.clear();
+        self.open();
     }
     pub fn wh(&self) -> (usize, usize) {
         (self.w, self.h)
     }
+    /**
+     * 截取一帧, 与上一帧相比有变化或force时按颜色空间color转换为I420, i444为true时转换为I444
+     * 返回宽高, 需要编码时附带截屏时刻, 否则yuv保持不变
+     * 没有新画面且force时, yuv为上次转换的画面且宽高未变则返回当前时刻, 重新编码上一帧
+     */
+    pub fn cap_yuv(
+        &mut self,
+        yuv: &mut Vec<u8>,
+        i444: bool,
+        color: ColorSpace,
+        diff: &mut Diff,
+        force: bool,
+    ) -> (usize, usize, Option<Instant>) {
+        let frame = match self.cap() {
+            Some(frame) => frame,
+            None => {
+                let last = force && self.yuv_wh == (self.w, self.h) && !yuv.is_empty();
+                return (self.w, self.h, if last { Some(Instant::now()) } else { None });
+            }
+        };
+        let (bgra, width, height, stride) = (frame.data, frame.width, frame.height, frame.stride);
+        if diff.update(bgra, width, height, stride) == 0 && !force {
+            return (width, height, None);
+        }
+        if i444 {
+            convert::bgra_to_i444_par(width, height, bgra, stride, yuv, color);
+        } else {
+            convert::bgra_to_i420_par(width, height, bgra, stride, yuv, color);
+        }
+        let timestamp = frame.timestamp;
+        self.yuv_wh = (width, height);
+        (width, height, Some(timestamp))
+    }
+    /**
+     * 截取一帧, 返回的画面总是BGRA
+     * 没有新画面时等待一次后返回None, Wayland portal和DXGI只在画面变化时出帧
+     */
     #[inline]
-    pub fn cap(&mut self) -> (&[u8], usize, usize) {
-        loop {
-            match &mut self.capturer {
-                Some(capturer) => {
-                    // Wait until there's a frame.
-                    let cp = capturer.frame();
-                    let buffer = match cp {
-                        Ok(buffer) => buffer,
-                        Err(error) => {
-                            std::thread::sleep(self.sleep);
-                            if error.kind() == WouldBlock {
-                                // Keep spinning.
-                                continue;
-                            } else {
-                                std::thread::sleep(std::time::Duration::from_millis(200));
-                                self.reload();
-                                continue;
-                            }
-                        }
-                    };
-                    return (unsafe { from_raw_parts(buffer.as_ptr(), buffer.len()) }, self.w, self.h);
-                    // return (buffer.as_ptr(), buffer.len());
+    pub fn cap(&mut self) -> Option<Frame<'_>> {
+        if self.capturers.is_empty() {
+            std::thread::sleep(std::time::Duration::from_millis(200));
+            self.reload();
+            return None;
+        }
+        if self.capturers.len() > 1 {
+            return match self.compose() {
+                Ok(true) => Some(Frame {
+                    data: &self.canvas,
+                    width: self.w,
+                    height: self.h,
+                    stride: self.w * 4,
+                    format: Format::Bgra,
+                    timestamp: self.latest,
+                }),
+                Ok(false) => {
+                    std::thread::sleep(self.sleep);
+                    None
                 }
-                None => {
+                Err(_) => {
                     std::thread::sleep(std::time::Duration::from_millis(200));
                     self.reload();
-                    continue;
+                    None
                 }
             };
         }
+        let capturer = &mut self.capturers[0].0;
+        let frame = match capturer.frame() {
+            Ok(frame) => frame,
+            Err(error) => {
+                std::thread::sleep(self.sleep);
+                if error.kind() != WouldBlock {
+                    std::thread::sleep(std::time::Duration::from_millis(200));
+                    self.reload();
+                }
+                return None;
+            }
+        };
+        // 后端的实际分辨率可能与显示器信息不同, 以画面为准
+        self.w = frame.width;
+        self.h = frame.height;
+        if frame.format != Format::Bgra {
+            self.canvas.resize(self.w * self.h * 4, 0);
+            blit(&mut self.canvas, self.w, &frame, 0, 0);
+            return Some(Frame {
+                data: &self.canvas,
+                stride: self.w * 4,
+                format: Format::Bgra,
+                ..frame
+            });
+        }
+        Some(Frame {
+            data: unsafe { from_raw_parts(frame.data.as_ptr(), frame.data.len()) },
+            ..frame
+        })
+    }
+    /**
+     * 拼接各显示器, 有更新且每个显示器都截到过画面时返回true
+     */
+    fn compose(&mut self) -> std::io::Result<bool> {
+        let mut updated = false;
+        for (i, (capturer, x, y)) in self.capturers.iter_mut().enumerate() {
+            match capturer.frame() {
+                Ok(frame) => {
+                    blit(&mut self.canvas, self.w, &frame, *x, *y);
+                    self.latest = frame.timestamp;
+                    self.filled[i] = true;
+                    updated = true;
+                }
+                Err(error) if error.kind() == WouldBlock => {}
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(updated && self.filled.iter().all(|f| *f))
+    }
+}
+
+/**
+ * 把画面复制到宽dst_w的BGRA画布(x, y)处, 超出画布的部分丢弃
+ */
+fn blit(dst: &mut [u8], dst_w: usize, src: &Frame, x: usize, y: usize) {
+    if src.height == 0 || x >= dst_w {
+        return;
+    }
+    let n = src.width.min(dst_w - x) * 4;
+    for row in 0..src.height {
+        let d = ((y + row) * dst_w + x) * 4;
+        if d + n > dst.len() {
+            return;
+        }
+        let s = row * src.stride;
+        let line = &mut dst[d..d + n];
+        line.copy_from_slice(&src.data[s..s + n]);
+        if src.format == Format::Rgba {
+            for p in line.chunks_exact_mut(4) {
+                p.swap(0, 2);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_blit() {
+    // 2x2画布, 1x2的画面放在右侧, 源画面每行有4字节对齐填充
+    let mut canvas = vec![0u8; 2 * 2 * 4];
+    let mut src = Frame {
+        data: &[1, 1, 1, 1, 9, 9, 9, 9, 2, 2, 2, 2, 9, 9, 9, 9],
+        width: 1,
+        height: 2,
+        stride: 8,
+        format: Format::Bgra,
+        timestamp: Instant::now(),
+    };
+    blit(&mut canvas, 2, &src, 1, 0);
+    assert_eq!(canvas, [0, 0, 0, 0, 1, 1, 1, 1, 0, 0, 0, 0, 2, 2, 2, 2]);
+    // 越界部分丢弃
+    blit(&mut canvas, 2, &src, 0, 1);
+    assert_eq!(&canvas[8..12], &[1, 1, 1, 1]);
+    // RGBA交换R和B
+    src.data = &[1, 2, 3, 4, 9, 9, 9, 9, 5, 6, 7, 8, 9, 9, 9, 9];
+    src.format = Format::Rgba;
+    blit(&mut canvas, 2, &src, 0, 0);
+    assert_eq!(&canvas[..4], &[3, 2, 1, 4]);
+    assert_eq!(&canvas[8..12], &[7, 6, 5, 8]);
+}
+
+#[test]
+fn test_origin() {
+    let monitors = [
+        Monitor {
+            x: 0,
+            y: 0,
+            width: 1920,
+            height: 1080,
+            primary: false,
+        },
+        Monitor {
+            x: -1280,
+            y: 100,
+            width: 1280,
+            height: 1024,
+            primary: true,
+        },
+    ];
+    assert_eq!(primary(&monitors), 1);
+    assert_eq!(primary(&[]), 0);
+    assert_eq!(origin(&monitors, 1), (-1280, 100));
+    assert_eq!(origin(&monitors, proto::ALL_MONITORS), (-1280, 0));
+    assert_eq!(origin(&monitors, 5), (0, 0));
+}
+
+#[test]
+fn test_cap_idle() {
+    // 只出一帧, 之后画面不变时不再出帧, 与portal和DXGI相同
+    struct Idle(Option<Vec<u8>>, Vec<u8>);
+    impl CaptureSource for Idle {
+        fn frame(&mut self) -> io::Result<Frame<'_>> {
+            self.1 = self.0.take().ok_or_else(|| io::Error::from(WouldBlock))?;
+            Ok(Frame {
+                data: &self.1,
+                width: 4,
+                height: 2,
+                stride: 16,
+                format: Format::Bgra,
+                timestamp: Instant::now(),
+            })
+        }
     }
+    let mut cap = Cap {
+        source: Source::Auto,
+        monitor: 0,
+        w: 4,
+        h: 2,
+        capturers: vec![(Box::new(Idle(Some(vec![200; 32]), Vec::new())), 0, 0)],
+        filled: vec![false],
+        canvas: Vec::new(),
+        latest: Instant::now(),
+        yuv_wh: (0, 0),
+        sleep: Duration::from_millis(1),
+    };
+    let mut yuv = Vec::new();
+    let mut diff = Diff::new();
+    let color = ColorSpace::default();
+    assert!(cap.cap_yuv(&mut yuv, false, color, &mut diff, false).2.is_some());
+    let first = yuv.clone();
+    // 没有新画面时不阻塞
+    assert_eq!(cap.cap_yuv(&mut yuv, false, color, &mut diff, false), (4, 2, None));
+    // force时重新编码上一帧
+    let (w, h, fresh) = cap.cap_yuv(&mut yuv, false, color, &mut diff, true);
+    assert_eq!((w, h), (4, 2));
+    assert!(fresh.is_some());
+    assert_eq!(yuv, first);
+    // 宽高变化后旧的yuv不能重新编码
+    cap.w = 6;
+    assert_eq!(cap.cap_yuv(&mut yuv, false, color, &mut diff, true), (6, 2, None));
 }
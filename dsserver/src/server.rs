@@ -1,109 +1,139 @@
+use enigo::agent::Token;
 use enigo::Axis;
 use enigo::Coordinate;
 use enigo::Direction;
-use enigo::Enigo;
-use enigo::Keyboard;
-use enigo::Mouse;
-use enigo::Settings;
 
+use crate::cursor;
+use crate::files::Files;
 use crate::key_mouse;
+use crate::limit::Limiter;
+use crate::rate::Rate;
 use crate::screen;
-use std::collections::hash_map::DefaultHasher;
-use std::hash::Hasher;
+use crate::session;
+use dscom::auth::Challenge;
+use dscom::auth::Level;
+use dscom::clipboard::Clipboard;
+use dscom::convert::ColorSpace;
+use dscom::proto;
+use dscom::proto::Codec;
+use dscom::proto::Message;
+use dscom::proto::Monitor;
+use dscom::stream::Stream;
+use dscom::stream::Writer;
+use dscom::tls::ServerConfig;
+use dscom::tls::TlsStream;
 use std::io::Read;
-use std::io::Write;
 use std::net::TcpListener;
 use std::net::TcpStream;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
 use std::sync::mpsc::channel;
-use std::sync::LazyLock;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
 use std::sync::Mutex;
-use std::thread;
 use std::time;
 
-pub fn run(port: u16, pwd: String) {
-    let mut hasher = DefaultHasher::new();
-    hasher.write(pwd.as_bytes());
-    let pk = hasher.finish();
-    let suc = [
-        (pk >> (7 * 8)) as u8,
-        (pk >> (6 * 8)) as u8,
-        (pk >> (5 * 8)) as u8,
-        (pk >> (4 * 8)) as u8,
-        (pk >> (3 * 8)) as u8,
-        (pk >> (2 * 8)) as u8,
-        (pk >> (1 * 8)) as u8,
-        pk as u8,
-    ];
+// 同一地址连续失败次数上限及锁定时长
+const MAX_AUTH_FAILS: u32 = 5;
+const AUTH_LOCK: time::Duration = time::Duration::from_secs(60);
+// TLS及认证阶段读超时
+const AUTH_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+// 剪贴板轮询间隔
+const CLIP_POLL: time::Duration = time::Duration::from_millis(500);
+// 同一会话关键帧请求的最小间隔, 关键帧较大且所有查看同一显示器的会话都会收到
+const KEYFRAME_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
+/**
+ * 启动参数
+ */
+pub struct Config {
+    pub port: u16,
+    // 各权限的密码
+    pub pwds: Vec<(Level, String)>,
+    pub tls: Option<Arc<ServerConfig>>,
+    pub caps: u32,
+    // 文件传输目录
+    pub files: PathBuf,
+    pub color: ColorSpace,
+    pub source: screen::Source,
+}
+
+/**
+ * 监听端口并处理连接, input为输入线程的发送端
+ */
+pub fn run(config: Config, input: Sender<Token>) {
+    let mgr = session::Manager::new(config.color, config.source);
+    let port = config.port;
     let (tx6, rx) = channel::<TcpStream>();
     if cfg!(target_os = "windows") {
         let tx4 = tx6.clone();
         std::thread::spawn(move || {
             let listener_ipv4 = TcpListener::bind(format!("0.0.0.0:{}", port)).unwrap();
-            for sr in listener_ipv4.incoming() {
-                match sr {
-                    Ok(stream) => {
-                        tx4.send(stream).unwrap();
-                    }
-                    Err(e) => {
-                        println!("error {}", e);
-                    }
-                }
-            }
+            incoming(listener_ipv4, tx4);
         });
     }
     std::thread::spawn(move || {
         let listener_ipv6 = TcpListener::bind(format!("[::0]:{}", port)).unwrap();
-        for sr in listener_ipv6.incoming() {
-            match sr {
-                Ok(stream) => {
-                    tx6.send(stream).unwrap();
-                }
-                Err(e) => {
-                    println!("error {}", e);
+        incoming(listener_ipv6, tx6);
+    });
+
+    let console_mgr = mgr.clone();
+    std::thread::spawn(move || {
+        console(console_mgr);
+    });
+
+    dispatch(rx, config, mgr, input);
+}
+
+/**
+ * 在已绑定的listener上后台处理连接, 忽略config.port, 不读取控制台命令
+ * 用于在测试进程内启动服务端
+ */
+pub fn spawn(listener: TcpListener, config: Config, input: Sender<Token>) {
+    let mgr = session::Manager::new(config.color, config.source);
+    let (tx, rx) = channel::<TcpStream>();
+    std::thread::spawn(move || incoming(listener, tx));
+    std::thread::spawn(move || dispatch(rx, config, mgr, input));
+}
+
+/**
+ * 接受连接转给dispatch
+ */
+fn incoming(listener: TcpListener, tx: Sender<TcpStream>) {
+    for sr in listener.incoming() {
+        match sr {
+            Ok(stream) => {
+                if tx.send(stream).is_err() {
+                    return;
                 }
             }
+            Err(e) => {
+                println!("error {}", e);
+            }
         }
-    });
+    }
+}
 
+/**
+ * 每个连接一个线程
+ */
+fn dispatch(rx: Receiver<TcpStream>, config: Config, mgr: Arc<session::Manager>, input: Sender<Token>) {
+    let limiter = Arc::new(Mutex::new(Limiter::new(MAX_AUTH_FAILS, AUTH_LOCK)));
+    let config = Arc::new(config);
     loop {
         match rx.recv() {
-            Ok(mut stream) => {
-                // 检查连接合法性
-                let mut check = [0u8; 8];
-                match stream.read_exact(&mut check) {
-                    Ok(_) => {
-                        if suc != check {
-                            println!("Password error");
-                            let _ = stream.write_all(&[2]);
-                            continue;
-                        }
-                    }
-                    Err(_) => {
-                        println!("Request error");
-                        continue;
-                    }
-                }
-                if let Err(_) = stream.write_all(&[1]) {
-                    continue;
-                }
-                let ss = stream.try_clone().unwrap();
-                let th1 = std::thread::spawn(move || {
-                    if let Err(e) = std::panic::catch_unwind(||{
-                        screen_stream(ss);
-                    }) {
-                        eprintln!("{:?}", e);
-                    }
-                });
-                let th2 = std::thread::spawn(move || {
-                    if let Err(e) = std::panic::catch_unwind(||{
-                        event(stream);
-                    }) {
-                        eprintln!("{:?}", e);
-                    }
+            Ok(tcp) => {
+                let config = config.clone();
+                let limiter = limiter.clone();
+                let mgr = mgr.clone();
+                let input = input.clone();
+                std::thread::spawn(move || {
+                    serve(tcp, &config, &limiter, &mgr, input);
                 });
-                th1.join().unwrap();
-                th2.join().unwrap();
-                println!("Break !");
             }
             Err(_) => {
                 return;
@@ -112,168 +142,546 @@ pub fn run(port: u16, pwd: String) {
     }
 }
 
+/**
+ * 单个连接: 握手认证后加入会话
+ */
+fn serve(tcp: TcpStream, config: &Config, limiter: &Mutex<Limiter>, mgr: &Arc<session::Manager>, input: Sender<Token>) {
+    let _ = tcp.set_read_timeout(Some(AUTH_TIMEOUT));
+    let mut stream = match config.tls.clone() {
+        Some(config) => match TlsStream::accept(tcp, config) {
+            Ok(s) => Stream::Tls(s),
+            Err(e) => {
+                println!("TLS error {}", e);
+                return;
+            }
+        },
+        None => Stream::Tcp(tcp),
+    };
+    // 握手
+    let caps = match proto::server_hello(&mut stream, config.caps) {
+        Ok(caps) => caps,
+        Err(e) => {
+            println!("Handshake error {}", e);
+            return;
+        }
+    };
+    // 检查连接合法性
+    let level = match authenticate(&mut stream, &config.pwds, limiter) {
+        Some(level) => level,
+        None => return,
+    };
+    let _ = stream.set_read_timeout(None);
+    // 显示器, 默认查看主显示器
+    let monitors = screen::monitors(mgr.source());
+    if caps & proto::CAP_MONITOR != 0 && Message::Monitors(monitors.clone()).write_to(&mut stream).is_err() {
+        return;
+    }
+    let monitor = screen::primary(&monitors);
+    // 剪贴板, 只读会话不能写入服务端剪贴板
+    let clip = if caps & (proto::CAP_CLIP_UP | proto::CAP_CLIP_DOWN) != 0 {
+        match Clipboard::new() {
+            Ok(c) => Some(Arc::new(Mutex::new(c))),
+            Err(e) => {
+                println!("Clipboard unavailable {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let clip_down = clip.clone().filter(|_| caps & proto::CAP_CLIP_DOWN != 0);
+    let clip_up = clip.filter(|_| caps & proto::CAP_CLIP_UP != 0 && level >= Level::Full);
+    // 画面/剪贴板/文件共用的发送端
+    let writer = Writer::new(stream.try_clone().unwrap()).unwrap();
+    let files = if caps & proto::CAP_FILE != 0 && level >= Level::Full {
+        Some(Files::new(config.files.clone(), writer.clone()))
+    } else {
+        None
+    };
+    let codec = Codec::from_caps(caps);
+    let i444 = caps & proto::CAP_I444 != 0 && codec == Codec::VP9;
+    let color = caps & proto::CAP_COLOR != 0;
+    let (id, packets) = mgr.add(stream.try_clone().unwrap(), level, monitor, codec, i444, color);
+    // 已发送的视频字节数, 与客户端回报比较得出网络积压
+    let sent = Arc::new(AtomicU64::new(0));
+    let rate = Rate::new(sent.clone());
+    // 查看的显示器左上角, 事件线程切换显示器时更新, 光标坐标据此换算
+    let origin = Arc::new(Mutex::new(screen::origin(&monitors, monitor)));
+    let done = Arc::new(AtomicBool::new(false));
+    let th2 = if caps & proto::CAP_CURSOR != 0 {
+        let (writer, origin, sent, done) = (writer.clone(), origin.clone(), sent.clone(), done.clone());
+        Some(std::thread::spawn(move || cursor_stream(writer, &origin, &sent, &done)))
+    } else {
+        None
+    };
+    let th1 = std::thread::spawn(move || {
+        if let Err(e) = std::panic::catch_unwind(|| {
+            screen_stream(writer, packets, clip_down, &sent, caps & proto::CAP_KEYFRAME != 0);
+        }) {
+            eprintln!("{:?}", e);
+        }
+    });
+    let view = View {
+        mgr: mgr.clone(),
+        id,
+        origin,
+        monitors,
+    };
+    if let Err(e) = std::panic::catch_unwind(move || {
+        event(stream, level, clip_up, files, view, rate, &input);
+    }) {
+        eprintln!("{:?}", e);
+    }
+    mgr.remove(id);
+    th1.join().unwrap();
+    done.store(true, Ordering::Relaxed);
+    if let Some(th2) = th2 {
+        th2.join().unwrap();
+    }
+}
 
-static mut ENIGO: LazyLock<Mutex<Enigo>> =
-    LazyLock::new(|| Mutex::new(Enigo::new(&Settings::default()).unwrap()));
+/**
+ * 控制台命令
+ * list: 列出会话
+ * kick <id>: 断开会话
+ */
+fn console(mgr: Arc<session::Manager>) {
+    for line in std::io::stdin().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        let mut args = line.split_whitespace();
+        match (args.next(), args.next()) {
+            (Some("list"), _) => {
+                for s in mgr.list() {
+                    let secs = s.since.elapsed().unwrap_or_default().as_secs();
+                    let monitor = if s.monitor == proto::ALL_MONITORS {
+                        String::from("all")
+                    } else {
+                        format!("monitor {}", s.monitor)
+                    };
+                    let codec = if s.i444 {
+                        format!("{} 4:4:4", s.codec.name())
+                    } else {
+                        s.codec.name().to_string()
+                    };
+                    println!("{}\t{}\t{}\t{}\t{}\t{}\t{}s", s.id, s.peer, s.level.name(), monitor, codec, s.color.name(), secs);
+                }
+            }
+            (Some("kick"), Some(id)) => match id.parse::<u64>() {
+                Ok(id) if mgr.kick(id) => {}
+                _ => println!("No session {}", id),
+            },
+            (Some(_), _) => {
+                println!("Commands: list, kick <id>");
+            }
+            _ => {}
+        }
+    }
+}
 
 /**
- * 事件处理
+ * 挑战应答认证, 失败过多的地址暂时拒绝, 成功时返回所用密码对应的权限
  */
-fn event(mut stream: TcpStream) {
-    let mut cmd = [0u8];
-    let mut move_cmd = [0u8; 4];
-    while let Ok(_) = stream.read_exact(&mut cmd) {
-        match cmd[0] {
-            dscom::KEY_UP => {
-                stream.read_exact(&mut cmd).unwrap();
-                if let Some(key) = key_mouse::key_to_enigo(cmd[0]) {
-                    unsafe {
-                        if let std::result::Result::Ok(mut eg) = ENIGO.lock() {
-                            let _ = eg.key(key, Direction::Release);
-                        }
-                    }
+fn authenticate(stream: &mut Stream, pwds: &[(Level, String)], limiter: &Mutex<Limiter>) -> Option<Level> {
+    let ip = match stream.peer_addr() {
+        Ok(addr) => addr.ip().to_canonical(),
+        Err(_) => return None,
+    };
+    let fail = |stream: &mut Stream, code: u8| {
+        let _ = Message::AuthResult { code, level: 0 }.write_to(stream);
+    };
+    if !limiter.lock().unwrap().allow(ip) {
+        println!("Too many failures from {}", ip);
+        fail(stream, proto::AUTH_LIMITED);
+        return None;
+    }
+    let challenge = Challenge::new();
+    if Message::Challenge(challenge.nonce()).write_to(stream).is_err() {
+        // 结束占用的尝试
+        limiter.lock().unwrap().fail(ip);
+        return None;
+    }
+    let level = match Message::read_from_limited(stream, proto::MAX_HANDSHAKE) {
+        Ok(Message::Auth(mac)) => {
+            let candidates: Vec<&str> = pwds.iter().map(|(_, pwd)| pwd.as_str()).collect();
+            match challenge.verify_any(&candidates, &mac) {
+                Some(i) => pwds[i].0,
+                None => {
+                    println!("Password error from {}", ip);
+                    limiter.lock().unwrap().fail(ip);
+                    fail(stream, proto::AUTH_FAIL);
+                    return None;
                 }
             }
-            dscom::KEY_DOWN => {
-                stream.read_exact(&mut cmd).unwrap();
-                if let Some(key) = key_mouse::key_to_enigo(cmd[0]) {
-                    unsafe {
-                        if let std::result::Result::Ok(mut eg) = ENIGO.lock() {
-                            let _ = eg.key(key, Direction::Press);
-                        }
-                    }
+        }
+        _ => {
+            println!("Request error from {}", ip);
+            limiter.lock().unwrap().fail(ip);
+            return None;
+        }
+    };
+    limiter.lock().unwrap().success(ip);
+    let ok = Message::AuthResult {
+        code: proto::AUTH_OK,
+        level: level as u8,
+    };
+    ok.write_to(stream).ok()?;
+    Some(level)
+}
+
+/**
+ * 权限是否允许该输入
+ */
+fn permit(level: Level, msg: &Message) -> bool {
+    match msg {
+        Message::KeyUp(_) | Message::KeyDown(_) | Message::Text(_) => level >= Level::Full,
+        Message::MouseKeyUp(_)
+        | Message::MouseKeyDown(_)
+        | Message::MouseWheelUp
+        | Message::MouseWheelDown
+        | Message::Move { .. } => level >= Level::Mouse,
+        _ => true,
+    }
+}
+
+/**
+ * 会话查看的显示器, 鼠标坐标相对于其左上角
+ */
+struct View {
+    mgr: Arc<session::Manager>,
+    id: u64,
+    monitors: Vec<Monitor>,
+    origin: Arc<Mutex<(i32, i32)>>,
+}
+
+/**
+ * 事件处理, 输入消息转为Token发给输入线程
+ */
+fn event<R: Read>(
+    mut stream: R,
+    level: Level,
+    clip: Option<Arc<Mutex<Clipboard>>>,
+    mut files: Option<Files>,
+    mut view: View,
+    mut rate: Rate,
+    input: &Sender<Token>,
+) {
+    let mut last_keyframe: Option<time::Instant> = None;
+    // 输入线程已退出时丢弃输入
+    let inject = |token: Token| {
+        let _ = input.send(token);
+    };
+    while let Ok(msg) = Message::read_from(&mut stream) {
+        if !permit(level, &msg) {
+            continue;
+        }
+        match msg {
+            Message::KeyUp(key) => {
+                if let Some(token) = key_mouse::key_to_token(key, Direction::Release) {
+                    inject(token);
                 }
             }
-            dscom::MOUSE_KEY_UP => {
-                stream.read_exact(&mut cmd).unwrap();
-                if let Some(button) = key_mouse::mouse_to_engin(cmd[0]) {
-                    unsafe {
-                        if let std::result::Result::Ok(mut eg) = ENIGO.lock() {
-                            let _ = eg.button(button, Direction::Release);
-                        }
-                    }
+            Message::KeyDown(key) => {
+                if let Some(token) = key_mouse::key_to_token(key, Direction::Press) {
+                    inject(token);
                 }
             }
-            dscom::MOUSE_KEY_DOWN => {
-                stream.read_exact(&mut cmd).unwrap();
-                if let Some(button) = key_mouse::mouse_to_engin(cmd[0]) {
-                    unsafe {
-                        if let std::result::Result::Ok(mut eg) = ENIGO.lock() {
-                            let _ = eg.button(button, Direction::Press);
-                        }
-                    }
+            Message::Text(text) => {
+                // enigo按平台选择最快的方式直接输入文本, 不经过键盘布局
+                inject(Token::Text(text));
+            }
+            Message::MouseKeyUp(key) => {
+                if let Some(button) = key_mouse::mouse_to_engin(key) {
+                    inject(Token::Button(button, Direction::Release));
                 }
             }
-            dscom::MOUSE_WHEEL_UP => unsafe {
-                if let std::result::Result::Ok(mut eg) = ENIGO.lock() {
-                    let _ = eg.scroll(-2, Axis::Vertical);
+            Message::MouseKeyDown(key) => {
+                if let Some(button) = key_mouse::mouse_to_engin(key) {
+                    inject(Token::Button(button, Direction::Press));
                 }
             }
-            dscom::MOUSE_WHEEL_DOWN => unsafe {
-                if let std::result::Result::Ok(mut eg) = ENIGO.lock() {
-                    let _ = eg.scroll(2, Axis::Vertical);
+            Message::MouseWheelUp => inject(Token::Scroll(-2, Axis::Vertical)),
+            Message::MouseWheelDown => inject(Token::Scroll(2, Axis::Vertical)),
+            Message::Move { x, y } => {
+                let origin = *view.origin.lock().unwrap();
+                let (x, y) = (origin.0 + x as i32, origin.1 + y as i32);
+                inject(Token::MoveMouse(x, y, Coordinate::Abs));
+            }
+            Message::SelectMonitor(monitor) => {
+                // 分辨率或显示器布局可能已变化
+                let monitors = screen::monitors(view.mgr.source());
+                if !monitors.is_empty() {
+                    view.monitors = monitors;
                 }
+                let monitor = if monitor == proto::ALL_MONITORS || (monitor as usize) < view.monitors.len() {
+                    monitor
+                } else {
+                    screen::primary(&view.monitors)
+                };
+                *view.origin.lock().unwrap() = screen::origin(&view.monitors, monitor);
+                view.mgr.select(view.id, monitor);
             }
-            dscom::MOVE => {
-                stream.read_exact(&mut move_cmd).unwrap();
-                let x = ((move_cmd[0] as i32) << 8) | (move_cmd[1] as i32);
-                let y = ((move_cmd[2] as i32) << 8) | (move_cmd[3] as i32);
-                unsafe {
-                    if let std::result::Result::Ok(mut eg) = ENIGO.lock() {
-                        let _ = eg.move_mouse(x, y, Coordinate::Abs);
-                    }
+            Message::KeyframeRequest if last_keyframe.is_none_or(|t| t.elapsed() >= KEYFRAME_INTERVAL) => {
+                last_keyframe = Some(time::Instant::now());
+                view.mgr.request_keyframe(view.id);
+            }
+            Message::Feedback { received, pending } => {
+                let (bitrate, fps) = rate.update(time::Instant::now(), received, pending);
+                view.mgr.target(view.id, bitrate, fps);
+            }
+            Message::Clipboard { kind, data } => {
+                if let Some(clip) = &clip {
+                    clip.lock().unwrap().set(kind, &data);
                 }
             }
-            _ => {
-                return;
+            msg @ (Message::FilePut { .. }
+            | Message::FileData { .. }
+            | Message::FileEnd { .. }
+            | Message::FileGet { .. }
+            | Message::FileList(_)) => {
+                if let Some(files) = &mut files {
+                    files.handle(msg);
+                }
+            }
+            Message::Unknown(kind) => {
+                // 新版本客户端的消息, 跳过
+                println!("Skip unknown message {}", kind);
             }
+            _ => {}
         }
     }
 }
 
-/**
- * 编码数据header
- */
-#[inline]
-fn encode(data_len: usize, res: &mut [u8]) {
-    res[0] = (data_len >> 16) as u8;
-    res[1] = (data_len >> 8) as u8;
-    res[2] = data_len as u8;
-}
-
-
 /*
-图像字节序
+图像流
++------------+
+|    META    |  宽高, 协商CAP_COLOR后附带颜色空间
++------------+
+| VIDEO_KEY  |  关键帧, 未协商CAP_KEYFRAME时为VIDEO
 +------------+
-|     24     |
+|   VIDEO    |  每帧编码数据
 +------------+
-|   length   |
+|    ...     |
 +------------+
-|   data     |
+|    META    |  切换显示器或分辨率变化后的宽高
++------------+
+|   VIDEO    |
 +------------+
-length: 数据长度
-data: 数据
 */
-fn screen_stream(mut stream: TcpStream) {
-    let fps = 30;
-    let spf = time::Duration::from_nanos(1_000_000_000 / fps);
-    // vpxencode
-    let (iw, ih) = screen::cap_wh().unwrap();
-    let ecfg = vpx_codec::encoder::Config {
-        width: iw as _,
-        height: ih as _,
-        timebase: [1, (fps as i32) * 1000], // 120fps
-        bitrate: 8192,
-        codec: vpx_codec::encoder::VideoCodecId::VP8,
-    };
-    let mut enc = vpx_codec::encoder::Encoder::new(ecfg).unwrap();
-    
-
-    let (w, h) = (iw as usize, ih as usize);
-
-    // 发送w, h
-    let mut meta = [0u8; 4];
-    meta[0] = (w >> 8) as u8;
-    meta[1] = w as u8;
-    meta[2] = (h >> 8) as u8;
-    meta[3] = h as u8;
-    if let Err(_) = stream.write_all(&meta) {
-        return;
-    }
-
-    let mut header = [0u8; 3];
-    let start = time::Instant::now();
-    let mut yuv = Vec::<u8>::new();
+fn screen_stream(
+    writer: Writer,
+    packets: Receiver<session::Packet>,
+    clip: Option<Arc<Mutex<Clipboard>>>,
+    sent: &AtomicU64,
+    key_video: bool,
+) {
+    // 已发送的宽高和颜色空间, 客户端收到第一个META前不发送其他消息
+    let mut size = None;
+    let mut next_poll = time::Instant::now();
     loop {
-        let now = time::Instant::now();
-        let time = now - start;
-        let ms = time.as_secs() * 1000 + time.subsec_millis() as u64;
-        match screen::cap_screen(&mut yuv)  {
-            Some((_iw, _ih)) => {
-                if iw != _iw || ih != _ih {
-                    let _ = enc.finish();
-                    println!("encode break work.");
-                    return;
-                }
-
-                for f in enc.encode(ms as i64, &yuv).unwrap() {
-                    let len = f.data.len();
-                    encode(len, &mut header);
-                    if let Err(_) = stream.write_all(&header) {
-                        return;
-                    }
-                    if let Err(_) = stream.write_all(f.data) {
-                        return;
+        match packets.recv_timeout(CLIP_POLL) {
+            Ok(packet) => {
+                if size != Some((packet.width, packet.height, packet.color)) {
+                    size = Some((packet.width, packet.height, packet.color));
+                    let meta = Message::Meta {
+                        width: packet.width as u16,
+                        height: packet.height as u16,
+                        color: packet.color,
+                    };
+                    if writer.send(&meta).is_err() {
+                        break;
                     }
                 }
-                let dt = now.elapsed();
-                if dt < spf {
-                    thread::sleep(spf - dt);
+                let kind = if packet.key && key_video { proto::VIDEO_KEY } else { proto::VIDEO };
+                if writer.send_frame(kind, &packet.data).is_err() {
+                    break;
                 }
+                sent.fetch_add(packet.data.len() as u64, Ordering::Relaxed);
             }
-            None => {
-                thread::sleep(time::Duration::from_millis(10));
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        if let (Some(clip), Some(_)) = (&clip, size) {
+            if time::Instant::now() >= next_poll {
+                next_poll = time::Instant::now() + CLIP_POLL;
+                let changed = clip.lock().unwrap().poll();
+                if let Some((kind, data)) = changed {
+                    if writer.send(&Message::Clipboard { kind, data }).is_err() {
+                        break;
+                    }
+                }
             }
         }
     }
+    // 通知事件线程退出
+    let _ = writer.shutdown();
+}
+
+/*
+光标流, 与图像流共用发送端但不等待视频
++--------------+
+| CURSOR_SHAPE |  形状变化时发送
++--------------+
+|  CURSOR_POS  |  位置或可见性变化时发送, 坐标相对画面左上角
++--------------+
+|     ...      |
++--------------+
+*/
+fn cursor_stream(writer: Writer, origin: &Mutex<(i32, i32)>, sent: &AtomicU64, done: &AtomicBool) {
+    let mut cursor = match cursor::Cursor::new() {
+        Some(c) => c,
+        None => return,
+    };
+    let mut last = None;
+    while !done.load(Ordering::Relaxed) {
+        std::thread::sleep(cursor::POLL);
+        // 第一帧发出后客户端才开始处理消息
+        if sent.load(Ordering::Relaxed) == 0 {
+            continue;
+        }
+        let state = match cursor.poll() {
+            Some(state) => state,
+            None => continue,
+        };
+        if let Some(s) = state.shape {
+            let shape = Message::CursorShape {
+                width: s.width,
+                height: s.height,
+                hot_x: s.hot_x,
+                hot_y: s.hot_y,
+                rgba: s.rgba,
+            };
+            if writer.send(&shape).is_err() {
+                break;
+            }
+        }
+        let (ox, oy) = *origin.lock().unwrap();
+        let pos = Message::CursorPos {
+            x: state.x - ox,
+            y: state.y - oy,
+            visible: state.visible,
+        };
+        if last.as_ref() != Some(&pos) {
+            if writer.send(&pos).is_err() {
+                break;
+            }
+            last = Some(pos);
+        }
+    }
+}
+
+/**
+ * 把字节流交给事件处理, 返回产生的输入
+ * 会话查看的显示器左上角为origin, 截屏来源为合成画面
+ */
+#[cfg(test)]
+fn replay(bytes: &[u8], level: Level, origin: (i32, i32)) -> Vec<Token> {
+    let source = screen::Source::Synthetic { width: 640, height: 480 };
+    let view = View {
+        mgr: session::Manager::new(ColorSpace::default(), source),
+        id: 1,
+        monitors: screen::monitors(source),
+        origin: Arc::new(Mutex::new(origin)),
+    };
+    let rate = Rate::new(Arc::new(AtomicU64::new(0)));
+    let (tx, rx) = channel();
+    event(bytes, level, None, None, view, rate, &tx);
+    drop(tx);
+    rx.iter().collect()
+}
+
+#[cfg(test)]
+fn encode(msgs: &[Message]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for m in msgs {
+        m.write_to(&mut bytes).unwrap();
+    }
+    bytes
+}
+
+#[test]
+fn test_event_input() {
+    use dscom::keymap::*;
+    use enigo::Button;
+    use enigo::Key;
+
+    let mut bytes = encode(&[
+        Message::Move { x: 10, y: 20 },
+        Message::MouseKeyDown(233),
+        Message::MouseKeyUp(233),
+        Message::MouseKeyDown(235),
+        // 未知的鼠标键和按键忽略
+        Message::MouseKeyDown(1),
+        Message::MouseWheelUp,
+        Message::MouseWheelDown,
+        Message::KeyDown(KEY_A),
+        Message::KeyUp(KEY_A),
+        Message::KeyDown(0xffff),
+        Message::KeyDown(KEY_ENTER),
+        Message::Text("中文 é".to_string()),
+    ]);
+    // 新版本客户端的消息跳过, 不影响之后的输入
+    proto::write_frame(&mut bytes, 200, &[1, 2, 3]).unwrap();
+    bytes.extend(encode(&[Message::Move { x: 0, y: 0 }]));
+    // 不完整的消息结束处理
+    let mut tail = encode(&[Message::Move { x: 5, y: 5 }]);
+    tail.pop();
+    bytes.extend(tail);
+
+    let tokens = replay(&bytes, Level::Full, (-1280, 100));
+    assert_eq!(
+        tokens,
+        [
+            Token::MoveMouse(-1270, 120, Coordinate::Abs),
+            Token::Button(Button::Left, Direction::Press),
+            Token::Button(Button::Left, Direction::Release),
+            Token::Button(Button::Right, Direction::Press),
+            Token::Scroll(-2, Axis::Vertical),
+            Token::Scroll(2, Axis::Vertical),
+            key_mouse::key_to_token(KEY_A, Direction::Press).unwrap(),
+            key_mouse::key_to_token(KEY_A, Direction::Release).unwrap(),
+            Token::Key(Key::Return, Direction::Press),
+            Token::Text("中文 é".to_string()),
+            Token::MoveMouse(-1280, 100, Coordinate::Abs),
+        ]
+    );
+}
+
+#[test]
+fn test_event_level() {
+    let bytes = encode(&[
+        Message::KeyDown(dscom::keymap::KEY_A),
+        Message::Text("x".to_string()),
+        Message::Move { x: 1, y: 2 },
+        Message::MouseWheelDown,
+    ]);
+    assert_eq!(replay(&bytes, Level::View, (0, 0)), []);
+    // 仅鼠标的会话不能输入按键和文本
+    assert_eq!(
+        replay(&bytes, Level::Mouse, (0, 0)),
+        [Token::MoveMouse(1, 2, Coordinate::Abs), Token::Scroll(2, Axis::Vertical)]
+    );
+    assert_eq!(replay(&bytes, Level::Full, (0, 0)).len(), 4);
+}
+
+#[test]
+fn test_event_select_monitor() {
+    // 切换显示器后坐标换算到新显示器, 合成画面只有一个位于原点的显示器
+    let bytes = encode(&[
+        Message::Move { x: 1, y: 1 },
+        Message::SelectMonitor(0),
+        Message::Move { x: 1, y: 1 },
+        Message::SelectMonitor(7),
+        Message::Move { x: 2, y: 2 },
+    ]);
+    assert_eq!(
+        replay(&bytes, Level::Full, (100, 100)),
+        [
+            Token::MoveMouse(101, 101, Coordinate::Abs),
+            Token::MoveMouse(1, 1, Coordinate::Abs),
+            Token::MoveMouse(2, 2, Coordinate::Abs),
+        ]
+    );
 }
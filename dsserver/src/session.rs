@@ -0,0 +1,477 @@
+use std::net::SocketAddr;
+use std::sync::mpsc::sync_channel;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::SyncSender;
+use std::sync::mpsc::TrySendError;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time;
+
+use dscom::auth::Level;
+use dscom::convert::ColorSpace;
+use dscom::proto::Codec;
+use dscom::stream::Stream;
+
+use crate::diff;
+use crate::diff::Diff;
+use crate::rate;
+use crate::screen;
+
+/// 每个会话最多缓存的帧数, 超出后该会话丢帧直到下一个关键帧
+const QUEUE_LEN: usize = 8;
+
+/**
+ * 编码后的一帧, 所有会话共享同一份数据
+ */
+#[derive(Clone)]
+pub struct Packet {
+    pub data: Arc<Vec<u8>>,
+    pub key: bool,
+    // 画面宽高和颜色空间, 变化时会话重发META
+    pub width: usize,
+    pub height: usize,
+    pub color: ColorSpace,
+}
+
+#[derive(Clone, Debug)]
+pub struct SessionInfo {
+    pub id: u64,
+    pub peer: SocketAddr,
+    pub level: Level,
+    pub since: time::SystemTime,
+    /// 查看的显示器
+    pub monitor: u8,
+    /// 握手时协商的视频编码
+    pub codec: Codec,
+    /// 当前是否按I444编码
+    pub i444: bool,
+    /// RGB转YUV的颜色空间
+    pub color: ColorSpace,
+}
+
+impl SessionInfo {
+    fn feed(&self) -> Feed {
+        Feed {
+            monitor: self.monitor,
+            codec: self.codec,
+            i444: self.i444,
+            color: self.color,
+        }
+    }
+}
+
+/**
+ * 流水线的输入和输出格式, 相同的会话共享一条流水线
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Feed {
+    monitor: u8,
+    codec: Codec,
+    i444: bool,
+    color: ColorSpace,
+}
+
+struct Session {
+    info: SessionInfo,
+    tx: SyncSender<Packet>,
+    stream: Stream,
+    // 等待关键帧, 新会话或丢帧后为true
+    skip: bool,
+    // 该会话网络能承受的码率和帧率
+    bitrate: u32,
+    fps: u32,
+    // 客户端接受I444, 码率足够时启用
+    allow_i444: bool,
+}
+
+/**
+ * 一个显示器按一种编码的截屏编码流水线
+ */
+struct Pipeline {
+    feed: Feed,
+    // 下一帧需要关键帧
+    keyframe: bool,
+}
+
+struct Inner {
+    sessions: Vec<Session>,
+    next_id: u64,
+    pipelines: Vec<Pipeline>,
+}
+
+/**
+ * 会话管理, 查看同一显示器且编码相同的会话共享一条截屏编码流水线
+ */
+pub struct Manager {
+    inner: Mutex<Inner>,
+    // 协商了CAP_COLOR的会话使用的颜色空间
+    color: ColorSpace,
+    source: screen::Source,
+}
+
+impl Manager {
+    pub fn new(color: ColorSpace, source: screen::Source) -> Arc<Manager> {
+        Arc::new(Manager {
+            color,
+            source,
+            inner: Mutex::new(Inner {
+                sessions: Vec::new(),
+                next_id: 1,
+                pipelines: Vec::new(),
+            }),
+        })
+    }
+
+    /**
+     * 截屏来源, 显示器列表按此枚举
+     */
+    pub fn source(&self) -> screen::Source {
+        self.source
+    }
+
+    /**
+     * 注册会话, 返回会话id及其帧队列, 必要时启动流水线
+     * 客户端不支持CAP_COLOR时按默认颜色空间编码
+     */
+    pub fn add(
+        self: &Arc<Self>,
+        stream: Stream,
+        level: Level,
+        monitor: u8,
+        codec: Codec,
+        allow_i444: bool,
+        allow_color: bool,
+    ) -> (u64, Receiver<Packet>) {
+        let (tx, rx) = sync_channel(QUEUE_LEN);
+        let peer = stream.peer_addr().unwrap_or_else(|_| ([0, 0, 0, 0], 0).into());
+        let mut inner = self.inner.lock().unwrap();
+        let id = inner.next_id;
+        inner.next_id += 1;
+        let info = SessionInfo {
+            id,
+            peer,
+            level,
+            since: time::SystemTime::now(),
+            monitor,
+            codec,
+            i444: false,
+            color: if allow_color { self.color } else { ColorSpace::default() },
+        };
+        let feed = info.feed();
+        inner.sessions.push(Session {
+            info,
+            tx,
+            stream,
+            skip: true,
+            bitrate: rate::START_BITRATE,
+            fps: rate::MAX_FPS,
+            allow_i444,
+        });
+        self.watch(&mut inner, feed);
+        println!("Session {} open from {} ({}, {})", id, peer, level.name(), codec.name());
+        (id, rx)
+    }
+
+    /**
+     * 会话切换显示器, 从新流水线的下一个关键帧开始接收
+     */
+    pub fn select(self: &Arc<Self>, id: u64, monitor: u8) {
+        let mut inner = self.inner.lock().unwrap();
+        let feed = match inner.sessions.iter_mut().find(|s| s.info.id == id) {
+            Some(s) if s.info.monitor != monitor => {
+                s.info.monitor = monitor;
+                s.skip = true;
+                s.info.feed()
+            }
+            _ => return,
+        };
+        self.watch(&mut inner, feed);
+    }
+
+    /**
+     * 会话请求关键帧, 在此之前的帧对它已无用
+     */
+    pub fn request_keyframe(&self, id: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        let feed = match inner.sessions.iter_mut().find(|s| s.info.id == id) {
+            Some(s) => {
+                s.skip = true;
+                s.info.feed()
+            }
+            None => return,
+        };
+        if let Some(p) = inner.pipelines.iter_mut().find(|p| p.feed == feed) {
+            p.keyframe = true;
+        }
+    }
+
+    /**
+     * 流水线这次没有可编码的画面, 保留关键帧请求到下一次
+     */
+    fn defer_keyframe(&self, feed: Feed) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(p) = inner.pipelines.iter_mut().find(|p| p.feed == feed) {
+            p.keyframe = true;
+        }
+    }
+
+    /**
+     * 更新会话的目标码率和帧率, 码率跨过阈值时在I420和I444流水线间切换
+     */
+    pub fn target(self: &Arc<Self>, id: u64, bitrate: u32, fps: u32) {
+        let mut inner = self.inner.lock().unwrap();
+        let feed = match inner.sessions.iter_mut().find(|s| s.info.id == id) {
+            Some(s) => {
+                s.bitrate = bitrate;
+                s.fps = fps;
+                let i444 = s.allow_i444 && rate::i444(s.info.i444, bitrate);
+                if i444 == s.info.i444 {
+                    return;
+                }
+                println!("Session {} switch to {}", id, if i444 { "I444" } else { "I420" });
+                s.info.i444 = i444;
+                s.skip = true;
+                s.info.feed()
+            }
+            None => return,
+        };
+        self.watch(&mut inner, feed);
+    }
+
+    /**
+     * 请求流水线的关键帧, 没有流水线时启动
+     */
+    fn watch(self: &Arc<Self>, inner: &mut Inner, feed: Feed) {
+        if let Some(p) = inner.pipelines.iter_mut().find(|p| p.feed == feed) {
+            p.keyframe = true;
+            return;
+        }
+        inner.pipelines.push(Pipeline {
+            feed,
+            keyframe: true,
+        });
+        let mgr = self.clone();
+        thread::spawn(move || {
+            let e = match std::panic::catch_unwind(|| pipeline(&mgr, feed)) {
+                Ok(Ok(())) => return,
+                Ok(Err(e)) => e,
+                Err(payload) => match payload.downcast::<String>() {
+                    Ok(msg) => *msg,
+                    Err(payload) => match payload.downcast::<&str>() {
+                        Ok(msg) => msg.to_string(),
+                        Err(_) => "unknown panic".to_string(),
+                    },
+                },
+            };
+            println!("Pipeline {:?} failed: {}", feed, e);
+            mgr.abort(feed);
+        });
+    }
+
+    /**
+     * 流水线异常退出, 断开查看它的会话, 客户端可以重新连接
+     * 不断开的话会话会一直等待画面
+     */
+    fn abort(&self, feed: Feed) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.pipelines.retain(|p| p.feed != feed);
+        inner.sessions.retain(|s| {
+            if s.info.feed() == feed {
+                let _ = s.stream.shutdown();
+                println!("Session {} closed", s.info.id);
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    pub fn remove(&self, id: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.sessions.retain(|s| {
+            if s.info.id == id {
+                let _ = s.stream.shutdown();
+                println!("Session {} closed", id);
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    pub fn list(&self) -> Vec<SessionInfo> {
+        let inner = self.inner.lock().unwrap();
+        inner.sessions.iter().map(|s| s.info.clone()).collect()
+    }
+
+    /**
+     * 断开指定会话
+     */
+    pub fn kick(&self, id: u64) -> bool {
+        let inner = self.inner.lock().unwrap();
+        match inner.sessions.iter().find(|s| s.info.id == id) {
+            Some(s) => {
+                let _ = s.stream.shutdown();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /**
+     * 分发一帧给该流水线的会话, 慢会话丢帧而不阻塞其他会话
+     */
+    fn broadcast(&self, feed: Feed, packet: Packet) {
+        let mut inner = self.inner.lock().unwrap();
+        let mut keyframe = false;
+        for s in inner.sessions.iter_mut().filter(|s| s.info.feed() == feed) {
+            if s.skip && !packet.key {
+                continue;
+            }
+            match s.tx.try_send(packet.clone()) {
+                Ok(_) => {
+                    s.skip = false;
+                }
+                Err(TrySendError::Full(_)) => {
+                    s.skip = true;
+                    keyframe = true;
+                }
+                Err(TrySendError::Disconnected(_)) => {}
+            }
+        }
+        if keyframe {
+            if let Some(p) = inner.pipelines.iter_mut().find(|p| p.feed == feed) {
+                p.keyframe = true;
+            }
+        }
+    }
+
+    /**
+     * 流水线状态, 没有会话查看时停止并返回None, 否则返回并清除关键帧请求
+     * 共享一条流水线的会话按最慢的会话编码
+     */
+    fn poll(&self, feed: Feed) -> Option<Control> {
+        let mut inner = self.inner.lock().unwrap();
+        let watching = inner.sessions.iter().filter(|s| s.info.feed() == feed);
+        let (bitrate, fps) = watching.fold((u32::MAX, u32::MAX), |(b, f), s| (b.min(s.bitrate), f.min(s.fps)));
+        if bitrate == u32::MAX {
+            inner.pipelines.retain(|p| p.feed != feed);
+            return None;
+        }
+        let p = inner.pipelines.iter_mut().find(|p| p.feed == feed)?;
+        Some(Control {
+            keyframe: std::mem::replace(&mut p.keyframe, false),
+            bitrate,
+            fps,
+        })
+    }
+}
+
+/**
+ * 流水线每帧的控制参数
+ */
+struct Control {
+    keyframe: bool,
+    bitrate: u32,
+    fps: u32,
+}
+
+/**
+ * 截屏编码流水线, 截屏器不能跨线程, 在流水线线程内创建
+ * 没有会话查看时返回Ok, 编码器出错时返回Err
+ */
+fn pipeline(mgr: &Manager, feed: Feed) -> Result<(), String> {
+    let mut bitrate = rate::START_BITRATE;
+    let start = time::Instant::now();
+    // 上一帧的pts
+    let mut pts = -1;
+    let mut yuv = Vec::<u8>::new();
+    let mut cap = screen::Cap::new(mgr.source, feed.monitor);
+    let mut diff = Diff::new();
+    let format = if feed.i444 {
+        vpx_codec::encoder::ImageFormat::I444
+    } else {
+        vpx_codec::encoder::ImageFormat::I420
+    };
+    loop {
+        // vpxencode, 分辨率变化时重建
+        let (iw, ih) = cap.wh();
+        if iw == 0 || ih == 0 {
+            // 截屏器未就绪, 等到截到第一帧, 期间会话全部离开时停止
+            // 新编码器的第一帧总是关键帧, 这里清除的关键帧请求不会丢失
+            if mgr.poll(feed).is_none() {
+                return Ok(());
+            }
+            cap.cap_yuv(&mut yuv, feed.i444, feed.color, &mut diff, true);
+            continue;
+        }
+        let ecfg = vpx_codec::encoder::Config {
+            width: iw as _,
+            height: ih as _,
+            // pts为毫秒
+            timebase: [1, 1000],
+            bitrate,
+            codec: match feed.codec {
+                Codec::VP8 => vpx_codec::encoder::VideoCodecId::VP8,
+                Codec::VP9 => vpx_codec::encoder::VideoCodecId::VP9,
+            },
+            format,
+        };
+        let mut enc = vpx_codec::encoder::Encoder::new(ecfg).map_err(|e| e.to_string())?;
+        // 上次编码的时间, 重建编码器后第一帧必须编码
+        let mut encoded: Option<time::Instant> = None;
+        if feed.codec == Codec::VP9 {
+            // VP9针对文字和大面积纯色的屏幕内容调优
+            enc.set_screen_content(true).map_err(|e| e.to_string())?;
+        }
+        loop {
+            let ctl = match mgr.poll(feed) {
+                None => return Ok(()),
+                Some(ctl) => ctl,
+            };
+            if ctl.keyframe {
+                enc.force_keyframe();
+            }
+            if ctl.bitrate != bitrate && enc.set_bitrate(ctl.bitrate).is_ok() {
+                bitrate = ctl.bitrate;
+            }
+            let spf = time::Duration::from_nanos(1_000_000_000 / ctl.fps.max(1) as u64);
+            let now = time::Instant::now();
+            // 画面静止时跳过编码, 请求关键帧或超过MIN_REFRESH时照常编码, 截屏没有出新帧时重新编码上一帧
+            let force = ctl.keyframe || encoded.is_none_or(|t| now - t >= diff::MIN_REFRESH);
+            let (_iw, _ih, fresh) = cap.cap_yuv(&mut yuv, feed.i444, feed.color, &mut diff, force);
+            if iw != _iw || ih != _ih {
+                // 分辨率变化, 按新大小重建编码器, 会话随下一帧重发META
+                println!("Resolution {}x{} -> {}x{}", iw, ih, _iw, _ih);
+                break;
+            }
+            if let Some(timestamp) = fresh {
+                encoded = Some(now);
+                // pts为截屏时刻, 必须递增
+                let ms = timestamp.saturating_duration_since(start).as_millis() as i64;
+                pts = ms.max(pts + 1);
+                for f in enc.encode(pts, &yuv).map_err(|e| e.to_string())? {
+                    mgr.broadcast(
+                        feed,
+                        Packet {
+                            data: Arc::new(f.data.to_vec()),
+                            key: f.key,
+                            width: iw,
+                            height: ih,
+                            color: feed.color,
+                        },
+                    );
+                }
+            } else if ctl.keyframe {
+                // 还没有截到画面等情况, 画面静止时不能等到下一个新画面
+                mgr.defer_keyframe(feed);
+            }
+            let dt = now.elapsed();
+            if dt < spf {
+                thread::sleep(spf - dt);
+            }
+        }
+        let _ = enc.finish();
+    }
+}
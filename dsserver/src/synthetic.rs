@@ -0,0 +1,101 @@
+use std::io;
+use std::time::Instant;
+
+use crate::screen::CaptureSource;
+use crate::screen::Format;
+use crate::screen::Frame;
+
+/*
+合成画面
+不需要显示器, 按帧号生成确定的画面, 用于集成测试和性能测试
+背景为水平方向的蓝色渐变和垂直方向的绿色渐变, 不随帧变化
+画面中间一条高SQUARE的横带内有一个白色方块, 每帧右移STEP像素, 到最右侧后回到最左侧
+*/
+
+/// 方块边长, 像素
+pub const SQUARE: usize = 64;
+/// 方块每帧移动的像素
+pub const STEP: usize = 8;
+
+/**
+ * 第n帧(x, y)处的BGRA像素
+ */
+pub fn pixel(width: usize, height: usize, n: u64, x: usize, y: usize) -> [u8; 4] {
+    let top = height.saturating_sub(SQUARE) / 2;
+    let left = (n as usize).wrapping_mul(STEP) % width.max(1);
+    if (top..top + SQUARE).contains(&y) && (left..left + SQUARE).contains(&x) {
+        return [255, 255, 255, 255];
+    }
+    let ramp = |v: usize, len: usize| (v * 255 / len.saturating_sub(1).max(1)) as u8;
+    [ramp(x, width), ramp(y, height), 128, 255]
+}
+
+/**
+ * 每次取帧生成下一帧, 第一帧的帧号为0
+ */
+pub struct Synthetic {
+    width: usize,
+    height: usize,
+    n: u64,
+    buf: Vec<u8>,
+}
+
+impl Synthetic {
+    pub fn new(width: usize, height: usize) -> Synthetic {
+        let mut buf = vec![0u8; width * height * 4];
+        for (y, line) in buf.chunks_exact_mut(width * 4).enumerate() {
+            for (x, p) in line.chunks_exact_mut(4).enumerate() {
+                p.copy_from_slice(&pixel(width, height, 0, x, y));
+            }
+        }
+        Synthetic { width, height, n: 0, buf }
+    }
+}
+
+impl CaptureSource for Synthetic {
+    fn frame(&mut self) -> io::Result<Frame<'_>> {
+        let (w, h, n) = (self.width, self.height, self.n);
+        // 只有方块所在的横带会变化
+        if n > 0 {
+            let top = h.saturating_sub(SQUARE) / 2;
+            for y in top..(top + SQUARE).min(h) {
+                let line = &mut self.buf[y * w * 4..(y + 1) * w * 4];
+                for (x, p) in line.chunks_exact_mut(4).enumerate() {
+                    p.copy_from_slice(&pixel(w, h, n, x, y));
+                }
+            }
+        }
+        self.n += 1;
+        Ok(Frame {
+            data: &self.buf,
+            width: w,
+            height: h,
+            stride: w * 4,
+            format: Format::Bgra,
+            timestamp: Instant::now(),
+        })
+    }
+}
+
+#[test]
+fn test_synthetic() {
+    let (w, h) = (200, 100);
+    let mut s = Synthetic::new(w, h);
+    let at = |data: &[u8], x: usize, y: usize| data[(y * w + x) * 4..(y * w + x) * 4 + 4].to_vec();
+    for n in 0..40u64 {
+        let f = s.frame().unwrap();
+        assert_eq!((f.width, f.height, f.stride, f.format), (w, h, w * 4, Format::Bgra));
+        for (x, y) in [(0, 0), (w - 1, h - 1), (10, 50), (199, 60), (n as usize * STEP % w, 18)] {
+            assert_eq!(at(f.data, x, y), pixel(w, h, n, x, y));
+        }
+    }
+    // 渐变背景
+    assert_eq!(pixel(w, h, 0, 0, 0), [0, 0, 128, 255]);
+    assert_eq!(pixel(w, h, 0, w - 1, h - 1), [255, 255, 128, 255]);
+    // 方块移动并在右侧回绕
+    assert_eq!(pixel(w, h, 0, 0, 18), [255; 4]);
+    assert_eq!(pixel(w, h, 0, 0, 17), [0, 43, 128, 255]);
+    assert_eq!(pixel(w, h, 1, 0, 18), [0, 46, 128, 255]);
+    assert_eq!(pixel(w, h, 1, STEP, 18), [255; 4]);
+    assert_eq!(pixel(w, h, 25, 0, 18), [255; 4]);
+}
@@ -0,0 +1,318 @@
+use std::io;
+use std::os::fd::OwnedFd;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::sync::LazyLock;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use ashpd::desktop::screencast::CursorMode;
+use ashpd::desktop::screencast::Screencast;
+use ashpd::desktop::screencast::SourceType;
+use ashpd::desktop::PersistMode;
+use ashpd::desktop::Session;
+use ashpd::WindowIdentifier;
+use pipewire as pw;
+use pollster::FutureExt;
+use pw::spa;
+use pw::spa::param::format::FormatProperties;
+use pw::spa::param::format::MediaSubtype;
+use pw::spa::param::format::MediaType;
+use pw::spa::param::video::VideoFormat;
+use pw::spa::param::video::VideoInfoRaw;
+use pw::spa::param::ParamType;
+use pw::spa::pod::Pod;
+use pw::stream::StreamFlags;
+
+use dscom::proto::Monitor;
+
+use crate::screen::CaptureSource;
+use crate::screen::Format;
+use crate::screen::Frame;
+
+/*
+Wayland截屏
+scrap在Wayland下截不到画面, 改为通过xdg-desktop-portal的ScreenCast申请屏幕, 由PipeWire接收画面
+整个进程共用一个portal会话, 第一次截屏时服务端桌面弹出授权对话框, 选中的每个显示器为一路画面
+授权可保存时restore token写入TOKEN文件, 之后启动不再询问
+Wayland下光标无法单独采集, 由portal画在画面中
+*/
+
+/// 保存portal授权的文件
+const TOKEN: &str = "diffscreen.portal";
+
+/**
+ * 一路画面的最新一帧, 由PipeWire线程写入
+ */
+struct Latest {
+    data: Vec<u8>,
+    width: usize,
+    height: usize,
+    stride: usize,
+    format: Format,
+    timestamp: Instant,
+    // 每收到一帧加一
+    seq: u64,
+}
+
+struct Source {
+    monitor: Monitor,
+    node: u32,
+    frame: Mutex<Latest>,
+}
+
+/// portal会话的各路画面, 申请失败时为空
+static SOURCES: LazyLock<Vec<Arc<Source>>> = LazyLock::new(|| {
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || run(tx));
+    match rx.recv() {
+        Ok(Ok(sources)) => sources,
+        Ok(Err(e)) => {
+            println!("Screen cast error {}", e);
+            Vec::new()
+        }
+        Err(_) => Vec::new(),
+    }
+});
+
+/**
+ * portal授权的各路画面在虚拟桌面中的位置, 第一路为主显示器
+ */
+pub fn monitors() -> Vec<Monitor> {
+    SOURCES.iter().map(|s| s.monitor).collect()
+}
+
+/**
+ * 读取一路画面, 多个截屏器可共用同一路
+ */
+pub struct Capturer {
+    source: Arc<Source>,
+    seq: u64,
+    buf: Vec<u8>,
+}
+
+impl Capturer {
+    pub fn new(index: usize) -> io::Result<Capturer> {
+        let source = SOURCES
+            .get(index)
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no screen cast stream"))?;
+        Ok(Capturer {
+            source,
+            seq: 0,
+            buf: Vec::new(),
+        })
+    }
+}
+
+impl CaptureSource for Capturer {
+    fn frame(&mut self) -> io::Result<Frame<'_>> {
+        let f = self.source.frame.lock().unwrap();
+        if f.seq == self.seq {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        self.seq = f.seq;
+        self.buf.clear();
+        self.buf.extend_from_slice(&f.data);
+        Ok(Frame {
+            data: &self.buf,
+            width: f.width,
+            height: f.height,
+            stride: f.stride,
+            format: f.format,
+            timestamp: f.timestamp,
+        })
+    }
+}
+
+/**
+ * portal会话和PipeWire主循环都在此线程, 随进程一直运行
+ */
+fn run(tx: mpsc::Sender<io::Result<Vec<Arc<Source>>>>) {
+    // 代理和会话释放后portal即停止投屏
+    let (_proxy, _session, sources, fd) = match start().block_on() {
+        Ok(portal) => portal,
+        Err(e) => {
+            let _ = tx.send(Err(io::Error::other(e)));
+            return;
+        }
+    };
+    pw::init();
+    let pipewire = || -> Result<_, pw::Error> {
+        let mainloop = pw::main_loop::MainLoop::new(None)?;
+        let context = pw::context::Context::new(&mainloop)?;
+        let core = context.connect_fd(fd, None)?;
+        let streams = sources
+            .iter()
+            .map(|s| connect(&core, s.clone()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok((mainloop, context, core, streams))
+    };
+    match pipewire() {
+        Ok((mainloop, _context, _core, _streams)) => {
+            let _ = tx.send(Ok(sources));
+            mainloop.run();
+        }
+        Err(e) => {
+            let _ = tx.send(Err(io::Error::other(e)));
+        }
+    }
+}
+
+/**
+ * 申请截屏, 返回portal代理, 会话, 各路画面和PipeWire连接
+ */
+async fn start() -> ashpd::Result<(Screencast<'static>, Session<'static, Screencast<'static>>, Vec<Arc<Source>>, OwnedFd)> {
+    let token = std::fs::read_to_string(TOKEN).ok();
+    let proxy = Screencast::new().await?;
+    let session = proxy.create_session().await?;
+    proxy
+        .select_sources(
+            &session,
+            CursorMode::Embedded,
+            SourceType::Monitor.into(),
+            true,
+            token.as_deref().map(str::trim),
+            PersistMode::ExplicitlyRevoked,
+        )
+        .await?;
+    let response = proxy.start(&session, &WindowIdentifier::default()).await?.response()?;
+    if let Some(token) = response.restore_token() {
+        if let Err(e) = std::fs::write(TOKEN, token) {
+            println!("Save {} error {}", TOKEN, e);
+        }
+    }
+    let sources = response
+        .streams()
+        .iter()
+        .enumerate()
+        .map(|(i, s)| {
+            let (x, y) = s.position().unwrap_or((0, 0));
+            let (w, h) = s.size().unwrap_or((0, 0));
+            Arc::new(Source {
+                monitor: Monitor {
+                    x,
+                    y,
+                    width: w.clamp(0, u16::MAX as i32) as u16,
+                    height: h.clamp(0, u16::MAX as i32) as u16,
+                    primary: i == 0,
+                },
+                node: s.pipe_wire_node_id(),
+                frame: Mutex::new(Latest {
+                    data: Vec::new(),
+                    width: 0,
+                    height: 0,
+                    stride: 0,
+                    format: Format::Bgra,
+                    timestamp: Instant::now(),
+                    seq: 0,
+                }),
+            })
+        })
+        .collect();
+    let fd = proxy.open_pipe_wire_remote(&session).await?;
+    Ok((proxy, session, sources, fd))
+}
+
+/**
+ * 连接一路画面, 只接受BGRx/BGRA/RGBx/RGBA
+ */
+fn connect(core: &pw::core::Core, source: Arc<Source>) -> Result<(pw::stream::Stream, pw::stream::StreamListener<Arc<Source>>), pw::Error> {
+    let stream = pw::stream::Stream::new(
+        core,
+        "diffscreen",
+        pw::properties::properties! {
+            *pw::keys::MEDIA_TYPE => "Video",
+            *pw::keys::MEDIA_CATEGORY => "Capture",
+            *pw::keys::MEDIA_ROLE => "Screen",
+        },
+    )?;
+    let node = source.node;
+    let listener = stream
+        .add_local_listener_with_user_data(source)
+        .param_changed(|_, source, id, param| {
+            let param = match param {
+                Some(param) if id == ParamType::Format.as_raw() => param,
+                _ => return,
+            };
+            match spa::param::format_utils::parse_format(param) {
+                Ok((MediaType::Video, MediaSubtype::Raw)) => {}
+                _ => return,
+            }
+            let mut info = VideoInfoRaw::default();
+            if info.parse(param).is_ok() {
+                let size = info.size();
+                let mut f = source.frame.lock().unwrap();
+                (f.width, f.height) = (size.width as usize, size.height as usize);
+                f.format = match info.format() {
+                    VideoFormat::RGBx | VideoFormat::RGBA => Format::Rgba,
+                    _ => Format::Bgra,
+                };
+            }
+        })
+        .process(|stream, source| {
+            let mut buffer = match stream.dequeue_buffer() {
+                Some(buffer) => buffer,
+                None => return,
+            };
+            let data = match buffer.datas_mut().first_mut() {
+                Some(data) => data,
+                None => return,
+            };
+            let chunk = data.chunk();
+            let (offset, size, stride) = (chunk.offset() as usize, chunk.size() as usize, chunk.stride());
+            let bytes = match data.data() {
+                Some(bytes) => bytes,
+                None => return,
+            };
+            let mut f = source.frame.lock().unwrap();
+            if f.width == 0 || f.height == 0 || stride < f.width as i32 * 4 {
+                return;
+            }
+            // 画面没有变化时portal可能发送空的buffer
+            let stride = stride as usize;
+            let len = stride * (f.height - 1) + f.width * 4;
+            let src = match bytes.get(offset..offset + size) {
+                Some(src) if size >= len => &src[..len],
+                _ => return,
+            };
+            f.data.clear();
+            f.data.extend_from_slice(src);
+            f.stride = stride;
+            f.timestamp = Instant::now();
+            f.seq += 1;
+        })
+        .register()?;
+    let format = pw::spa::pod::object!(
+        pw::spa::utils::SpaTypes::ObjectParamFormat,
+        ParamType::EnumFormat,
+        pw::spa::pod::property!(FormatProperties::MediaType, Id, MediaType::Video),
+        pw::spa::pod::property!(FormatProperties::MediaSubtype, Id, MediaSubtype::Raw),
+        pw::spa::pod::property!(
+            FormatProperties::VideoFormat,
+            Choice,
+            Enum,
+            Id,
+            VideoFormat::BGRx,
+            VideoFormat::BGRx,
+            VideoFormat::BGRA,
+            VideoFormat::RGBx,
+            VideoFormat::RGBA,
+        ),
+    );
+    let values = pw::spa::pod::serialize::PodSerializer::serialize(
+        std::io::Cursor::new(Vec::new()),
+        &pw::spa::pod::Value::Object(format),
+    )
+    .map_err(|_| pw::Error::CreationFailed)?
+    .0
+    .into_inner();
+    let mut params = [Pod::from_bytes(&values).ok_or(pw::Error::CreationFailed)?];
+    stream.connect(
+        spa::utils::Direction::Input,
+        Some(node),
+        StreamFlags::AUTOCONNECT | StreamFlags::MAP_BUFFERS,
+        &mut params,
+    )?;
+    Ok((stream, listener))
+}
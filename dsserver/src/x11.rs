@@ -0,0 +1,193 @@
+use std::io;
+use std::time::Instant;
+
+use memmap2::Mmap;
+use memmap2::MmapOptions;
+use x11rb::connection::Connection;
+use x11rb::connection::RequestConnection;
+use x11rb::protocol::randr;
+use x11rb::protocol::randr::ConnectionExt as _;
+use x11rb::protocol::shm;
+use x11rb::protocol::shm::ConnectionExt as _;
+use x11rb::protocol::xproto::ConnectionExt as _;
+use x11rb::protocol::xproto::ImageFormat;
+use x11rb::protocol::xproto::ImageOrder;
+use x11rb::protocol::xproto::Screen;
+use x11rb::protocol::xproto::Window;
+use x11rb::rust_connection::RustConnection;
+
+use dscom::proto::Monitor;
+
+use crate::screen::CaptureSource;
+use crate::screen::Format;
+use crate::screen::Frame;
+
+/*
+X11截屏
+直接从根窗口取图, 不需要GPU和RandR输出, Xvfb等虚拟X服务也能截屏
+MIT-SHM 1.2由X服务创建共享内存并传回fd, 远程DISPLAY或关闭了MIT-SHM时退回GetImage
+*/
+
+/**
+ * 各显示器在根窗口中的位置, 没有RandR 1.5时整个根窗口为一个显示器
+ */
+pub fn monitors() -> Vec<Monitor> {
+    match x11rb::connect(None) {
+        Ok((conn, screen)) => list(&conn, &conn.setup().roots[screen]),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn list(conn: &RustConnection, screen: &Screen) -> Vec<Monitor> {
+    let randr = || -> Option<randr::GetMonitorsReply> {
+        conn.extension_information(randr::X11_EXTENSION_NAME).ok()??;
+        let version = conn.randr_query_version(1, 5).ok()?.reply().ok()?;
+        if (version.major_version, version.minor_version) < (1, 5) {
+            return None;
+        }
+        conn.randr_get_monitors(screen.root, true).ok()?.reply().ok()
+    };
+    match randr() {
+        Some(reply) if !reply.monitors.is_empty() => reply
+            .monitors
+            .iter()
+            .map(|m| Monitor {
+                x: m.x as i32,
+                y: m.y as i32,
+                width: m.width,
+                height: m.height,
+                primary: m.primary,
+            })
+            .collect(),
+        _ => vec![Monitor {
+            x: 0,
+            y: 0,
+            width: screen.width_in_pixels,
+            height: screen.height_in_pixels,
+            primary: true,
+        }],
+    }
+}
+
+/**
+ * 共享内存段, 大小为一整帧
+ */
+struct Shm {
+    seg: shm::Seg,
+    map: Mmap,
+}
+
+pub struct Capturer {
+    conn: RustConnection,
+    root: Window,
+    x: i16,
+    y: i16,
+    width: u16,
+    height: u16,
+    shm: Option<Shm>,
+    // 没有共享内存时GetImage的结果
+    buf: Vec<u8>,
+}
+
+impl Capturer {
+    /**
+     * 截取monitors()中第index个显示器, 只支持每像素32位的小端画面
+     */
+    pub fn new(index: usize) -> io::Result<Capturer> {
+        let (conn, screen) = x11rb::connect(None).map_err(io::Error::other)?;
+        let setup = conn.setup();
+        let root = &setup.roots[screen];
+        let bpp = setup.pixmap_formats.iter().find(|f| f.depth == root.root_depth).map(|f| f.bits_per_pixel);
+        if bpp != Some(32) || setup.image_byte_order != ImageOrder::LSB_FIRST {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "unsupported X11 pixel format"));
+        }
+        let m = list(&conn, root)
+            .get(index)
+            .copied()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no monitor"))?;
+        let root = root.root;
+        let shm = attach(&conn, m.width as usize * m.height as usize * 4);
+        if shm.is_none() {
+            println!("MIT-SHM unavailable, use GetImage");
+        }
+        Ok(Capturer {
+            conn,
+            root,
+            x: m.x as i16,
+            y: m.y as i16,
+            width: m.width,
+            height: m.height,
+            shm,
+            buf: Vec::new(),
+        })
+    }
+}
+
+/**
+ * 由X服务创建共享内存段, 需要MIT-SHM 1.2和本地连接
+ */
+fn attach(conn: &RustConnection, size: usize) -> Option<Shm> {
+    conn.extension_information(shm::X11_EXTENSION_NAME).ok()??;
+    let version = conn.shm_query_version().ok()?.reply().ok()?;
+    if (version.major_version, version.minor_version) < (1, 2) {
+        return None;
+    }
+    let seg = conn.generate_id().ok()?;
+    let reply = conn.shm_create_segment(seg, size as u32, false).ok()?.reply().ok()?;
+    let map = unsafe { MmapOptions::new().len(size).map(&reply.shm_fd) };
+    match map {
+        Ok(map) => Some(Shm { seg, map }),
+        Err(_) => {
+            let _ = conn.shm_detach(seg);
+            None
+        }
+    }
+}
+
+impl CaptureSource for Capturer {
+    fn frame(&mut self) -> io::Result<Frame<'_>> {
+        let (w, h) = (self.width as usize, self.height as usize);
+        let z = ImageFormat::Z_PIXMAP;
+        let data: &[u8] = match &self.shm {
+            Some(shm) => {
+                self.conn
+                    .shm_get_image(self.root, self.x, self.y, self.width, self.height, !0, z.into(), shm.seg, 0)
+                    .map_err(io::Error::other)?
+                    .reply()
+                    .map_err(io::Error::other)?;
+                &shm.map[..w * h * 4]
+            }
+            None => {
+                let reply = self
+                    .conn
+                    .get_image(z, self.root, self.x, self.y, self.width, self.height, !0)
+                    .map_err(io::Error::other)?
+                    .reply()
+                    .map_err(io::Error::other)?;
+                self.buf = reply.data;
+                &self.buf
+            }
+        };
+        if data.len() < w * h * 4 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "short X11 image"));
+        }
+        Ok(Frame {
+            data,
+            width: w,
+            height: h,
+            // 32位像素按32位对齐, 行间没有填充
+            stride: w * 4,
+            format: Format::Bgra,
+            timestamp: Instant::now(),
+        })
+    }
+}
+
+impl Drop for Capturer {
+    fn drop(&mut self) {
+        if let Some(shm) = &self.shm {
+            let _ = self.conn.shm_detach(shm.seg);
+            let _ = self.conn.flush();
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use std::io;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+
+use dsclient::headless;
+use dsclient::headless::Viewer;
+use dscom::auth::Level;
+use dscom::convert::ColorSpace;
+use dscom::convert::Matrix;
+use dscom::keymap::KEY_A;
+use dscom::proto;
+use dscom::proto::Message;
+use dsserver::input;
+use dsserver::input::Input;
+use dsserver::input::Recorder;
+use dsserver::key_mouse;
+use dsserver::screen::Source;
+use dsserver::server;
+use dsserver::synthetic;
+use enigo::agent::Token;
+use enigo::Axis;
+use enigo::Button;
+use enigo::Coordinate;
+use enigo::Direction;
+
+/*
+端到端测试
+进程内启动服务端, 截屏来源为合成画面, 输入只记录不执行
+无界面客户端连接并认证, 解码画面与合成画面比较, 发送的输入与服务端记录比较
+*/
+
+const W: usize = 320;
+const H: usize = 240;
+const PWD: &str = "diffscreen";
+const VIEW_PWD: &str = "view";
+// 读超时, 避免服务端出错时测试卡住
+const TIMEOUT: Duration = Duration::from_secs(20);
+// 有损编码后允许的单个通道误差和平均误差
+const MAX_ERROR: u8 = 40;
+const MEAN_ERROR: f64 = 8.0;
+
+/**
+ * 在随机端口启动服务端, 合成画面为width*height, 返回地址和输入记录
+ * 不启用需要桌面环境的剪贴板, 文件传输和光标
+ */
+fn start(width: usize, height: usize) -> (String, Recorder) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let rec = Recorder::default();
+    let make = {
+        let rec = rec.clone();
+        move || Ok(Box::new(rec) as Box<dyn Input>)
+    };
+    let config = server::Config {
+        port: 0,
+        pwds: vec![(Level::Full, PWD.to_string()), (Level::View, VIEW_PWD.to_string())],
+        tls: None,
+        caps: proto::CAPS & !(proto::CAP_CLIP_UP | proto::CAP_CLIP_DOWN | proto::CAP_FILE | proto::CAP_CURSOR),
+        files: PathBuf::from("diffscreen_files"),
+        color: ColorSpace {
+            matrix: Matrix::BT709,
+            full_range: true,
+        },
+        source: Source::Synthetic {
+            width: width as u16,
+            height: height as u16,
+        },
+    };
+    server::spawn(listener, config, input::spawn(make));
+    (addr, rec)
+}
+
+fn connect(addr: &str, pwd: &str, offer: u32) -> io::Result<Viewer> {
+    let conn = headless::connect(addr, pwd, None, offer)?;
+    conn.stream.set_read_timeout(Some(TIMEOUT))?;
+    Ok(Viewer::new(conn))
+}
+
+/**
+ * 方块所在横带以外的画面不随帧变化, 按合成画面逐点比较
+ */
+fn check_background(v: &Viewer) {
+    let (w, h) = (v.width, v.height);
+    let top = (h - synthetic::SQUARE) / 2;
+    let (mut sum, mut n) = (0u64, 0u64);
+    // 最后一行和一列落在取整后的色度采样上, 一并检查
+    let ys = (0..h).step_by(3).chain([h - 1]);
+    for y in ys.filter(|y| !(top..top + synthetic::SQUARE).contains(y)) {
+        for x in (0..w).step_by(3).chain([w - 1]) {
+            let [b, g, r, _] = synthetic::pixel(w, h, 0, x, y);
+            let got = v.pixel(x, y);
+            for (c, want) in got.into_iter().zip([r, g, b]) {
+                let e = c.abs_diff(want);
+                assert!(e <= MAX_ERROR, "({}, {}) got {:?} want {:?}", x, y, got, [r, g, b]);
+                sum += e as u64;
+                n += 1;
+            }
+        }
+    }
+    let mean = sum as f64 / n as f64;
+    assert!(mean <= MEAN_ERROR, "mean error {}", mean);
+}
+
+/**
+ * 方块在横带中线上的左边界, 没有找到白色方块时返回None
+ */
+fn square_x(v: &Viewer) -> Option<usize> {
+    let w = v.width;
+    let y = (v.height - synthetic::SQUARE) / 2 + synthetic::SQUARE / 2;
+    let white = |x: usize| v.pixel(x, y).iter().all(|c| *c >= 255 - MAX_ERROR);
+    // 方块宽SQUARE, 取其中间部分判断, 避开边缘的编码误差
+    (0..w).find(|x| (*x..(*x + synthetic::SQUARE / 2).min(w)).all(white))
+}
+
+/**
+ * 接收若干帧, 核对背景并确认方块在移动
+ */
+fn check_video(offer: u32, width: usize, height: usize) {
+    let (addr, _) = start(width, height);
+    let mut v = connect(&addr, PWD, offer).unwrap();
+    assert_eq!(v.conn.level, Level::Full);
+    assert_eq!(v.conn.monitors.len(), 1);
+    let mut seen = Vec::new();
+    for _ in 0..30 {
+        v.next_frame().unwrap();
+        assert_eq!((v.width, v.height), (width, height));
+        if let Some(x) = square_x(&v) {
+            seen.push(x);
+        }
+    }
+    check_background(&v);
+    seen.dedup();
+    assert!(seen.len() > 1, "square not moving {:?}", seen);
+}
+
+#[test]
+fn test_video_vp8() {
+    check_video(proto::CAPS & !(proto::CAP_VP9 | proto::CAP_I444), W, H);
+}
+
+#[test]
+fn test_video_vp9() {
+    check_video(proto::CAPS & !proto::CAP_I444, W, H);
+}
+
+#[test]
+fn test_video_legacy() {
+    // 不支持颜色空间和关键帧的旧版本客户端
+    check_video(proto::CAPS & !(proto::CAP_VP9 | proto::CAP_I444 | proto::CAP_COLOR | proto::CAP_KEYFRAME), W, H);
+}
+
+#[test]
+fn test_video_odd_size() {
+    // Xvfb, RandR和portal都可能给出奇数宽高
+    check_video(proto::CAPS & !proto::CAP_I444, W + 1, H - 1);
+    check_video(proto::CAPS & !(proto::CAP_VP9 | proto::CAP_I444), W - 1, H + 1);
+}
+
+#[test]
+fn test_auth() {
+    let (addr, _) = start(W, H);
+    let e = connect(&addr, "wrong", proto::CAPS).err().unwrap();
+    assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+    let v = connect(&addr, VIEW_PWD, proto::CAPS).unwrap();
+    assert_eq!(v.conn.level, Level::View);
+}
+
+#[test]
+fn test_keyframe_request() {
+    let (addr, _) = start(W, H);
+    let mut v = connect(&addr, PWD, proto::CAPS).unwrap();
+    v.next_frame().unwrap();
+    assert!(v.key);
+    for _ in 0..5 {
+        v.next_frame().unwrap();
+    }
+    // 客户端解码出错后重新同步
+    v.send(&Message::KeyframeRequest).unwrap();
+    let mut key = false;
+    for _ in 0..10 {
+        v.next_frame().unwrap();
+        key |= v.key;
+    }
+    assert!(key, "no keyframe after request");
+}
+
+/**
+ * 等待服务端记录到n个输入
+ */
+fn wait_tokens(rec: &Recorder, n: usize) -> Vec<Token> {
+    let deadline = Instant::now() + TIMEOUT;
+    while rec.tokens().len() < n && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    rec.tokens()
+}
+
+#[test]
+fn test_input() {
+    let (addr, rec) = start(W, H);
+    // 只读会话的输入被丢弃
+    let mut view = connect(&addr, VIEW_PWD, proto::CAPS).unwrap();
+    view.send(&Message::Move { x: 1, y: 1 }).unwrap();
+    view.send(&Message::Text("no".to_string())).unwrap();
+
+    let mut v = connect(&addr, PWD, proto::CAPS).unwrap();
+    v.next_frame().unwrap();
+    for msg in [
+        Message::Move { x: 10, y: 20 },
+        Message::MouseKeyDown(233),
+        Message::MouseKeyUp(233),
+        Message::MouseWheelDown,
+        Message::KeyDown(KEY_A),
+        Message::KeyUp(KEY_A),
+        Message::Text("hi".to_string()),
+    ] {
+        v.send(&msg).unwrap();
+    }
+    let want = [
+        Token::MoveMouse(10, 20, Coordinate::Abs),
+        Token::Button(Button::Left, Direction::Press),
+        Token::Button(Button::Left, Direction::Release),
+        Token::Scroll(2, Axis::Vertical),
+        key_mouse::key_to_token(KEY_A, Direction::Press).unwrap(),
+        key_mouse::key_to_token(KEY_A, Direction::Release).unwrap(),
+        Token::Text("hi".to_string()),
+    ];
+    assert_eq!(wait_tokens(&rec, want.len()), want);
+    // 画面仍在继续
+    v.next_frame().unwrap();
+}
@@ -93,20 +93,25 @@ pub struct Con {
 unsafe impl Send for Con {}
 
 impl Con {
-    async fn open_connection() -> ei::Context {
+    async fn open_connection() -> Result<ei::Context, NewConError> {
         use ashpd::desktop::remote_desktop::DeviceType;
 
+        let portal_err = |_| NewConError::EstablishCon("remote desktop portal error");
+
         trace!("open_connection");
-        if let Some(context) = ei::Context::connect_to_env().unwrap() {
+        if let Ok(Some(context)) = ei::Context::connect_to_env() {
             trace!("done open_connection after connect_to_env");
-            context
+            Ok(context)
+        } else if std::env::var_os("WAYLAND_DISPLAY").is_none() {
+            // The portal would ask for permission even though X11 input works
+            Err(NewConError::EstablishCon("not a wayland session"))
         } else {
             debug!("Unable to find ei socket. Trying xdg desktop portal.");
-            let remote_desktop = RemoteDesktop::new().await.unwrap();
+            let remote_desktop = RemoteDesktop::new().await.map_err(portal_err)?;
             trace!("New desktop");
 
             // device_bitmask |= DeviceType::Touchscreen;
-            let session = remote_desktop.create_session().await.unwrap();
+            let session = remote_desktop.create_session().await.map_err(portal_err)?;
             remote_desktop
                 .select_devices(
                     &session,
@@ -117,20 +122,20 @@ impl Con {
                            * EnigoSettings */
                 ) // TODO: Add DeviceType::Touchscreen once we support it in enigo
                 .await
-                .unwrap();
+                .map_err(portal_err)?;
             trace!("new session");
             remote_desktop
                 .start(&session, &ashpd::WindowIdentifier::default())
                 .await
-                .unwrap();
+                .map_err(portal_err)?;
             trace!("start session");
             // This is needed so there is no zbus error
             std::thread::sleep(std::time::Duration::from_millis(10));
-            let fd = remote_desktop.connect_to_eis(&session).await.unwrap();
+            let fd = remote_desktop.connect_to_eis(&session).await.map_err(portal_err)?;
             let stream = UnixStream::from(fd);
             stream.set_nonblocking(true).unwrap(); // TODO: Check if this is a good idea
             trace!("done open_connection");
-            ei::Context::new(stream).unwrap()
+            ei::Context::new(stream).map_err(|_| NewConError::EstablishCon("unable to create the libei context"))
         }
     }
 
@@ -148,7 +153,7 @@ impl Con {
         let sequence = 0;
         let time_created = Instant::now();
 
-        let context = Self::open_connection().block_on();
+        let context = Self::open_connection().block_on()?;
         let HandshakeResp {
             connection,
             serial,
@@ -159,7 +164,7 @@ impl Con {
             ei::handshake::ContextType::Sender,
             &INTERFACES,
         )
-        .unwrap();
+        .map_err(|_| NewConError::EstablishCon("libei handshake failed"))?;
 
         trace!("main: handshake");
 
@@ -132,6 +132,58 @@ impl Con {
     pub fn set_delay(&mut self, delay: u32) {
         self.delay = delay * 1000;
     }
+
+    /// Send the key sequence to the current window
+    fn keysequence(&mut self, string: &CString, direction: Direction) -> InputResult<()> {
+        let res = match direction {
+            Direction::Click => {
+                debug!(
+                    "xdo_send_keysequence_window with string {:?}, delay {}",
+                    string, self.delay
+                );
+                unsafe {
+                    xdo_send_keysequence_window(
+                        self.xdo,
+                        CURRENT_WINDOW,
+                        string.as_ptr(),
+                        self.delay as useconds_t,
+                    )
+                }
+            }
+            Direction::Press => {
+                debug!(
+                    "xdo_send_keysequence_window_down with string {:?}, delay {}",
+                    string, self.delay
+                );
+                unsafe {
+                    xdo_send_keysequence_window_down(
+                        self.xdo,
+                        CURRENT_WINDOW,
+                        string.as_ptr(),
+                        self.delay as useconds_t,
+                    )
+                }
+            }
+            Direction::Release => {
+                debug!(
+                    "xdo_send_keysequence_window_up with string {:?}, delay {}",
+                    string, self.delay
+                );
+                unsafe {
+                    xdo_send_keysequence_window_up(
+                        self.xdo,
+                        CURRENT_WINDOW,
+                        string.as_ptr(),
+                        self.delay as useconds_t,
+                    )
+                }
+            }
+        };
+        if res != XDO_SUCCESS {
+            return Err(InputError::Simulate("unable to enter key"));
+        }
+        Ok(())
+    }
 }
 
 impl Drop for Con {
@@ -182,61 +234,22 @@ impl Keyboard for Con {
             ));
         };
 
-        let res = match direction {
-            Direction::Click => {
-                debug!(
-                    "xdo_send_keysequence_window with string {:?}, delay {}",
-                    string, self.delay
-                );
-                unsafe {
-                    xdo_send_keysequence_window(
-                        self.xdo,
-                        CURRENT_WINDOW,
-                        string.as_ptr(),
-                        self.delay as useconds_t,
-                    )
-                }
-            }
-            Direction::Press => {
-                debug!(
-                    "xdo_send_keysequence_window_down with string {:?}, delay {}",
-                    string, self.delay
-                );
-                unsafe {
-                    xdo_send_keysequence_window_down(
-                        self.xdo,
-                        CURRENT_WINDOW,
-                        string.as_ptr(),
-                        self.delay as useconds_t,
-                    )
-                }
-            }
-            Direction::Release => {
-                debug!(
-                    "xdo_send_keysequence_window_up with string {:?}, delay {}",
-                    string, self.delay
-                );
-                unsafe {
-                    xdo_send_keysequence_window_up(
-                        self.xdo,
-                        CURRENT_WINDOW,
-                        string.as_ptr(),
-                        self.delay as useconds_t,
-                    )
-                }
-            }
-        };
-        if res != XDO_SUCCESS {
-            return Err(InputError::Simulate("unable to enter key"));
-        }
-        Ok(())
+        self.keysequence(&string, direction)
     }
 
-    fn raw(&mut self, _keycode: u16, _direction: Direction) -> InputResult<()> {
-        // TODO: Lookup the key name for the keycode and then enter that with xdotool.
-        // This is a bit weird, because xdotool will then do the reverse. Maybe there is
-        // a better way?
-        todo!("You cant enter raw keycodes with xdotool")
+    fn raw(&mut self, keycode: u16, direction: Direction) -> InputResult<()> {
+        if !(8..=255).contains(&keycode) {
+            return Err(InputError::InvalidInput(
+                "Keycode has to be in the range 8..=255 on X11",
+            ));
+        }
+        // xdo treats a key name that is not a keysym but starts with a digit as an
+        // explicit keycode. Pad it to two digits so 8 and 9 are not parsed as the
+        // keysyms of the digits
+        let Ok(string) = CString::new(format!("{keycode:02}")) else {
+            return Err(InputError::InvalidInput("the keycode contained a null byte"));
+        };
+        self.keysequence(&string, direction)
     }
 }
 
@@ -249,15 +249,21 @@ impl Image {
         self.inner().fmt
     }
 
+    /// Whether the chroma planes have full resolution (VP9 profile 1).
+    #[inline]
+    pub fn is_i444(&self) -> bool {
+        self.format() == vpx_img_fmt::VPX_IMG_FMT_I444
+    }
+
     #[inline]
     pub fn inner(&self) -> &vpx_image_t {
         unsafe { &*self.0 }
     }
 
+    /// Visible width; rows in `data` are `stride` bytes apart.
     #[inline]
     pub fn width(&self) -> usize {
-        self.stride()[0] as usize
-        // self.inner().d_w as _
+        self.inner().d_w as _
     }
 
     #[inline]
@@ -275,17 +281,17 @@ impl Image {
         self.inner().planes.iter().map(|p| *p as *mut u8).collect()
     }
 
+    /// Y, U and V planes, each covering all visible rows at its own stride.
     #[inline]
     pub fn data(&self) -> (&[u8], &[u8], &[u8]) {
         unsafe {
             let stride = self.stride();
             let planes = self.planes();
-            let h = (self.height() as usize + 1) & !1;
-            let n = stride[0] as usize * h;
-            let y = slice::from_raw_parts(planes[0], n);
-            let n = stride[1] as usize * (h >> 1);
-            let u = slice::from_raw_parts(planes[1], n);
-            let v = slice::from_raw_parts(planes[2], n);
+            let h = self.height();
+            let ch = (h + self.inner().y_chroma_shift as usize) >> self.inner().y_chroma_shift;
+            let y = slice::from_raw_parts(planes[0], stride[0] as usize * h);
+            let u = slice::from_raw_parts(planes[1], stride[1] as usize * ch);
+            let v = slice::from_raw_parts(planes[2], stride[2] as usize * ch);
             (y, u, v)
         }
     }
@@ -40,7 +40,6 @@ use std::{ptr, slice};
 
 use thiserror::Error;
 
-#[cfg(feature = "vp9")]
 use vpx_sys::vp8e_enc_control_id::*;
 use vpx_sys::vpx_codec_cx_pkt_kind::VPX_CODEC_CX_FRAME_PKT;
 use vpx_sys::*;
@@ -64,25 +63,91 @@ impl Default for VideoCodecId {
     }
 }
 
+/// The layout of the raw frames passed to `Encoder::encode`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ImageFormat {
+    /// Planar YUV with chroma subsampled by 2 in both directions.
+    #[default]
+    I420,
+    /// Planar YUV with full-resolution chroma. Needs VP9 (profile 1).
+    I444,
+}
+
+impl ImageFormat {
+    /// The number of bytes of a `width`x`height` frame.
+    pub fn frame_len(&self, width: usize, height: usize) -> usize {
+        match self {
+            // chroma planes round odd sizes up, as `vpx_img_wrap` does
+            ImageFormat::I420 => width * height + 2 * width.div_ceil(2) * height.div_ceil(2),
+            ImageFormat::I444 => width * height * 3,
+        }
+    }
+}
+
 pub struct Encoder {
     ctx: vpx_codec_ctx_t,
+    codec: VideoCodecId,
+    format: ImageFormat,
+    cfg: vpx_codec_enc_cfg_t,
     width: usize,
     height: usize,
+    force_kf: bool,
+}
+
+/// The reason an encoder call failed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Error)]
+pub enum ErrorKind {
+    /// A libvpx function returned an error code. `VPX_CODEC_INVALID_PARAM`
+    /// usually means libvpx refused the new configuration.
+    #[error("{call} failed with {code:?}")]
+    Codec {
+        call: &'static str,
+        code: vpx_codec_err_t,
+    },
+    /// A libvpx function returned a null pointer.
+    #[error("{0} returned a null pointer")]
+    NullPointer(&'static str),
+    /// Width and height must be non-zero. Odd sizes are fine, chroma planes
+    /// round up.
+    #[error("invalid frame size {width}x{height}, both must be non-zero")]
+    InvalidSize { width: c_uint, height: c_uint },
+    /// A setting is outside the range libvpx accepts.
+    #[error("{name} {value} is out of range")]
+    OutOfRange { name: &'static str, value: i64 },
+    /// The codec cannot encode frames of this format.
+    #[error("{codec:?} does not support {format:?}")]
+    UnsupportedFormat {
+        codec: VideoCodecId,
+        format: ImageFormat,
+    },
+    /// The frame passed to `encode` is smaller than the configured size.
+    #[error("frame of {len} bytes is too small for {width}x{height}")]
+    ShortFrame {
+        len: usize,
+        width: usize,
+        height: usize,
+    },
 }
 
 #[derive(Debug, Error)]
-#[error("VPX encode error: {msg}")]
+#[error("VPX encode error: {kind}")]
 pub struct Error {
-    msg: String,
+    kind: ErrorKind,
     #[cfg(feature = "backtrace")]
     #[backtrace]
     backtrace: Backtrace,
 }
 
-impl From<String> for Error {
-    fn from(msg: String) -> Self {
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
         Self {
-            msg,
+            kind,
             #[cfg(feature = "backtrace")]
             backtrace: Backtrace::capture(),
         }
@@ -92,30 +157,35 @@ impl From<String> for Error {
 pub type Result<T> = std::result::Result<T, Error>;
 
 macro_rules! call_vpx {
-    ($x:expr) => {{
-        let result = unsafe { $x }; // original expression
-        let result_int = unsafe { std::mem::transmute::<_, i32>(result) };
-        // if result != VPX_CODEC_OK {
-        if result_int != 0 {
-            return Err(Error::from(format!(
-                "Function call failed (error code {}).",
-                result_int
-            )));
+    ($f:ident($($arg:expr),* $(,)?)) => {{
+        let result = unsafe { $f($($arg),*) }; // original expression
+        if result != VPX_CODEC_OK {
+            return Err(Error::from(ErrorKind::Codec {
+                call: stringify!($f),
+                code: result,
+            }));
         }
         result
     }};
 }
 
 macro_rules! call_vpx_ptr {
-    ($x:expr) => {{
-        let result = unsafe { $x }; // original expression
+    ($f:ident($($arg:expr),* $(,)?)) => {{
+        let result = unsafe { $f($($arg),*) }; // original expression
         if result.is_null() {
-            return Err(Error::from("Bad pointer.".to_string()));
+            return Err(Error::from(ErrorKind::NullPointer(stringify!($f))));
         }
         result
     }};
 }
 
+fn check_size(width: c_uint, height: c_uint) -> Result<()> {
+    if width == 0 || height == 0 {
+        return Err(Error::from(ErrorKind::InvalidSize { width, height }));
+    }
+    Ok(())
+}
+
 impl Encoder {
     pub fn new(config: Config) -> Result<Self> {
         let i = match config.codec {
@@ -124,12 +194,13 @@ impl Encoder {
             VideoCodecId::VP9 => call_vpx_ptr!(vpx_codec_vp9_cx()),
         };
 
-        if config.width % 2 != 0 {
-            return Err(Error::from("Width must be divisible by 2".to_string()));
-        }
-        if config.height % 2 != 0 {
-            return Err(Error::from("Height must be divisible by 2".to_string()));
-        }
+        check_size(config.width, config.height)?;
+        let profile = match (config.codec, config.format) {
+            (_, ImageFormat::I420) => 0,
+            #[cfg(feature = "vp9")]
+            (VideoCodecId::VP9, ImageFormat::I444) => 1,
+            (codec, format) => return Err(Error::from(ErrorKind::UnsupportedFormat { codec, format })),
+        };
 
         let c = MaybeUninit::zeroed();
         let mut c = unsafe { c.assume_init() };
@@ -140,6 +211,7 @@ impl Encoder {
         c.g_timebase.num = config.timebase[0];
         c.g_timebase.den = config.timebase[1];
         c.rc_target_bitrate = config.bitrate;
+        c.g_profile = profile;
 
         c.g_threads = 8;
         c.g_error_resilient = VPX_ERROR_RESILIENT_DEFAULT;
@@ -183,34 +255,192 @@ impl Encoder {
 
         Ok(Self {
             ctx,
+            codec: config.codec,
+            format: config.format,
+            cfg: c,
             width: config.width as usize,
             height: config.height as usize,
+            force_kf: false,
+        })
+    }
+
+    /// Change the target bitrate (in kilobits per second) of the running
+    /// encoder. Takes effect from the next encoded frame.
+    pub fn set_bitrate(&mut self, bitrate: c_uint) -> Result<()> {
+        self.reconfigure(|c| c.rc_target_bitrate = bitrate)
+    }
+
+    /// The current target bitrate (in kilobits per second).
+    pub fn bitrate(&self) -> c_uint {
+        self.cfg.rc_target_bitrate
+    }
+
+    /// Change the quantizer range. Both ends are in `0..=63`, lower means
+    /// better quality and more bits.
+    pub fn set_quantizer(&mut self, min: c_uint, max: c_uint) -> Result<()> {
+        if max > 63 {
+            return Err(out_of_range("max quantizer", max as i64));
+        }
+        if min > max {
+            return Err(out_of_range("min quantizer", min as i64));
+        }
+        self.reconfigure(|c| {
+            c.rc_min_quantizer = min;
+            c.rc_max_quantizer = max;
+        })
+    }
+
+    /// Change the speed/quality trade-off (`VP8E_SET_CPUUSED`). Higher is
+    /// faster; libvpx accepts `-16..=16` for VP8 and `-9..=9` for VP9.
+    pub fn set_cpu_used(&mut self, speed: c_int) -> Result<()> {
+        let max = match self.codec {
+            VideoCodecId::VP8 => 16,
+            #[cfg(feature = "vp9")]
+            VideoCodecId::VP9 => 9,
+        };
+        if !(-max..=max).contains(&speed) {
+            return Err(out_of_range("cpu used", speed as i64));
+        }
+        call_vpx!(vpx_codec_control_(
+            &mut self.ctx,
+            VP8E_SET_CPUUSED as _,
+            speed
+        ));
+        Ok(())
+    }
+
+    /// Tune the encoder for screen content (text, flat areas, scrolling)
+    /// instead of camera video. Uses `VP8E_SET_SCREEN_CONTENT_MODE` for VP8
+    /// and `VP9E_SET_TUNE_CONTENT` for VP9.
+    pub fn set_screen_content(&mut self, on: bool) -> Result<()> {
+        match self.codec {
+            VideoCodecId::VP8 => {
+                call_vpx!(vpx_codec_control_(
+                    &mut self.ctx,
+                    VP8E_SET_SCREEN_CONTENT_MODE as _,
+                    on as c_uint
+                ));
+            }
+            #[cfg(feature = "vp9")]
+            VideoCodecId::VP9 => {
+                let content = if on {
+                    vp9e_tune_content::VP9E_CONTENT_SCREEN
+                } else {
+                    vp9e_tune_content::VP9E_CONTENT_DEFAULT
+                };
+                call_vpx!(vpx_codec_control_(
+                    &mut self.ctx,
+                    VP9E_SET_TUNE_CONTENT as _,
+                    content as c_int
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The codec this encoder produces.
+    pub fn codec(&self) -> VideoCodecId {
+        self.codec
+    }
+
+    /// The raw image format this encoder expects.
+    pub fn format(&self) -> ImageFormat {
+        self.format
+    }
+
+    /// Change the number of encoder threads.
+    pub fn set_threads(&mut self, threads: c_uint) -> Result<()> {
+        if threads == 0 {
+            return Err(out_of_range("threads", 0));
+        }
+        self.reconfigure(|c| c.g_threads = threads)
+    }
+
+    /// Place a keyframe at least every `max` frames, or only when the
+    /// encoder decides to (scene changes) if `None`.
+    pub fn set_keyframe_interval(&mut self, max: Option<c_uint>) -> Result<()> {
+        self.reconfigure(|c| match max {
+            Some(max) => {
+                c.kf_mode = vpx_kf_mode::VPX_KF_AUTO;
+                c.kf_min_dist = 0;
+                c.kf_max_dist = max;
+            }
+            None => c.kf_mode = vpx_kf_mode::VPX_KF_DISABLED,
         })
     }
 
+    /// Change the frame size. libvpx refuses to grow past the size the
+    /// encoder was created with; create a new encoder in that case.
+    pub fn set_resolution(&mut self, width: c_uint, height: c_uint) -> Result<()> {
+        check_size(width, height)?;
+        self.reconfigure(|c| {
+            c.g_w = width;
+            c.g_h = height;
+        })?;
+        self.width = width as usize;
+        self.height = height as usize;
+        Ok(())
+    }
+
+    /// The current frame size.
+    pub fn resolution(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Make the next encoded frame a keyframe (`VPX_EFLAG_FORCE_KF`), e.g.
+    /// so a receiver that lost packets can resync.
+    pub fn force_keyframe(&mut self) {
+        self.force_kf = true;
+    }
+
+    /// Apply a configuration change, keeping the old one if libvpx rejects it.
+    fn reconfigure<F: FnOnce(&mut vpx_codec_enc_cfg_t)>(&mut self, change: F) -> Result<()> {
+        let mut cfg = self.cfg;
+        change(&mut cfg);
+        call_vpx!(vpx_codec_enc_config_set(&mut self.ctx, &cfg));
+        self.cfg = cfg;
+        Ok(())
+    }
+
     pub fn encode(&mut self, pts: i64, data: &[u8]) -> Result<Packets> {
-        assert!(2 * data.len() >= 3 * self.width * self.height);
+        if data.len() < self.format.frame_len(self.width, self.height) {
+            return Err(Error::from(ErrorKind::ShortFrame {
+                len: data.len(),
+                width: self.width,
+                height: self.height,
+            }));
+        }
 
         let image = MaybeUninit::zeroed();
         let mut image = unsafe { image.assume_init() };
 
+        let fmt = match self.format {
+            ImageFormat::I420 => vpx_img_fmt::VPX_IMG_FMT_I420,
+            ImageFormat::I444 => vpx_img_fmt::VPX_IMG_FMT_I444,
+        };
         call_vpx_ptr!(vpx_img_wrap(
             &mut image,
-            vpx_img_fmt::VPX_IMG_FMT_I420,
+            fmt,
             self.width as _,
             self.height as _,
             1,
             data.as_ptr() as _,
         ));
 
+        let flags = if self.force_kf {
+            VPX_EFLAG_FORCE_KF as vpx_enc_frame_flags_t
+        } else {
+            0
+        };
         call_vpx!(vpx_codec_encode(
             &mut self.ctx,
             &image,
             pts,
             1, // Duration
-            0, // Flags
+            flags,
             vpx_sys::VPX_DL_REALTIME as c_ulong,
         ));
+        self.force_kf = false;
 
         Ok(Packets {
             ctx: &mut self.ctx,
@@ -235,6 +465,10 @@ impl Encoder {
     }
 }
 
+fn out_of_range(name: &'static str, value: i64) -> Error {
+    Error::from(ErrorKind::OutOfRange { name, value })
+}
+
 impl Drop for Encoder {
     fn drop(&mut self) {
         unsafe {
@@ -268,6 +502,8 @@ pub struct Config {
     pub bitrate: c_uint,
     /// The codec
     pub codec: VideoCodecId,
+    /// The raw image format passed to `encode`.
+    pub format: ImageFormat,
 }
 
 pub struct Packets<'a> {
@@ -332,4 +568,121 @@ impl Finish {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+fn test_config(codec: VideoCodecId, format: ImageFormat) -> Config {
+    Config {
+        width: 64,
+        height: 48,
+        timebase: [1, 1000],
+        bitrate: 256,
+        codec,
+        format,
+    }
+}
+
+#[cfg(test)]
+fn test_frame(format: ImageFormat, luma: u8) -> Vec<u8> {
+    let mut data = vec![128u8; format.frame_len(64, 48)];
+    data[..64 * 48].fill(luma);
+    data
+}
+
+#[test]
+fn test_reconfigure() {
+    let mut enc = Encoder::new(test_config(VideoCodecId::VP8, ImageFormat::I420)).unwrap();
+    let kind = |r: Result<()>| r.unwrap_err().kind();
+
+    enc.set_bitrate(500).unwrap();
+    assert_eq!(enc.bitrate(), 500);
+    enc.set_quantizer(4, 50).unwrap();
+    assert_eq!(
+        kind(enc.set_quantizer(4, 64)),
+        ErrorKind::OutOfRange { name: "max quantizer", value: 64 }
+    );
+    assert_eq!(
+        kind(enc.set_quantizer(20, 10)),
+        ErrorKind::OutOfRange { name: "min quantizer", value: 20 }
+    );
+    enc.set_threads(2).unwrap();
+    assert_eq!(kind(enc.set_threads(0)), ErrorKind::OutOfRange { name: "threads", value: 0 });
+    enc.set_cpu_used(-16).unwrap();
+    enc.set_cpu_used(16).unwrap();
+    assert_eq!(kind(enc.set_cpu_used(17)), ErrorKind::OutOfRange { name: "cpu used", value: 17 });
+
+    // the rejected settings leave the encoder usable
+    assert_eq!(enc.bitrate(), 500);
+    assert!(enc.encode(0, &test_frame(ImageFormat::I420, 16)).unwrap().count() > 0);
+}
+
+#[cfg(feature = "vp9")]
+#[test]
+fn test_cpu_used_vp9() {
+    let mut enc = Encoder::new(test_config(VideoCodecId::VP9, ImageFormat::I420)).unwrap();
+    enc.set_cpu_used(9).unwrap();
+    assert_eq!(
+        enc.set_cpu_used(10).unwrap_err().kind(),
+        ErrorKind::OutOfRange { name: "cpu used", value: 10 }
+    );
+}
+
+#[test]
+fn test_errors() {
+    let kind = |config: Config| Encoder::new(config).err().unwrap().kind();
+
+    let mut config = test_config(VideoCodecId::VP8, ImageFormat::I420);
+    config.width = 0;
+    assert_eq!(kind(config), ErrorKind::InvalidSize { width: 0, height: 48 });
+    assert_eq!(
+        kind(test_config(VideoCodecId::VP8, ImageFormat::I444)),
+        ErrorKind::UnsupportedFormat {
+            codec: VideoCodecId::VP8,
+            format: ImageFormat::I444,
+        }
+    );
+
+    let mut enc = Encoder::new(test_config(VideoCodecId::VP8, ImageFormat::I420)).unwrap();
+    let short = enc.encode(0, &[0u8; 100]).err().unwrap().kind();
+    assert_eq!(short, ErrorKind::ShortFrame { len: 100, width: 64, height: 48 });
+}
+
+#[test]
+fn test_odd_size() {
+    for (codec, format) in [
+        (VideoCodecId::VP8, ImageFormat::I420),
+        #[cfg(feature = "vp9")]
+        (VideoCodecId::VP9, ImageFormat::I420),
+        #[cfg(feature = "vp9")]
+        (VideoCodecId::VP9, ImageFormat::I444),
+    ] {
+        let mut config = test_config(codec, format);
+        (config.width, config.height) = (63, 47);
+        let mut enc = Encoder::new(config).unwrap();
+        let frame = vec![128u8; format.frame_len(63, 47)];
+        // one byte short of the rounded-up chroma planes
+        let short = enc.encode(0, &frame[..frame.len() - 1]).err().unwrap().kind();
+        assert_eq!(short, ErrorKind::ShortFrame { len: frame.len() - 1, width: 63, height: 47 });
+        let mut n = 0;
+        for pts in 0..30 {
+            n += enc.encode(pts, &frame).unwrap().count();
+        }
+        // VP9 may hold frames back for lookahead
+        assert!(n > 0);
+    }
+}
+
+#[test]
+fn test_force_keyframe() {
+    fn keys(enc: &mut Encoder, pts: i64, luma: u8) -> Vec<bool> {
+        let frame = test_frame(ImageFormat::I420, luma);
+        enc.encode(pts, &frame).unwrap().map(|f| f.key).collect()
+    }
+
+    let mut enc = Encoder::new(test_config(VideoCodecId::VP8, ImageFormat::I420)).unwrap();
+    assert_eq!(keys(&mut enc, 0, 16), [true]);
+    assert_eq!(keys(&mut enc, 1, 20), [false]);
+    enc.force_keyframe();
+    assert_eq!(keys(&mut enc, 2, 24), [true]);
+    assert_eq!(keys(&mut enc, 3, 28), [false]);
+}